@@ -0,0 +1,582 @@
+//! In-memory spatial index of addresses, used to answer reverse queries
+//! locally instead of sending a query to elasticsearch for each POI.
+//!
+//! For country-scale imports, addresses may not fit in memory at once: the
+//! imported area is then split into tiles which are processed one by one.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::future::Future;
+use std::sync::Arc;
+
+use elasticsearch::{ClearScrollParts, CountParts, Elasticsearch, ScrollParts, SearchParts};
+use places::{addr::Addr, admin::Admin, coord::Coord, Place};
+use rstar::{RTree, RTreeObject, AABB};
+use serde::{de, Deserialize, Deserializer};
+use serde_json::json;
+use tracing::{info, warn};
+
+const ADDR_INDEX: &str = "munin_addr";
+
+// Parameters of the scroll used to load addresses from elasticsearch
+const SCROLL_ALIVE: &str = "1m";
+const SCROLL_CHUNK_SIZE: i64 = 5_000;
+
+// Tiles won't be split further than this size in degrees
+const MIN_TILE_SIZE: f64 = 0.01;
+
+// Relative tolerance on the size of the area searched around a point
+const SEARCH_AREA_TOLERANCE: f64 = 1e-9;
+
+// Mean radius of the Earth in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+// Length of a degree of latitude in meters, consistently with distances
+// computed by `haversine_distance`
+const METERS_PER_DEGREE: f64 = EARTH_RADIUS * PI / 180.;
+
+// Units accepted for distances by elasticsearch, with their length in meters
+const DISTANCE_UNITS: &[(&str, f64)] = &[
+    ("nauticalmiles", 1852.),
+    ("millimeters", 0.001),
+    ("centimeters", 0.01),
+    ("kilometers", 1000.),
+    ("meters", 1.),
+    ("miles", 1609.344),
+    ("yards", 0.9144),
+    ("inch", 0.0254),
+    ("feet", 0.3048),
+    ("nmi", 1852.),
+    ("NM", 1852.),
+    ("mm", 0.001),
+    ("cm", 0.01),
+    ("km", 1000.),
+    ("mi", 1609.344),
+    ("yd", 0.9144),
+    ("ft", 0.3048),
+    ("in", 0.0254),
+    ("m", 1.),
+];
+
+/// Parse a distance written with elasticsearch units ("500m", "2km", ...)
+/// into meters, a distance without unit is in meters.
+pub fn parse_distance(raw: &str) -> Option<f64> {
+    let raw = raw.trim();
+
+    let (value, unit_length) = DISTANCE_UNITS
+        .iter()
+        .find_map(|(unit, length)| Some((raw.strip_suffix(unit)?, *length)))
+        .unwrap_or((raw, 1.));
+
+    let value: f64 = value.trim().parse().ok()?;
+
+    if !value.is_finite() || value < 0. {
+        return None;
+    }
+
+    Some(value * unit_length)
+}
+
+/// A distance written with elasticsearch units, it can only be built from a
+/// valid value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distance {
+    raw: String,
+    meters: f64,
+}
+
+impl Distance {
+    pub fn parse(raw: &str) -> Option<Self> {
+        Some(Self {
+            raw: raw.to_string(),
+            meters: parse_distance(raw)?,
+        })
+    }
+
+    /// The distance as it was written, which is understood by elasticsearch.
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn meters(&self) -> f64 {
+        self.meters
+    }
+}
+
+impl<'de> Deserialize<'de> for Distance {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+
+        Self::parse(&raw).ok_or_else(|| {
+            de::Error::custom(format!(
+                "invalid distance `{raw}`, expected eg. `50m` or `1km`"
+            ))
+        })
+    }
+}
+
+/// Distance in meters between two points on Earth, given as [lon, lat].
+pub(crate) fn haversine_distance([lon1, lat1]: [f64; 2], [lon2, lat2]: [f64; 2]) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// A rectangular area, the minimal bounds are inclusive while maximal bounds
+/// are exclusive so that tiles don't overlap.
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl Tile {
+    /// Build a tile from a bounding box with format [lon1, lat1, lon2, lat2],
+    /// the whole world is covered if no bounding box is given.
+    pub fn from_bbox(bbox: Option<[f64; 4]>) -> Self {
+        let [min_lon, min_lat, max_lon, max_lat] = bbox.unwrap_or([-180., -90., 180., 90.]);
+
+        // Maximal bounds are exclusive, they are extended a bit to include
+        // POIs that lay on the border.
+        Self {
+            min_lon,
+            min_lat,
+            max_lon: max_lon + f64::EPSILON * max_lon.abs().max(1.),
+            max_lat: max_lat + f64::EPSILON * max_lat.abs().max(1.),
+        }
+    }
+
+    /// Format as a bounding box [lon1, lat1, lon2, lat2].
+    pub fn bbox(&self) -> [f64; 4] {
+        [self.min_lon, self.min_lat, self.max_lon, self.max_lat]
+    }
+
+    fn split(&self) -> [Self; 4] {
+        let mid_lon = (self.min_lon + self.max_lon) / 2.;
+        let mid_lat = (self.min_lat + self.max_lat) / 2.;
+
+        [
+            Self {
+                max_lon: mid_lon,
+                max_lat: mid_lat,
+                ..*self
+            },
+            Self {
+                min_lon: mid_lon,
+                max_lat: mid_lat,
+                ..*self
+            },
+            Self {
+                max_lon: mid_lon,
+                min_lat: mid_lat,
+                ..*self
+            },
+            Self {
+                min_lon: mid_lon,
+                min_lat: mid_lat,
+                ..*self
+            },
+        ]
+    }
+
+    /// Extend the tile such that it contains all points that are at most at
+    /// given distance in meters from it.
+    pub fn with_margin(&self, margin: f64) -> Self {
+        let lat_margin = margin / METERS_PER_DEGREE;
+        let min_lat = (self.min_lat - lat_margin).max(-90.);
+        let max_lat = (self.max_lat + lat_margin).min(90.);
+
+        // Degrees of longitude are shorter further from the equator
+        let max_abs_lat = min_lat.abs().max(max_lat.abs());
+        let lon_margin = (margin / (METERS_PER_DEGREE * max_abs_lat.to_radians().cos())).min(360.);
+
+        Self {
+            min_lon: (self.min_lon - lon_margin).max(-180.),
+            min_lat,
+            max_lon: (self.max_lon + lon_margin).min(180.),
+            max_lat,
+        }
+    }
+
+    fn envelope(&self) -> AABB<[f64; 2]> {
+        AABB::from_corners([self.min_lon, self.min_lat], [self.max_lon, self.max_lat])
+    }
+
+    fn es_filter(&self) -> serde_json::Value {
+        json!({
+            "geo_bounding_box": {
+                "coord": {
+                    "top_left": { "lat": self.max_lat, "lon": self.min_lon },
+                    "bottom_right": { "lat": self.min_lat, "lon": self.max_lon }
+                }
+            }
+        })
+    }
+}
+
+/// Split an area into tiles such that the addresses around each of them, up
+/// to a distance of `margin` meters, don't exceed `max_addresses`.
+pub async fn plan_tiles(
+    es: &Elasticsearch,
+    area: Tile,
+    margin: f64,
+    max_addresses: usize,
+) -> Vec<Tile> {
+    let count = |tile: Tile| async move { count_addresses(es, &tile.with_margin(margin)).await };
+    let tiles = split_tiles(area, max_addresses, count).await;
+    info!("addresses will be loaded in {} tiles", tiles.len());
+    tiles
+}
+
+/// Split an area into tiles until `count` doesn't exceed `max_addresses` for
+/// any tile or tiles can't be split further.
+async fn split_tiles<F, Fut>(area: Tile, max_addresses: usize, count: F) -> Vec<Tile>
+where
+    F: Fn(Tile) -> Fut,
+    Fut: Future<Output = usize>,
+{
+    let mut pending = vec![area];
+    let mut tiles = Vec::new();
+
+    while let Some(tile) = pending.pop() {
+        let count = count(tile).await;
+        let can_split =
+            (tile.max_lon - tile.min_lon).min(tile.max_lat - tile.min_lat) > MIN_TILE_SIZE;
+
+        if count > max_addresses && can_split {
+            pending.extend(tile.split());
+        } else {
+            if count > max_addresses {
+                warn!("{count} addresses will be loaded for tile {tile:?}");
+            }
+
+            tiles.push(tile);
+        }
+    }
+
+    tiles
+}
+
+async fn count_addresses(es: &Elasticsearch, tile: &Tile) -> usize {
+    #[derive(Deserialize)]
+    struct CountResponse {
+        count: usize,
+    }
+
+    es.count(CountParts::Index(&[ADDR_INDEX]))
+        .ignore_unavailable(true)
+        .body(json!({ "query": { "bool": { "filter": tile.es_filter() } } }))
+        .send()
+        .await
+        .expect("failed to count addresses")
+        .json::<CountResponse>()
+        .await
+        .expect("failed to parse addresses count")
+        .count
+}
+
+struct IndexedAddr {
+    point: [f64; 2],
+    addr: Addr,
+}
+
+impl RTreeObject for IndexedAddr {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        AABB::from_point(self.point)
+    }
+}
+
+/// Spatial index over the addresses of a tile.
+pub struct AddrIndex {
+    tree: RTree<IndexedAddr>,
+}
+
+impl AddrIndex {
+    /// Load all addresses from a tile into memory.
+    pub async fn load(es: &Elasticsearch, tile: &Tile) -> Self {
+        #[derive(Deserialize)]
+        struct ScrollResponse {
+            #[serde(rename = "_scroll_id")]
+            scroll_id: Option<String>,
+            hits: Hits,
+        }
+
+        #[derive(Deserialize)]
+        struct Hits {
+            hits: Vec<Hit>,
+        }
+
+        #[derive(Deserialize)]
+        struct Hit {
+            #[serde(rename = "_source")]
+            source: Addr,
+        }
+
+        // Admins are shared between addresses to save memory
+        let mut admins: HashMap<String, Arc<Admin>> = HashMap::new();
+        let mut addrs = Vec::new();
+
+        let mut response: ScrollResponse = es
+            .search(SearchParts::Index(&[ADDR_INDEX]))
+            .ignore_unavailable(true)
+            .scroll(SCROLL_ALIVE)
+            .size(SCROLL_CHUNK_SIZE)
+            .body(json!({
+                "query": { "bool": { "filter": tile.es_filter() } },
+                "sort": ["_doc"]
+            }))
+            .send()
+            .await
+            .expect("failed to query addresses")
+            .json()
+            .await
+            .expect("failed to parse addresses");
+
+        while !response.hits.hits.is_empty() {
+            for Hit { source: mut addr } in response.hits.hits {
+                for admin in &mut addr.street.administrative_regions {
+                    *admin = admins
+                        .entry(admin.id.clone())
+                        .or_insert_with(|| admin.clone())
+                        .clone();
+                }
+
+                addrs.push(IndexedAddr {
+                    point: [addr.coord.lon(), addr.coord.lat()],
+                    addr,
+                });
+            }
+
+            let scroll_id = match response.scroll_id {
+                Some(scroll_id) => scroll_id,
+                None => break,
+            };
+
+            response = es
+                .scroll(ScrollParts::None)
+                .body(json!({ "scroll": SCROLL_ALIVE, "scroll_id": scroll_id }))
+                .send()
+                .await
+                .expect("failed to scroll addresses")
+                .json()
+                .await
+                .expect("failed to parse addresses");
+        }
+
+        if let Some(scroll_id) = response.scroll_id {
+            if let Err(err) = es
+                .clear_scroll(ClearScrollParts::None)
+                .body(json!({ "scroll_id": [scroll_id] }))
+                .send()
+                .await
+            {
+                warn!("failed to clear addresses scroll: {err}");
+            }
+        }
+
+        info!("loaded {} addresses for tile {tile:?}", addrs.len());
+
+        Self {
+            tree: RTree::bulk_load(addrs),
+        }
+    }
+
+    /// Get up to `max_count` addresses at most at `max_distance` meters of
+    /// input coordinates, sorted by distance.
+    pub fn nearest(&self, coord: &Coord, max_distance: f64, max_count: usize) -> Vec<Place> {
+        // The search area is slightly extended so that rounding errors don't
+        // exclude addresses laying at exactly the max distance.
+        let search_area = Tile {
+            min_lon: coord.lon(),
+            min_lat: coord.lat(),
+            max_lon: coord.lon(),
+            max_lat: coord.lat(),
+        }
+        .with_margin(max_distance * (1. + SEARCH_AREA_TOLERANCE));
+
+        let mut candidates: Vec<_> = self
+            .tree
+            .locate_in_envelope(&search_area.envelope())
+            .map(|indexed| {
+                let distance = haversine_distance([coord.lon(), coord.lat()], indexed.point);
+                (distance, &indexed.addr)
+            })
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+
+        candidates.sort_by(|(x, _), (y, _)| x.total_cmp(y));
+
+        candidates
+            .into_iter()
+            .take(max_count)
+            .map(|(_, addr)| Place::Addr(addr.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use places::street::Street;
+
+    fn indexed_addr(id: &str, lon: f64, lat: f64) -> IndexedAddr {
+        let addr = Addr {
+            id: id.to_string(),
+            house_number: "1".to_string(),
+            name: "1 rue bob".to_string(),
+            street: Street::default(),
+            label: "1 rue bob".to_string(),
+            coord: Coord::new(lon, lat),
+            approx_coord: None,
+            weight: 0.,
+            zip_codes: Vec::new(),
+            distance: None,
+            country_codes: Vec::new(),
+            context: None,
+        };
+
+        IndexedAddr {
+            point: [lon, lat],
+            addr,
+        }
+    }
+
+    fn ids(places: Vec<Place>) -> Vec<String> {
+        places
+            .into_iter()
+            .map(|place| match place {
+                Place::Addr(addr) => addr.id,
+                _ => panic!("expected an address"),
+            })
+            .collect()
+    }
+
+    fn contains(tile: &Tile, [lon, lat]: [f64; 2]) -> bool {
+        (tile.min_lon..tile.max_lon).contains(&lon) && (tile.min_lat..tile.max_lat).contains(&lat)
+    }
+
+    #[test]
+    fn parse_distances() {
+        assert_eq!(parse_distance("500m"), Some(500.));
+        assert_eq!(parse_distance(" 2km "), Some(2000.));
+        assert_eq!(parse_distance("1.5 nmi"), Some(2778.));
+        assert_eq!(parse_distance("30"), Some(30.));
+        assert_eq!(parse_distance("-1m"), None);
+        assert_eq!(parse_distance("km"), None);
+        assert_eq!(parse_distance("10 parsecs"), None);
+
+        let distance: Distance = serde_json::from_str(r#""2km""#).unwrap();
+        assert_eq!(distance.as_str(), "2km");
+        assert_eq!(distance.meters(), 2000.);
+        assert!(serde_json::from_str::<Distance>(r#""far""#).is_err());
+    }
+
+    #[test]
+    fn haversine() {
+        let degree = haversine_distance([2., 48.], [2., 49.]);
+        assert!((degree - METERS_PER_DEGREE).abs() < 1e-6);
+        assert_eq!(haversine_distance([2., 48.], [2., 48.]), 0.);
+
+        // A degree of longitude at 60° of latitude is about half a degree at
+        // the equator.
+        let at_equator = haversine_distance([0., 0.], [1., 0.]);
+        let at_60 = haversine_distance([0., 60.], [1., 60.]);
+        assert!((at_60 / at_equator - 0.5).abs() < 1e-3);
+
+        // Across the antimeridian
+        let across = haversine_distance([179.5, 0.], [-179.5, 0.]);
+        assert!((across - at_equator).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nearest_addresses_are_sorted_and_limited() {
+        let index = AddrIndex {
+            tree: RTree::bulk_load(vec![
+                indexed_addr("far", 2.01, 48.),
+                indexed_addr("close", 2.001, 48.),
+                indexed_addr("closest", 2., 48.0005),
+                indexed_addr("too_far", 2.1, 48.),
+            ]),
+        };
+
+        let coord = Coord::new(2., 48.);
+        let nearest = |max_distance, max_count| ids(index.nearest(&coord, max_distance, max_count));
+
+        assert_eq!(nearest(1000., 10), ["closest", "close", "far"]);
+        assert_eq!(nearest(1000., 2), ["closest", "close"]);
+        assert_eq!(nearest(100., 10), ["closest", "close"]);
+        assert_eq!(nearest(10., 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn nearest_addresses_at_max_distance() {
+        let coord = Coord::new(2., 48.);
+
+        // Addresses on the edges of the area searched around the POI
+        for (lon, lat) in [(2., 48.01), (2., 47.99), (2.01, 48.), (1.99, 48.)] {
+            let index = AddrIndex {
+                tree: RTree::bulk_load(vec![indexed_addr("edge", lon, lat)]),
+            };
+
+            let distance = haversine_distance([2., 48.], [lon, lat]);
+            assert_eq!(ids(index.nearest(&coord, distance, 10)), ["edge"]);
+            assert!(index.nearest(&coord, 0.99 * distance, 10).is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn split_tiles_until_small_enough() {
+        // A grid of addresses, some of which lay on the edges of tiles
+        let points: Vec<_> = (0..=8)
+            .flat_map(|x| (0..=8).map(move |y| [x as f64 / 8., y as f64 / 8.]))
+            .collect();
+
+        let count = |tile: Tile| {
+            let count = points
+                .iter()
+                .filter(|point| contains(&tile, **point))
+                .count();
+            async move { count }
+        };
+
+        let area = Tile::from_bbox(Some([0., 0., 1., 1.]));
+        let tiles = split_tiles(area, 10, count).await;
+        assert!(tiles.len() > 1);
+
+        for tile in &tiles {
+            let count = points
+                .iter()
+                .filter(|point| contains(tile, **point))
+                .count();
+            assert!(count <= 10, "{count} addresses in {tile:?}");
+        }
+
+        // Tiles don't overlap and cover the whole area, including its borders
+        for point in &points {
+            let containing = tiles.iter().filter(|tile| contains(tile, *point)).count();
+            assert_eq!(containing, 1, "{point:?} is in {containing} tiles");
+        }
+    }
+
+    #[tokio::test]
+    async fn split_tiles_down_to_min_size() {
+        // Addresses sharing a location can't be split into different tiles
+        let count = |tile: Tile| async move { usize::from(contains(&tile, [0.5, 0.5])) * 100 };
+
+        let area = Tile::from_bbox(Some([0., 0., 1., 1.]));
+        let tiles = split_tiles(area, 10, count).await;
+        let crowded: Vec<_> = (tiles.iter())
+            .filter(|tile| contains(tile, [0.5, 0.5]))
+            .collect();
+
+        assert_eq!(crowded.len(), 1);
+        assert!(crowded[0].max_lon - crowded[0].min_lon <= MIN_TILE_SIZE);
+        assert!(crowded[0].max_lon - crowded[0].min_lon > MIN_TILE_SIZE / 2.);
+    }
+}
@@ -5,17 +5,229 @@ use places::{
     addr::Addr, admin::find_country_codes, admin::Admin, coord::Coord, poi::Poi, street::Street,
     Address, Place,
 };
-use serde::Deserialize;
 use serde_json::json;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::ops::Deref;
 use std::sync::Arc;
+use tracing::debug;
 
+use crate::addr_index::{AddrIndex, Distance};
 use crate::lazy_es::LazyEs;
+use crate::provenance::AddressMethod;
+use crate::settings::ReverseDistances;
 
 // Prefixes used in ids for Address objects derived from OSM tags
 const FAFNIR_ADDR_NAMESPACE: &str = "addr_poi:";
 const FAFNIR_STREET_NAMESPACE: &str = "street_poi:";
-const MAX_REVERSE_DISTANCE: &str = "500m";
+
+// Max number of candidates fetched by a reverse
+const MAX_REVERSE_CANDIDATES: usize = 10;
+
+// Coordinates of a reverse are rounded to about a meter in its cache key, so
+// that a single query is sent for POIs sharing a location.
+const REVERSE_COORD_PRECISION: f64 = 1e5;
+
+// Tags holding the parts of an address
+const HOUSE_NUMBER_TAGS: &[&str] = &["addr:housenumber", "contact:housenumber"];
+const STREET_TAGS: &[&str] = &["addr:street", "contact:street"];
+const PLACE_TAGS: &[&str] = &["addr:place", "contact:place"];
+const POSTCODE_TAGS: &[&str] = &["addr:postcode", "contact:postcode"];
+const CITY_TAGS: &[&str] = &["addr:city", "contact:city"];
+
+/// All tags that may be read to build the address of a POI.
+const ADDRESS_TAGS: &[&[&str]] = &[
+    HOUSE_NUMBER_TAGS,
+    STREET_TAGS,
+    PLACE_TAGS,
+    POSTCODE_TAGS,
+    CITY_TAGS,
+];
+
+// Suffixes of house numbers which are written as separate words
+const HOUSE_NUMBER_SUFFIXES: &[&str] = &["bis", "ter", "quater", "quinquies"];
+
+// Words introducing a unit or a floor inside of a house number
+const UNIT_MARKERS: &[&str] = &["apartment", "apt", "unit", "suite", "ste"];
+const FLOOR_MARKERS: &[&str] = &["floor", "fl"];
+
+// Maximal length of a range of house numbers that will be expanded
+const MAX_HOUSE_NUMBER_RANGE: u32 = 20;
+
+/// House number read from a tag that may contain ranges ("12-14"), lists
+/// ("3;5"), suffixes ("12bis", "4 A") or units ("12 apt 3").
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HouseNumber {
+    /// Normalized main house number.
+    pub primary: String,
+    /// Other house numbers the POI is located at.
+    pub alternates: Vec<String>,
+    pub unit: Option<String>,
+    pub floor: Option<String>,
+}
+
+/// Parse the value of a house number tag.
+pub fn parse_house_number(raw: &str) -> Option<HouseNumber> {
+    let mut res = HouseNumber::default();
+    let mut numbers = Vec::new();
+
+    for item in raw.split([';', ',']) {
+        let (item, unit, floor) = split_unit_and_floor(item);
+        res.unit = res.unit.or(unit);
+        res.floor = res.floor.or(floor);
+
+        let range = item
+            .split_once('-')
+            .filter(|(_, end)| !end.contains('-'))
+            .map(|(start, end)| (start.trim(), end.trim()))
+            .filter(|(start, end)| !start.is_empty() && !end.is_empty());
+
+        match range {
+            Some((start, end)) => match (start.parse::<u32>(), end.parse::<u32>()) {
+                (Ok(start), Ok(end)) if start < end && end - start <= MAX_HOUSE_NUMBER_RANGE => {
+                    // Numbers on each side of a street usually have the same parity
+                    let step = if start % 2 == end % 2 { 2 } else { 1 };
+
+                    numbers.extend((start..=end).step_by(step).map(|number| number.to_string()));
+                }
+                _ => numbers.extend([start, end].map(normalize_house_number)),
+            },
+            None => numbers.push(normalize_house_number(item)),
+        }
+    }
+
+    let mut numbers = numbers.into_iter().filter(|x| !x.is_empty()).unique();
+    res.primary = numbers.next()?;
+    res.alternates = numbers.collect();
+    Some(res)
+}
+
+/// Normalize a single house number: "12bis" becomes "12 bis" and "4 a"
+/// becomes "4A".
+fn normalize_house_number(raw: &str) -> String {
+    let raw = raw.split_whitespace().join(" ");
+    let digits_end = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+
+    if digits_end == 0 {
+        return raw;
+    }
+
+    let (number, suffix) = raw.split_at(digits_end);
+    let suffix = suffix.trim();
+    let suffix_lower = suffix.to_lowercase();
+
+    if suffix.is_empty() {
+        number.to_string()
+    } else if suffix.chars().count() == 1 && suffix.chars().all(char::is_alphabetic) {
+        format!("{number}{}", suffix.to_uppercase())
+    } else if HOUSE_NUMBER_SUFFIXES.contains(&suffix_lower.as_str()) {
+        format!("{number} {suffix_lower}")
+    } else {
+        raw
+    }
+}
+
+/// Split a house number from unit and floor information that may follow it,
+/// such as in "12 apt 3" or "5 #2".
+fn split_unit_and_floor(raw: &str) -> (&str, Option<String>, Option<String>) {
+    // Tokens along with their byte offset in `raw`
+    let tokens: Vec<(usize, &str)> = (raw.split_whitespace())
+        .scan(0, |searched, token| {
+            let start = *searched + raw[*searched..].find(token)?;
+            *searched = start + token.len();
+            Some((start, token))
+        })
+        .collect();
+
+    let words = |start: usize, end: usize| (tokens[start..end].iter()).map(|(_, x)| x).join(" ");
+    let mut house_number_end = tokens.len();
+    let mut unit = None;
+    let mut floor = None;
+
+    for (i, (_, token)) in tokens.iter().enumerate().rev() {
+        let marker = token.trim_end_matches('.').to_lowercase();
+        let value = || Some(words(i + 1, house_number_end)).filter(|x| !x.is_empty());
+
+        if UNIT_MARKERS.contains(&marker.as_str()) {
+            unit = unit.or_else(value);
+        } else if FLOOR_MARKERS.contains(&marker.as_str()) {
+            floor = floor.or_else(value);
+        } else if token.len() > 1 && token.starts_with('#') {
+            unit = unit.or_else(|| Some(words(i, house_number_end)[1..].to_string()));
+        } else {
+            continue;
+        }
+
+        house_number_end = i;
+    }
+
+    let house_number = match tokens.get(house_number_end) {
+        Some((start, _)) => &raw[..*start],
+        None => raw,
+    };
+
+    (house_number.trim(), unit, floor)
+}
+
+/// Copy unit and floor information that is written in the house number tag
+/// into `addr:unit` and `addr:floor` properties if they are not defined.
+pub fn extract_unit_and_floor(properties: &mut BTreeMap<String, String>) {
+    let house_number = match properties
+        .get("addr:housenumber")
+        .and_then(|raw| parse_house_number(raw))
+    {
+        Some(house_number) => house_number,
+        None => return,
+    };
+
+    for (key, value) in [
+        ("addr:unit", house_number.unit),
+        ("addr:floor", house_number.floor),
+    ] {
+        if let Some(value) = value {
+            properties.entry(key.to_string()).or_insert(value);
+        }
+    }
+}
+
+/// Read the value of the first tag among a list of keys.
+fn find_tag<'a>(tags: &'a BTreeMap<String, String>, keys: &[&str]) -> Option<&'a String> {
+    keys.iter().find_map(|k| tags.get(*k))
+}
+
+/// Read the raw value of the house number tag of a POI.
+pub fn house_number_tag(poi: &Poi) -> Option<&String> {
+    find_tag(&poi.properties, HOUSE_NUMBER_TAGS)
+}
+
+/// Names of the addresses matching alternate house numbers of a POI for which
+/// the address was read from tags, eg. "14 rue bob" if tags contain the range
+/// "12-14".
+pub fn alternate_addr_names(poi: &Poi) -> Vec<String> {
+    let addr = match &poi.address {
+        Some(Address::Addr(addr)) if addr.id.starts_with(FAFNIR_ADDR_NAMESPACE) => addr,
+        _ => return Vec::new(),
+    };
+
+    let alternates = house_number_tag(poi)
+        .and_then(|raw| parse_house_number(raw))
+        .map(|house_number| house_number.alternates)
+        .unwrap_or_default();
+
+    alternates
+        .iter()
+        .map(|house_number| {
+            format_addr_name_and_label(
+                house_number,
+                &addr.street.name,
+                iter_admins(&poi.administrative_regions),
+                &poi.country_codes,
+            )
+            .0
+        })
+        .collect()
+}
 
 /// Check if a mimir address originates from OSM data.
 pub fn is_addr_derived_from_tags(addr: &Address) -> bool {
@@ -25,62 +237,86 @@ pub fn is_addr_derived_from_tags(addr: &Address) -> bool {
     }
 }
 
-pub enum CurPoiAddress {
-    /// No address was searched yet for this POI
-    NotFound,
-    /// A search already was performed, but the result was empty
-    None { coord: Coord },
-    /// An address has already been found
-    Some { coord: Coord, address: Box<Address> },
+/// Check if a tag may be read to build the address of a POI.
+pub fn is_address_tag(key: &str) -> bool {
+    (ADDRESS_TAGS.iter()).any(|keys| keys.contains(&key))
 }
 
-/// Get current value of address associated with a POI in the ES database if
-/// any, together with current coordinates of the POI that have been used to
-/// perform a reverse
-pub fn get_current_addr<'a>(poi_index: &str, osm_id: &'a str) -> LazyEs<'a, CurPoiAddress> {
-    #[derive(Deserialize)]
-    struct FetchPoi {
-        coord: Coord,
-        address: Option<Address>,
-    }
+/// Rebuild the tags an address was derived from. The postcode is only
+/// recovered if it differs from the one of its admins, as it would be read
+/// from them again otherwise. The city can't be recovered.
+pub fn addr_tags(addr: &Address) -> BTreeMap<String, String> {
+    let (house_number, street, zip_codes) = match addr {
+        Address::Addr(addr) => (Some(&addr.house_number), &addr.street, &addr.zip_codes),
+        Address::Street(street) => (None, street, &street.zip_codes),
+    };
 
-    LazyEs::NeedEsQuery {
-        header: json!({ "index": poi_index }),
-        query: json!({
-            "_source": ["address", "coord"],
-            "query": {"terms": {"_id": [osm_id]}}
-        }),
-        progress: Box::new(move |hits| {
-            LazyEs::Value({
-                assert!(hits.len() <= 1);
+    let postcode = match zip_codes.as_slice() {
+        [postcode]
+            if !(street.administrative_regions.iter())
+                .any(|admin| admin.zip_codes == [postcode.as_str()]) =>
+        {
+            Some(postcode)
+        }
+        _ => None,
+    };
 
-                if let Some(hit) = hits.into_iter().next() {
-                    let poi: FetchPoi =
-                        serde_json::from_str(hit.source.get()).expect("got invalid POI from ES");
+    let house_number = house_number.map(|value| (HOUSE_NUMBER_TAGS[0], value));
+    let street = Some((STREET_TAGS[0], &street.name));
+    let postcode = postcode.map(|value| (POSTCODE_TAGS[0], value));
 
-                    let coord = poi.coord;
+    (house_number.into_iter().chain(street).chain(postcode))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
 
-                    if let Some(address) = poi.address {
-                        CurPoiAddress::Some {
-                            coord,
-                            address: Box::new(address),
-                        }
-                    } else {
-                        CurPoiAddress::None { coord }
-                    }
-                } else {
-                    CurPoiAddress::NotFound
-                }
-            })
-        }),
+/// Get addresses close to input coordinates, they are read from the
+/// in-memory index if it is provided.
+pub fn get_addr_from_coords<'a>(
+    coord: &Coord,
+    max_distance: &Distance,
+    addr_index: Option<&AddrIndex>,
+) -> LazyEs<'a, Vec<Place>> {
+    if let Some(addr_index) = addr_index {
+        let max_distance = max_distance.meters();
+        return LazyEs::Value(addr_index.nearest(coord, max_distance, MAX_REVERSE_CANDIDATES));
     }
+
+    get_places_from_coords("munin_addr", coord, max_distance, |source| {
+        Place::Addr(serde_json::from_str(source).expect("got invalid address from ES"))
+    })
 }
 
-/// Get addresses close to input coordinates.
-pub fn get_addr_from_coords<'a>(coord: &Coord) -> LazyEs<'a, Vec<Place>> {
+/// Get streets close to input coordinates.
+pub fn get_street_from_coords<'a>(
+    coord: &Coord,
+    max_distance: &Distance,
+) -> LazyEs<'a, Vec<Place>> {
+    get_places_from_coords("munin_street", coord, max_distance, |source| {
+        Place::Street(serde_json::from_str(source).expect("got invalid street from ES"))
+    })
+}
+
+/// Get places of an index close to input coordinates, sorted by distance.
+fn get_places_from_coords<'a>(
+    index: &str,
+    coord: &Coord,
+    max_distance: &Distance,
+    parse: fn(&str) -> Place,
+) -> LazyEs<'a, Vec<Place>> {
+    let (lat, lon) = (coord.lat(), coord.lon());
+    let round = |x: f64| (x * REVERSE_COORD_PRECISION).round() as i64;
+
+    let cache_key = format!(
+        "reverse:{index}:{}:{}:{}",
+        max_distance.as_str(),
+        round(lat),
+        round(lon),
+    );
+
     LazyEs::NeedEsQuery {
         header: json!({
-            "index": ["munin_addr"],
+            "index": [index],
             "ignore_unavailable": true
         }),
         query: json!({
@@ -89,16 +325,17 @@ pub fn get_addr_from_coords<'a>(coord: &Coord) -> LazyEs<'a, Vec<Place>> {
                     "must": { "match_all": {} },
                     "filter": {
                         "geo_distance": {
-                            "distance": MAX_REVERSE_DISTANCE,
-                            "coord": { "lat": coord.lat(), "lon": coord.lon() }
+                            "distance": max_distance.as_str(),
+                            "coord": { "lat": lat, "lon": lon }
                         }
                     }
                 }
             },
+            "size": MAX_REVERSE_CANDIDATES,
             "sort": [
                 {
                     "_geo_distance": {
-                        "coord": { "lat": coord.lat(), "lon": coord.lon() },
+                        "coord": { "lat": lat, "lon": lon },
                         "order": "asc",
                         "unit": "m",
                         "distance_type": "arc",
@@ -107,31 +344,165 @@ pub fn get_addr_from_coords<'a>(coord: &Coord) -> LazyEs<'a, Vec<Place>> {
                 }
             ]
         }),
-        progress: Box::new(|hits| {
+        cache_key: Some(cache_key),
+        progress: Box::new(move |hits| {
             LazyEs::Value(
                 hits.into_iter()
-                    .map(|hit| {
-                        Place::Addr(
-                            serde_json::from_str(hit.source.get())
-                                .expect("got invalid address from ES"),
-                        )
-                    })
+                    .map(|hit| parse(hit.source.get()))
                     .collect(),
             )
         }),
     }
 }
 
+/// Information about a POI used to select the best candidate of a reverse.
+struct ReverseHints<'a> {
+    poi_id: &'a str,
+    street: Option<String>,
+    postcode: Option<String>,
+    city: Option<String>,
+    /// Id of the city admin the POI is located in.
+    city_admin_id: Option<String>,
+}
+
+impl<'a> ReverseHints<'a> {
+    fn new(poi: &'a Poi, tags: &BTreeMap<String, String>, geofinder: &AdminGeoFinder) -> Self {
+        Self {
+            poi_id: &poi.id,
+            street: find_tag(tags, STREET_TAGS).cloned(),
+            postcode: find_tag(tags, POSTCODE_TAGS).cloned(),
+            city: find_tag(tags, CITY_TAGS).cloned(),
+            city_admin_id: geofinder
+                .get(&poi.coord)
+                .iter()
+                .find(|admin| admin.is_city())
+                .map(|admin| admin.id.clone()),
+        }
+    }
+
+    fn matches(&self, address: &Address) -> ReverseMatch {
+        let (street_name, zip_codes, admins) = match address {
+            Address::Addr(addr) => (
+                &addr.street.name,
+                &addr.zip_codes,
+                &addr.street.administrative_regions,
+            ),
+            Address::Street(street) => (
+                &street.name,
+                &street.zip_codes,
+                &street.administrative_regions,
+            ),
+        };
+
+        let city = admins.iter().find(|admin| admin.is_city());
+        let same_text = |x: &str, y: &str| x.to_lowercase() == y.to_lowercase();
+
+        ReverseMatch {
+            street: (self.street.as_deref()).is_some_and(|street| same_text(street, street_name)),
+            postcode: (self.postcode.as_ref()).is_some_and(|postcode| zip_codes.contains(postcode)),
+            city: (self.city.as_deref())
+                .is_some_and(|name| city.is_some_and(|city| same_text(name, &city.name))),
+            city_admin: self
+                .city_admin_id
+                .as_ref()
+                .is_some_and(|id| city.is_some_and(|city| city.id == *id)),
+        }
+    }
+
+    /// Select the best candidate of a reverse, candidates are expected to be
+    /// sorted by distance. Candidates matching the most important hints are
+    /// preferred, then the closest one.
+    fn select(&self, candidates: Vec<Place>) -> Option<(Address, ReverseMatch)> {
+        candidates
+            .into_iter()
+            .map(|place| {
+                place
+                    .address()
+                    .expect("reverse returned a non-address object")
+            })
+            .enumerate()
+            .map(|(rank, address)| (rank, self.matches(&address), address))
+            .max_by_key(|(rank, matched, _)| (*matched, Reverse(*rank)))
+            .map(|(_, matched, address)| (address, matched))
+    }
+
+    fn log_selected(&self, matched: &ReverseMatch) {
+        debug!(
+            "reverse for {} selected an address using rule {}",
+            self.poi_id,
+            matched.rule()
+        );
+    }
+}
+
+/// Hints matched by a candidate of a reverse, ordered by importance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct ReverseMatch {
+    street: bool,
+    postcode: bool,
+    city: bool,
+    city_admin: bool,
+}
+
+impl ReverseMatch {
+    /// Short description of the matched hints, used for debugging.
+    fn rule(&self) -> String {
+        let rules: Vec<_> = [
+            (self.street, "street"),
+            (self.postcode, "postcode"),
+            (self.city, "city"),
+            (self.city_admin, "city_admin"),
+        ]
+        .into_iter()
+        .filter(|(matched, _)| *matched)
+        .map(|(_, rule)| rule)
+        .collect();
+
+        if rules.is_empty() {
+            "closest".to_string()
+        } else {
+            rules.join("+")
+        }
+    }
+}
+
+/// Get the best address close to input coordinates, if no address is in
+/// range the best street is used instead.
+fn reverse_address<'a>(
+    coord: &Coord,
+    distances: &ReverseDistances,
+    addr_index: Option<&AddrIndex>,
+    hints: ReverseHints<'a>,
+) -> LazyEs<'a, Option<Address>> {
+    let coord = *coord;
+    let street_distance = distances.street_distance.clone();
+
+    get_addr_from_coords(&coord, &distances.addr_distance, addr_index).then(move |places| {
+        match hints.select(places) {
+            Some((address, matched)) => {
+                hints.log_selected(&matched);
+                LazyEs::Value(Some(address))
+            }
+            None => get_street_from_coords(&coord, &street_distance).map(move |places| {
+                let (address, matched) = hints.select(places)?;
+                hints.log_selected(&matched);
+                Some(address)
+            }),
+        }
+    })
+}
+
 fn build_new_addr(
     house_number_tag: &str,
     street_tag: &str,
     poi: &Poi,
+    tags: &BTreeMap<String, String>,
     admins: Vec<Arc<Admin>>,
+    is_place: bool,
 ) -> Address {
-    let postcodes = poi
-        .properties
+    let postcodes = tags
         .iter()
-        .find(|(key, _)| ["addr:postcode", "contact:postcode"].contains(&key.as_str()))
+        .find(|(key, _)| POSTCODE_TAGS.contains(&key.as_str()))
         .map_or_else(
             || {
                 admins
@@ -145,14 +516,39 @@ fn build_new_addr(
             |(_, val)| vec![val.to_owned()],
         );
     let country_codes = find_country_codes(iter_admins(&admins));
-    let street_label = format_street_label(street_tag, iter_admins(&admins), &country_codes);
+
+    // Addresses referring to a place (`addr:place`) are often named after the
+    // city, which must not be repeated in labels.
+    let label_admins: Vec<&Admin> = iter_admins(&admins)
+        .filter(|admin| {
+            !(is_place && admin.is_city() && admin.name.eq_ignore_ascii_case(street_tag))
+        })
+        .collect();
+
+    // If no city was found in admins, the city from tags is used for labels
+    // of places.
+    let city_hint = find_tag(tags, CITY_TAGS)
+        .filter(|_| is_place && !admins.iter().any(|admin| admin.is_city()))
+        .filter(|city| !city.eq_ignore_ascii_case(street_tag));
+
+    let with_city_hint = |name: &str, label: String| match city_hint {
+        Some(city) if label == name => format!("{label} ({city})"),
+        _ => label,
+    };
+
+    let street_label = with_city_hint(
+        street_tag,
+        format_street_label(street_tag, label_admins.iter().copied(), &country_codes),
+    );
 
     let (addr_name, addr_label) = format_addr_name_and_label(
         house_number_tag,
         street_tag,
-        iter_admins(&admins),
+        label_admins.iter().copied(),
         &country_codes,
     );
+
+    let addr_label = with_city_hint(&addr_name, addr_label);
     let weight = admins.iter().find(|a| a.is_city()).map_or(0., |a| a.weight);
     if !house_number_tag.is_empty() {
         Address::Addr(Addr {
@@ -194,20 +590,51 @@ fn build_new_addr(
     }
 }
 
+/// Merge address tags of a POI over the ones it inherited from a polygon.
+fn merge_address_tags(
+    own: &BTreeMap<String, String>,
+    inherited: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut tags = inherited.clone();
+
+    for &keys in ADDRESS_TAGS {
+        if let Some(value) = find_tag(own, keys) {
+            tags.retain(|key, _| !keys.contains(&key.as_str()));
+            tags.insert(keys[0].to_string(), value.clone());
+        }
+    }
+
+    tags
+}
+
 /// Build mimir Address from Poi,using osm address tags (if present)
-/// or using reverse geocoding.
+/// or using reverse geocoding. Tagged addresses may refer to a street
+/// (`addr:street`) or to a place (`addr:place`).
 ///
 /// We also search for the admins that contains the coordinates of the poi
 /// and add them as the address's admins.
 ///
-/// If try_skip_reverse is set to true, it will reuse the address already
-/// attached to a POI in the ES database.
-pub fn find_address<'p>(
+/// POIs without an address of their own use the address tags inherited from
+/// the polygon they are located in, if any. Their own address tags, such as a
+/// house number without a street, take precedence over inherited ones.
+///
+/// The reverse looks for an address, then for a street, within input
+/// distances. Among candidates, the ones matching the POI's street, postcode
+/// and city are preferred over the closest one.
+///
+/// If the address found during a previous import is provided, it is used
+/// instead of performing a reverse and no query is sent to elasticsearch. It
+/// is only provided if inputs of the reverse didn't change since then.
+///
+/// The address is returned along with the method it was found with.
+pub(crate) fn find_address<'p>(
     poi: &'p Poi,
+    inherited_address: &'p BTreeMap<String, String>,
     geofinder: &'p AdminGeoFinder,
-    poi_index: &str,
-    try_skip_reverse: bool,
-) -> LazyEs<'p, Option<Address>> {
+    distances: &ReverseDistances,
+    addr_index: Option<&AddrIndex>,
+    previous_address: Option<Option<Address>>,
+) -> LazyEs<'p, Option<(Address, AddressMethod)>> {
     if poi
         .properties
         .iter()
@@ -217,84 +644,65 @@ pub fn find_address<'p>(
         return LazyEs::Value(None);
     }
 
-    let osm_addr_tag = ["addr:housenumber", "contact:housenumber"]
-        .iter()
-        .find_map(|k| {
-            poi.properties
-                .iter()
-                .find(|(key, _)| key == k)
-                .map(|(_, val)| val)
-        });
+    let has_own_address = find_tag(&poi.properties, STREET_TAGS).is_some()
+        || find_tag(&poi.properties, PLACE_TAGS).is_some();
 
-    let osm_street_tag = ["addr:street", "contact:street"].iter().find_map(|k| {
-        poi.properties
-            .iter()
-            .find(|(key, _)| key == k)
-            .map(|(_, val)| val)
-    });
-
-    match (osm_addr_tag, osm_street_tag) {
-        (Some(house_number_tag), Some(street_tag)) => LazyEs::Value(Some(build_new_addr(
-            house_number_tag,
-            street_tag,
-            poi,
-            geofinder.get(&poi.coord),
-        ))),
-        (None, Some(street_tag)) => get_addr_from_coords(&poi.coord).map(move |addrs| {
-            addrs
-                .into_iter()
-                .find_map(|p| {
-                    let as_address = p.address();
-
-                    match &as_address {
-                        Some(Address::Addr(a)) if a.street.name == *street_tag => as_address,
-                        _ => None,
-                    }
-                })
-                .or_else(|| {
-                    Some(build_new_addr(
-                        "",
-                        street_tag,
-                        poi,
-                        geofinder.get(&poi.coord),
-                    ))
-                })
-        }),
+    let tags = {
+        if has_own_address || inherited_address.is_empty() {
+            Cow::Borrowed(&poi.properties)
+        } else {
+            Cow::Owned(merge_address_tags(&poi.properties, inherited_address))
+        }
+    };
+
+    let osm_addr_tag = find_tag(&tags, HOUSE_NUMBER_TAGS)
+        .and_then(|raw| parse_house_number(raw))
+        .map(|house_number| house_number.primary);
+
+    let osm_street_tag = find_tag(&tags, STREET_TAGS).cloned();
+    let osm_place_tag = find_tag(&tags, PLACE_TAGS).cloned();
+
+    match (osm_addr_tag, osm_street_tag, osm_place_tag) {
+        (Some(house_number), Some(street_tag), _) => {
+            let admins = geofinder.get(&poi.coord);
+            let addr = build_new_addr(&house_number, &street_tag, poi, &tags, admins, false);
+            LazyEs::Value(Some((addr, AddressMethod::Tag)))
+        }
+        // Some addresses are only referring to a place (hamlet, square, ...)
+        // instead of a street, the place is then used as the street name.
+        (Some(house_number), None, Some(place_tag)) => {
+            let admins = geofinder.get(&poi.coord);
+            let addr = build_new_addr(&house_number, &place_tag, poi, &tags, admins, true);
+            LazyEs::Value(Some((addr, AddressMethod::Tag)))
+        }
+        // The inputs of the reverse didn't change since the address was searched
+        _ if previous_address.is_some() => LazyEs::Value(
+            (previous_address.flatten()).map(|address| (address, AddressMethod::Reused)),
+        ),
+        (None, Some(street_tag), _) => {
+            let hints = ReverseHints::new(poi, &tags, geofinder);
+
+            get_addr_from_coords(&poi.coord, &distances.addr_distance, addr_index).map(
+                move |addrs| {
+                    hints
+                        .select(addrs)
+                        .filter(|(_, matched)| matched.street)
+                        .map(|(address, matched)| {
+                            hints.log_selected(&matched);
+                            (address, AddressMethod::Reverse)
+                        })
+                        .or_else(|| {
+                            let admins = geofinder.get(&poi.coord);
+                            let addr = build_new_addr("", &street_tag, poi, &tags, admins, false);
+                            Some((addr, AddressMethod::Tag))
+                        })
+                },
+            )
+        }
         _ => {
-            let lazy_es_address = get_addr_from_coords(&poi.coord).map(|places| {
-                Some(
-                    places
-                        .into_iter()
-                        .next()?
-                        .address()
-                        .expect("`get_address_from_coords` returned a non-address object"),
-                )
-            });
-
-            if try_skip_reverse {
-                // Fetch the address already attached to the POI to avoid computing an
-                // unnecessary reverse.
-                get_current_addr(poi_index, &poi.id).then(move |current_address| {
-                    let changed_coords = |old_coord: Coord| {
-                        (old_coord.lon() - poi.coord.lon()).abs() > 1e-6
-                            || (old_coord.lat() - poi.coord.lat()).abs() > 1e-6
-                    };
-
-                    match current_address {
-                        CurPoiAddress::None { coord } if !changed_coords(coord) => {
-                            LazyEs::Value(None)
-                        }
-                        CurPoiAddress::Some { coord, address }
-                            if !is_addr_derived_from_tags(&address) && !changed_coords(coord) =>
-                        {
-                            LazyEs::Value(Some(*address))
-                        }
-                        _ => lazy_es_address,
-                    }
-                })
-            } else {
-                lazy_es_address
-            }
+            let hints = ReverseHints::new(poi, &tags, geofinder);
+            reverse_address(&poi.coord, distances, addr_index, hints)
+                .map(|address| address.map(|address| (address, AddressMethod::Reverse)))
         }
     }
 }
@@ -302,3 +710,318 @@ pub fn find_address<'p>(
 pub fn iter_admins(admins: &[Arc<Admin>]) -> impl Iterator<Item = &Admin> + Clone {
     admins.iter().map(|a| a.deref())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmogony::ZoneType;
+
+    fn city(id: &str, name: &str) -> Arc<Admin> {
+        Arc::new(Admin {
+            id: id.to_string(),
+            name: name.to_string(),
+            zone_type: Some(ZoneType::City),
+            ..Default::default()
+        })
+    }
+
+    fn addr(id: &str, street_name: &str, postcode: &str, city: &Arc<Admin>) -> Place {
+        let street = Street {
+            id: format!("street:{id}"),
+            name: street_name.to_string(),
+            administrative_regions: vec![city.clone()],
+            zip_codes: vec![postcode.to_string()],
+            ..Default::default()
+        };
+
+        Place::Addr(Addr {
+            id: id.to_string(),
+            house_number: "1".to_string(),
+            name: format!("1 {street_name}"),
+            street,
+            label: format!("1 {street_name}"),
+            coord: Coord::new(0., 0.),
+            approx_coord: None,
+            weight: 0.,
+            zip_codes: vec![postcode.to_string()],
+            distance: None,
+            country_codes: Vec::new(),
+            context: None,
+        })
+    }
+
+    fn hints() -> ReverseHints<'static> {
+        ReverseHints {
+            poi_id: "osm:node:1",
+            street: Some("Rue Bob".to_string()),
+            postcode: Some("75016".to_string()),
+            city: Some("Paris".to_string()),
+            city_admin_id: Some("admin:paris".to_string()),
+        }
+    }
+
+    fn selected_id(hints: &ReverseHints, candidates: Vec<Place>) -> Option<String> {
+        let (address, _) = hints.select(candidates)?;
+
+        match address {
+            Address::Addr(addr) => Some(addr.id),
+            Address::Street(street) => Some(street.id),
+        }
+    }
+
+    fn address(place: Place) -> Address {
+        match place {
+            Place::Addr(addr) => Address::Addr(addr),
+            _ => unreachable!(),
+        }
+    }
+
+    fn house_number(primary: &str, alternates: &[&str]) -> HouseNumber {
+        HouseNumber {
+            primary: primary.to_string(),
+            alternates: alternates.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn house_number_ranges_are_expanded() {
+        assert_eq!(
+            parse_house_number("12-14"),
+            Some(house_number("12", &["14"])),
+        );
+
+        // Different parities mean that both sides of the street are covered
+        assert_eq!(
+            parse_house_number("2-5"),
+            Some(house_number("2", &["3", "4", "5"])),
+        );
+    }
+
+    #[test]
+    fn wide_house_number_ranges_are_not_expanded() {
+        assert_eq!(parse_house_number("1-30"), Some(house_number("1", &["30"])),);
+    }
+
+    #[test]
+    fn house_number_lists_are_split() {
+        let expected = Some(house_number("3", &["5"]));
+        assert_eq!(parse_house_number("3;5"), expected);
+        assert_eq!(parse_house_number("3, 5"), expected);
+        assert_eq!(parse_house_number("3;3, 5"), expected);
+    }
+
+    #[test]
+    fn house_number_suffixes_are_normalized() {
+        assert_eq!(normalize_house_number("12bis"), "12 bis");
+        assert_eq!(normalize_house_number("12  BIS"), "12 bis");
+        assert_eq!(normalize_house_number("4 a"), "4A");
+        assert_eq!(normalize_house_number("Rear"), "Rear");
+        assert_eq!(parse_house_number("4 a").unwrap().primary, "4A");
+    }
+
+    #[test]
+    fn units_and_floors_are_split_from_house_numbers() {
+        assert_eq!(
+            split_unit_and_floor("12 apt 3"),
+            ("12", Some("3".to_string()), None),
+        );
+
+        assert_eq!(
+            split_unit_and_floor("5 #2"),
+            ("5", Some("2".to_string()), None),
+        );
+
+        assert_eq!(
+            split_unit_and_floor("7 fl 2"),
+            ("7", None, Some("2".to_string())),
+        );
+
+        assert_eq!(
+            split_unit_and_floor("7 Apt. 3 floor 2"),
+            ("7", Some("3".to_string()), Some("2".to_string())),
+        );
+
+        // The marker also appears inside of the house number
+        assert_eq!(
+            split_unit_and_floor("12fl fl 2"),
+            ("12fl", None, Some("2".to_string())),
+        );
+
+        let parsed = parse_house_number("12 apt 3").unwrap();
+        assert_eq!(parsed.primary, "12");
+        assert_eq!(parsed.unit.as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn empty_house_numbers_are_ignored() {
+        assert_eq!(parse_house_number(""), None);
+        assert_eq!(parse_house_number(" ; , "), None);
+        assert_eq!(parse_house_number("apt 3"), None);
+        assert_eq!(split_unit_and_floor(""), ("", None, None));
+    }
+
+    fn tagged_label(tags: &[(&str, &str)], admins: Vec<Arc<Admin>>, is_place: bool) -> String {
+        let poi = Poi {
+            id: "osm:node:1".to_string(),
+            ..Default::default()
+        };
+
+        let tags = (tags.iter())
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        match build_new_addr("5", "Bobtown", &poi, &tags, admins, is_place) {
+            Address::Addr(addr) => addr.label,
+            Address::Street(_) => panic!("expected an address"),
+        }
+    }
+
+    #[test]
+    fn place_label_does_not_repeat_city() {
+        let bobtown = city("admin:bobtown", "Bobtown");
+        assert_eq!(tagged_label(&[], vec![bobtown], true), "5 Bobtown");
+
+        // The city from tags is used if no city admin was found
+        let tags = [("addr:city", "Bobville")];
+        assert_eq!(
+            tagged_label(&tags, Vec::new(), true),
+            "5 Bobtown (Bobville)"
+        );
+    }
+
+    #[test]
+    fn street_label_is_unchanged() {
+        let bobtown = city("admin:bobtown", "Bobtown");
+        let label = tagged_label(&[], vec![bobtown], false);
+        assert_eq!(label, "5 Bobtown (Bobtown)");
+
+        let tags = [("addr:city", "Bobville")];
+        assert_eq!(tagged_label(&tags, Vec::new(), false), "5 Bobtown");
+    }
+
+    #[test]
+    fn addr_tags_keep_postcode_of_tags() {
+        let paris = city("admin:paris", "Paris");
+        let tags = addr_tags(&address(addr("addr:1", "Rue Bob", "75016", &paris)));
+
+        let expected: BTreeMap<_, _> = [
+            ("addr:housenumber", "1"),
+            ("addr:street", "Rue Bob"),
+            ("addr:postcode", "75016"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        assert_eq!(tags, expected);
+    }
+
+    #[test]
+    fn addr_tags_ignore_postcode_of_admins() {
+        let paris = Arc::new(Admin {
+            zip_codes: vec!["75016".to_string()],
+            ..city("admin:paris", "Paris").as_ref().clone()
+        });
+
+        let tags = addr_tags(&address(addr("addr:1", "Rue Bob", "75016", &paris)));
+        assert!(!tags.contains_key("addr:postcode"));
+        assert_eq!(tags["addr:street"], "Rue Bob");
+    }
+
+    #[test]
+    fn street_is_preferred_over_other_hints() {
+        let paris = city("admin:paris", "Paris");
+        let other = city("admin:other", "Other");
+
+        let candidates = vec![
+            addr("closest", "rue alice", "75016", &paris),
+            addr("same_street", "rue bob", "75015", &other),
+        ];
+
+        let selected = selected_id(&hints(), candidates);
+        assert_eq!(selected.as_deref(), Some("same_street"));
+    }
+
+    #[test]
+    fn postcode_is_preferred_over_city() {
+        let paris = city("admin:paris", "Paris");
+        let other = city("admin:other", "Other");
+
+        let candidates = vec![
+            addr("closest", "rue alice", "75015", &paris),
+            addr("same_postcode", "rue eve", "75016", &other),
+        ];
+
+        let selected = selected_id(&hints(), candidates);
+        assert_eq!(selected.as_deref(), Some("same_postcode"));
+    }
+
+    #[test]
+    fn city_name_is_preferred_over_city_admin() {
+        // Same admin as the POI, but its name differs from the city tag
+        let paris = city("admin:paris", "Lutèce");
+        let other = city("admin:other", "PARIS");
+
+        let candidates = vec![
+            addr("closest", "rue alice", "75015", &paris),
+            addr("same_city", "rue eve", "75015", &other),
+        ];
+
+        let selected = selected_id(&hints(), candidates);
+        assert_eq!(selected.as_deref(), Some("same_city"));
+    }
+
+    #[test]
+    fn closest_is_selected_among_equal_matches() {
+        let paris = city("admin:paris", "Paris");
+
+        let candidates = vec![
+            addr("closest", "rue alice", "75016", &paris),
+            addr("farther", "rue eve", "75016", &paris),
+        ];
+
+        let selected = selected_id(&hints(), candidates);
+        assert_eq!(selected.as_deref(), Some("closest"));
+    }
+
+    #[test]
+    fn closest_is_selected_when_no_hint_matches() {
+        let paris = city("admin:paris", "Paris");
+        let other = city("admin:other", "Other");
+
+        let candidates = vec![
+            addr("closest", "rue alice", "75015", &other),
+            addr("farther", "rue eve", "75015", &other),
+        ];
+
+        let selected = selected_id(&hints(), candidates);
+        assert_eq!(selected.as_deref(), Some("closest"));
+
+        let no_hints = ReverseHints {
+            poi_id: "osm:node:1",
+            street: None,
+            postcode: None,
+            city: None,
+            city_admin_id: None,
+        };
+
+        let candidates = vec![addr("closest", "rue bob", "75016", &paris)];
+        let selected = selected_id(&no_hints, candidates);
+        assert_eq!(selected.as_deref(), Some("closest"));
+
+        assert_eq!(selected_id(&hints(), Vec::new()), None);
+    }
+
+    #[test]
+    fn describe_matched_rules() {
+        let matched = ReverseMatch {
+            street: true,
+            city_admin: true,
+            ..Default::default()
+        };
+
+        assert_eq!(matched.rule(), "street+city_admin");
+        assert_eq!(ReverseMatch::default().rule(), "closest");
+    }
+}
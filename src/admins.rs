@@ -0,0 +1,259 @@
+//! Load administrative regions from elasticsearch, from a local snapshot of
+//! a previous run or from a cosmogony file.
+//!
+//! A snapshot is a MessagePack file: the tag of the admin index it was built
+//! from is followed by the list of admins. A self-describing format is used as
+//! some fields of admins are optional or stored as GeoJSON.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use elasticsearch::Elasticsearch;
+use mimirsbrunn::admin::{IntoAdmin, ADMIN_MAX_WEIGHT};
+use places::admin::Admin;
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
+
+use mimir::domain::ports::primary::list_documents::ListDocuments;
+
+use crate::mimir::{fetch_admins, MIMIR_PREFIX};
+use crate::settings::AdminsSettings;
+use crate::utils::{get_alias_index, get_index_creation_date};
+
+// Size of the buffers used to read and write snapshots
+const SNAPSHOT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Identifies the admin index a snapshot was built from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SnapshotTag {
+    index: String,
+    creation_date: u64,
+}
+
+/// Load admins from the source enabled in settings: a cosmogony file, a
+/// snapshot if it is still current, or elasticsearch. If snapshots are
+/// enabled, a new snapshot is written after admins are fetched from
+/// elasticsearch.
+pub async fn load_admins<G: ListDocuments<Admin>>(
+    es: &Elasticsearch,
+    mimir: &G,
+    settings: &AdminsSettings,
+) -> Vec<Admin> {
+    if let Some(path) = &settings.cosmogony {
+        info!("reading admins from cosmogony file {}", path.display());
+        return read_cosmogony(path.clone(), settings.langs.clone()).await;
+    }
+
+    let path = match &settings.snapshot {
+        Some(path) => path,
+        None => return fetch_admins(mimir).await,
+    };
+
+    let tag = current_tag(es).await;
+
+    if let Some(tag) = &tag {
+        if let Some(admins) = read_snapshot(path.clone(), tag.clone()).await {
+            info!(
+                "loaded {} admins from snapshot {}",
+                admins.len(),
+                path.display()
+            );
+            return admins;
+        }
+    }
+
+    let admins = fetch_admins(mimir).await;
+
+    match tag {
+        Some(tag) => write_snapshot(path.clone(), tag, admins).await,
+        None => {
+            warn!("could not identify admin index, snapshot won't be written");
+            admins
+        }
+    }
+}
+
+/// Identify the index currently published for admins.
+async fn current_tag(es: &Elasticsearch) -> Option<SnapshotTag> {
+    let index = get_alias_index(es, format!("{MIMIR_PREFIX}_admin")).await?;
+    let creation_date = get_index_creation_date(es, &index).await?;
+    Some(SnapshotTag {
+        index,
+        creation_date,
+    })
+}
+
+/// Read admins from a snapshot, `None` is returned if the snapshot doesn't
+/// exist, is invalid or was built from another index.
+async fn read_snapshot(path: PathBuf, tag: SnapshotTag) -> Option<Vec<Admin>> {
+    spawn_blocking(move || {
+        let file = File::open(&path)
+            .map_err(|err| info!("no admins snapshot at {}: {err}", path.display()))
+            .ok()?;
+
+        let mut reader = BufReader::with_capacity(SNAPSHOT_BUFFER_SIZE, file);
+
+        // Snapshots are only an optimization, invalid ones are written again
+        let invalid = |err| warn!("invalid admins snapshot `{}`: {err}", path.display());
+        let snapshot_tag: SnapshotTag = rmp_serde::from_read(&mut reader).map_err(invalid).ok()?;
+
+        if snapshot_tag != tag {
+            info!("admins snapshot is outdated: {snapshot_tag:?} (current: {tag:?})");
+            return None;
+        }
+
+        rmp_serde::from_read(&mut reader).map_err(invalid).ok()
+    })
+    .await
+    .expect("failed to read admins snapshot")
+}
+
+/// Write admins into a snapshot, failures are only logged as the snapshot
+/// is only an optimization for later runs. Admins are given back once they
+/// have been written.
+async fn write_snapshot(path: PathBuf, tag: SnapshotTag, admins: Vec<Admin>) -> Vec<Admin> {
+    spawn_blocking(move || {
+        let res: Result<(), Box<dyn std::error::Error>> = (|| {
+            let mut writer = BufWriter::with_capacity(SNAPSHOT_BUFFER_SIZE, File::create(&path)?);
+            rmp_serde::encode::write_named(&mut writer, &tag)?;
+            rmp_serde::encode::write_named(&mut writer, &admins)?;
+            writer.flush()?;
+            Ok(())
+        })();
+
+        match res {
+            Ok(()) => info!(
+                "wrote {} admins to snapshot {}",
+                admins.len(),
+                path.display()
+            ),
+            Err(err) => warn!(
+                "could not write admins snapshot `{}`: {err}",
+                path.display()
+            ),
+        }
+
+        admins
+    })
+    .await
+    .expect("failed to write admins snapshot")
+}
+/// Build admins from the zones of a cosmogony file, with the conversion used
+/// by cosmogony2mimir.
+pub async fn read_cosmogony(path: PathBuf, langs: Vec<String>) -> Vec<Admin> {
+    spawn_blocking(move || {
+        let zones: Vec<_> = cosmogony::read_zones_from_file(&path)
+            .unwrap_or_else(|err| {
+                panic!("could not open cosmogony file `{}`: {err}", path.display())
+            })
+            .map(|zone| zone.unwrap_or_else(|err| panic!("invalid zone in cosmogony file: {err}")))
+            .collect();
+
+        // Parents of zones are given by their index in the file
+        let zones_osm_id: BTreeMap<_, _> = (zones.iter())
+            .map(|zone| {
+                let insee = zone.tags.get("ref:INSEE").cloned();
+                (zone.id, (zone.osm_id.clone(), insee))
+            })
+            .collect();
+
+        (zones.into_iter())
+            .map(|zone| zone.into_admin(&zones_osm_id, &langs, ADMIN_MAX_WEIGHT, false, None))
+            .collect()
+    })
+    .await
+    .expect("failed to read cosmogony file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+    use places::coord::Coord;
+    use places::i18n_properties::I18nProperties;
+    use places::Property;
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fafnir-{name}-{}.msgpack", std::process::id()))
+    }
+
+    fn tag(index: &str) -> SnapshotTag {
+        SnapshotTag {
+            index: index.to_string(),
+            creation_date: 1_650_000_000_000,
+        }
+    }
+
+    fn admins() -> Vec<Admin> {
+        let boundary = Polygon::new(
+            LineString::from(vec![(0., 0.), (1., 0.), (1., 1.), (0., 1.), (0., 0.)]),
+            vec![],
+        );
+
+        let city = Admin {
+            id: "admin:osm:relation:1".to_string(),
+            level: 8,
+            name: "Bobtown".to_string(),
+            label: "Bobtown (12345)".to_string(),
+            zip_codes: vec!["12345".to_string()],
+            weight: 0.5,
+            coord: Coord::new(0.5, 0.5),
+            boundary: Some(MultiPolygon(vec![boundary])),
+            zone_type: Some(cosmogony::ZoneType::City),
+            codes: [("ISO3166-2".to_string(), "FR-BOB".to_string())].into(),
+            names: I18nProperties(vec![Property {
+                key: "fr".to_string(),
+                value: "Villebob".to_string(),
+            }]),
+            country_codes: vec!["fr".to_string()],
+            ..Default::default()
+        };
+
+        // Admins without a boundary are kept as well
+        let suburb = Admin {
+            id: "admin:osm:relation:2".to_string(),
+            level: 10,
+            name: "Bobville".to_string(),
+            ..Default::default()
+        };
+
+        vec![city, suburb]
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        let path = snapshot_path("snapshot-round-trip");
+        let admins = write_snapshot(path.clone(), tag("munin_admin_1"), admins()).await;
+        let read = read_snapshot(path.clone(), tag("munin_admin_1")).await;
+        std::fs::remove_file(&path).expect("could not remove snapshot");
+
+        assert_eq!(
+            serde_json::to_value(read.expect("snapshot was not read")).unwrap(),
+            serde_json::to_value(admins).unwrap(),
+        );
+    }
+
+    #[tokio::test]
+    async fn outdated_snapshot_is_ignored() {
+        let path = snapshot_path("outdated-snapshot");
+        write_snapshot(path.clone(), tag("munin_admin_1"), admins()).await;
+        let read = read_snapshot(path.clone(), tag("munin_admin_2")).await;
+        std::fs::remove_file(&path).expect("could not remove snapshot");
+        assert!(read.is_none());
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_snapshot_is_ignored() {
+        let path = snapshot_path("invalid-snapshot");
+        std::fs::write(&path, b"{\"index\": \"munin_admin_1\"}\n").unwrap();
+        let read = read_snapshot(path.clone(), tag("munin_admin_1")).await;
+        std::fs::remove_file(&path).expect("could not remove snapshot");
+        assert!(read.is_none());
+
+        // The file has been removed
+        assert!(read_snapshot(path, tag("munin_admin_1")).await.is_none());
+    }
+}
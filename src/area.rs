@@ -0,0 +1,402 @@
+//! Restrict imports to an area, given as a GeoJSON file or as a list of
+//! admins or countries.
+
+use std::path::Path;
+
+use geo_types::{LineString, MultiPolygon, Polygon};
+use places::admin::Admin;
+use serde_json::{json, Value};
+
+use crate::settings::AreaSettings;
+use crate::validation::{join_key, Problem};
+
+/// A set of polygons, POIs are imported if their location is inside of one
+/// of them.
+#[derive(Debug)]
+pub struct Area {
+    polygons: MultiPolygon<f64>,
+}
+
+impl Area {
+    /// Build the area described by settings located at `key`, admins and
+    /// countries are searched in input list of admins. Returns `None` if the
+    /// import is not restricted to an area, or problems if some admins or
+    /// countries can't be found.
+    pub fn from_settings(
+        settings: &AreaSettings,
+        key: &str,
+        admins: &[Admin],
+    ) -> Result<Option<Self>, Vec<Problem>> {
+        if settings.is_empty() {
+            return Ok(None);
+        }
+
+        let mut polygons = Vec::new();
+        let mut problems = Vec::new();
+
+        if let Some(path) = &settings.geojson {
+            polygons.extend(read_geojson_file(path).0);
+        }
+
+        for (i, admin_id) in settings.admins.iter().enumerate() {
+            let key = join_key(key, format!("admins[{i}]"));
+            let admin = admins.iter().find(|admin| &admin.id == admin_id);
+
+            match admin.map(admin_boundary) {
+                Some(Ok(boundary)) => polygons.extend(boundary.0.iter().cloned()),
+                Some(Err(message)) => problems.push(Problem::new(key, message)),
+                None => problems.push(Problem::new(key, format!("unknown admin `{admin_id}`"))),
+            }
+        }
+
+        for (i, country_code) in settings.countries.iter().enumerate() {
+            let key = join_key(key, format!("countries[{i}]"));
+
+            // All admins of a country share its code, the country is the one
+            // with the lowest level.
+            let country = admins
+                .iter()
+                .filter(|admin| {
+                    (admin.country_codes.iter()).any(|code| code.eq_ignore_ascii_case(country_code))
+                })
+                .min_by_key(|admin| admin.level);
+
+            match country.map(admin_boundary) {
+                Some(Ok(boundary)) => polygons.extend(boundary.0.iter().cloned()),
+                Some(Err(message)) => problems.push(Problem::new(key, message)),
+                None => problems.push(Problem::new(
+                    key,
+                    format!("unknown country `{country_code}`"),
+                )),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        Ok(Some(Self {
+            polygons: MultiPolygon(polygons),
+        }))
+    }
+
+    /// Bounding box of the area, with format [lon1, lat1, lon2, lat2].
+    pub fn bbox(&self) -> [f64; 4] {
+        let coords = (self.polygons.0.iter()).flat_map(|polygon| polygon.exterior().0.iter());
+
+        coords.fold(
+            [
+                f64::INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+            ],
+            |[min_lon, min_lat, max_lon, max_lat], coord| {
+                [
+                    min_lon.min(coord.x),
+                    min_lat.min(coord.y),
+                    max_lon.max(coord.x),
+                    max_lat.max(coord.y),
+                ]
+            },
+        )
+    }
+
+    /// Check if a point is inside of the area.
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        self.polygons.0.iter().any(|polygon| {
+            ring_contains(polygon.exterior(), lon, lat)
+                && !(polygon.interiors().iter()).any(|ring| ring_contains(ring, lon, lat))
+        })
+    }
+
+    /// Format the area as a GeoJSON geometry.
+    pub fn to_geojson(&self) -> String {
+        let ring = |ring: &LineString<f64>| -> Vec<[f64; 2]> {
+            ring.0.iter().map(|coord| [coord.x, coord.y]).collect()
+        };
+
+        let polygons: Vec<_> = (self.polygons.0.iter())
+            .map(|polygon| {
+                std::iter::once(polygon.exterior())
+                    .chain(polygon.interiors())
+                    .map(ring)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        json!({ "type": "MultiPolygon", "coordinates": polygons }).to_string()
+    }
+}
+
+fn admin_boundary(admin: &Admin) -> Result<&MultiPolygon<f64>, String> {
+    (admin.boundary.as_ref()).ok_or_else(|| format!("admin `{}` has no boundary", admin.id))
+}
+
+/// Ray casting test of a point against a closed ring. A point on a vertical
+/// edge is inside only if the ring is on the right of this edge, and a point
+/// on a horizontal edge only if the ring is above it, so that a point is never
+/// inside of two rings sharing an edge.
+fn ring_contains(ring: &LineString<f64>, lon: f64, lat: f64) -> bool {
+    let mut inside = false;
+
+    for segment in ring.0.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+
+        if (a.y > lat) != (b.y > lat) && lon < a.x + (lat - a.y) * (b.x - a.x) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// Read all polygons from a GeoJSON file, which can contain a geometry, a
+/// feature or a collection of features.
+fn read_geojson_file(path: &Path) -> MultiPolygon<f64> {
+    let raw = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("could not read `{}`: {err}", path.display()));
+
+    let geojson: Value = serde_json::from_str(&raw)
+        .unwrap_or_else(|err| panic!("invalid JSON in `{}`: {err}", path.display()));
+
+    let mut polygons = Vec::new();
+
+    parse_geojson(&geojson, &mut polygons)
+        .unwrap_or_else(|err| panic!("invalid GeoJSON in `{}`: {err}", path.display()));
+
+    MultiPolygon(polygons)
+}
+
+fn parse_geojson(geojson: &Value, polygons: &mut Vec<Polygon<f64>>) -> Result<(), String> {
+    let parse_ring = |ring: &Value| -> Result<LineString<f64>, String> {
+        let coords = (ring.as_array().into_iter().flatten())
+            .map(|coord| match coord.as_array().map(Vec::as_slice) {
+                Some([x, y, ..]) => Some((x.as_f64()?, y.as_f64()?)),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("invalid ring: {ring}"))?;
+
+        Ok(LineString::from(coords))
+    };
+
+    let parse_polygon = |polygon: &Value| -> Result<Polygon<f64>, String> {
+        let mut rings = (polygon.as_array().into_iter().flatten()).map(parse_ring);
+
+        let exterior = rings
+            .next()
+            .ok_or_else(|| format!("polygon without ring: {polygon}"))??;
+
+        Ok(Polygon::new(exterior, rings.collect::<Result<_, _>>()?))
+    };
+
+    match geojson["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in geojson["features"].as_array().into_iter().flatten() {
+                parse_geojson(feature, polygons)?;
+            }
+        }
+        Some("Feature") => parse_geojson(&geojson["geometry"], polygons)?,
+        Some("Polygon") => polygons.push(parse_polygon(&geojson["coordinates"])?),
+        Some("MultiPolygon") => {
+            for polygon in geojson["coordinates"].as_array().into_iter().flatten() {
+                polygons.push(parse_polygon(polygon)?);
+            }
+        }
+        _ => return Err(format!("expected polygons, got {}", geojson["type"])),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f64, max: f64) -> LineString<f64> {
+        LineString::from(vec![
+            (min, min),
+            (max, min),
+            (max, max),
+            (min, max),
+            (min, min),
+        ])
+    }
+
+    fn area(geojson: Value) -> Area {
+        let mut polygons = Vec::new();
+        parse_geojson(&geojson, &mut polygons).expect("invalid GeoJSON");
+
+        Area {
+            polygons: MultiPolygon(polygons),
+        }
+    }
+
+    fn admin(id: &str, level: u32, country_code: &str, boundary: Option<LineString<f64>>) -> Admin {
+        Admin {
+            id: id.to_string(),
+            level,
+            country_codes: vec![country_code.to_string()],
+            boundary: boundary.map(|ring| MultiPolygon(vec![Polygon::new(ring, vec![])])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ring_contains_points_inside() {
+        let ring = square(0., 10.);
+        assert!(ring_contains(&ring, 5., 5.));
+        assert!(!ring_contains(&ring, 15., 5.));
+        assert!(!ring_contains(&ring, -5., 5.));
+
+        // Concave ring, shaped as a U
+        let ring = LineString::from(vec![
+            (0., 0.),
+            (3., 0.),
+            (3., 3.),
+            (2., 3.),
+            (2., 1.),
+            (1., 1.),
+            (1., 3.),
+            (0., 3.),
+            (0., 0.),
+        ]);
+
+        assert!(ring_contains(&ring, 0.5, 2.));
+        assert!(!ring_contains(&ring, 1.5, 2.));
+        assert!(ring_contains(&ring, 2.5, 2.));
+    }
+
+    #[test]
+    fn ring_contains_edge_points_once() {
+        let left = square(0., 10.);
+        let right = LineString::from(vec![
+            (10., 0.),
+            (20., 0.),
+            (20., 10.),
+            (10., 10.),
+            (10., 0.),
+        ]);
+
+        for lat in [0., 5., 9.9] {
+            assert!(!ring_contains(&left, 10., lat));
+            assert!(ring_contains(&right, 10., lat));
+        }
+
+        assert!(ring_contains(&left, 5., 0.));
+        assert!(!ring_contains(&left, 5., 10.));
+        assert!(ring_contains(&left, 0., 0.));
+        assert!(!ring_contains(&left, 10., 10.));
+    }
+
+    #[test]
+    fn polygon_holes_are_excluded() {
+        let area = area(json!({
+            "type": "Polygon",
+            "coordinates": [
+                [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]],
+            ]
+        }));
+
+        assert!(area.contains(2., 2.));
+        assert!(!area.contains(5., 5.));
+        assert!(area.contains(7., 5.));
+    }
+
+    #[test]
+    fn multipolygons_and_features_are_merged() {
+        let area = area(json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "MultiPolygon",
+                        "coordinates": [
+                            [[[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]]],
+                            [[[5, 5], [6, 5], [6, 6], [5, 6], [5, 5]]],
+                        ]
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[-3, 2], [-2, 2], [-2, 8], [-3, 8], [-3, 2]]]
+                    }
+                },
+            ]
+        }));
+
+        assert!(area.contains(0.5, 0.5));
+        assert!(area.contains(5.5, 5.5));
+        assert!(area.contains(-2.5, 7.));
+        assert!(!area.contains(3., 3.));
+        assert_eq!(area.bbox(), [-3., 0., 6., 8.]);
+    }
+
+    #[test]
+    fn invalid_geojson_is_rejected() {
+        let parse = |geojson| parse_geojson(&geojson, &mut Vec::new());
+        assert!(parse(json!({ "type": "Point", "coordinates": [0, 0] })).is_err());
+        assert!(parse(json!({ "type": "Polygon", "coordinates": [] })).is_err());
+        assert!(parse(json!({ "type": "Polygon", "coordinates": [[[0, "a"]]] })).is_err());
+    }
+
+    #[test]
+    fn bbox_ignores_holes() {
+        let area = Area {
+            polygons: MultiPolygon(vec![Polygon::new(square(-2., 3.), vec![square(-1., 1.)])]),
+        };
+
+        assert_eq!(area.bbox(), [-2., -2., 3., 3.]);
+    }
+
+    #[test]
+    fn area_is_built_from_admins_and_countries() {
+        let admins = [
+            admin("admin:fr", 2, "fr", Some(square(0., 10.))),
+            admin("admin:paris", 8, "fr", Some(square(4., 5.))),
+            admin("admin:be", 2, "be", Some(square(20., 30.))),
+        ];
+
+        let settings = AreaSettings {
+            admins: vec!["admin:paris".to_string()],
+            countries: vec!["BE".to_string()],
+            ..Default::default()
+        };
+
+        let area = Area::from_settings(&settings, "area", &admins)
+            .expect("invalid area")
+            .expect("missing area");
+
+        assert!(area.contains(4.5, 4.5));
+        assert!(area.contains(25., 25.));
+        assert!(!area.contains(2., 2.));
+    }
+
+    #[test]
+    fn unknown_admins_are_reported() {
+        let admins = [admin("admin:fr", 2, "fr", None)];
+
+        let settings = AreaSettings {
+            admins: vec!["admin:fr".to_string(), "admin:paris".to_string()],
+            countries: vec!["be".to_string()],
+            ..Default::default()
+        };
+
+        let problems = Area::from_settings(&settings, "area", &admins).unwrap_err();
+        let problems: Vec<_> = problems.iter().map(ToString::to_string).collect();
+
+        assert_eq!(
+            problems,
+            [
+                "`area.admins[0]`: admin `admin:fr` has no boundary",
+                "`area.admins[1]`: unknown admin `admin:paris`",
+                "`area.countries[0]`: unknown country `be`",
+            ]
+        );
+    }
+}
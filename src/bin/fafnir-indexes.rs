@@ -0,0 +1,24 @@
+use mimirsbrunn::utils::logger::logger_init;
+use structopt::StructOpt;
+
+use fafnir::cli::load_settings_or_exit;
+use fafnir::indexes::{run_indexes_command, IndexesCommand};
+
+/// Manage indexes created by fafnir: list them, rollback to a previous index
+/// and prune old indexes.
+#[derive(StructOpt, Debug)]
+#[structopt(setting = structopt::clap::AppSettings::ColoredHelp)]
+struct Args {
+    #[structopt(flatten)]
+    config: fafnir::cli::Args,
+
+    #[structopt(subcommand)]
+    command: IndexesCommand,
+}
+
+#[tokio::main]
+async fn main() {
+    let _log_guard = logger_init().expect("could not init logger");
+    let args = Args::from_args();
+    run_indexes_command(load_settings_or_exit(args.config), args.command).await
+}
@@ -0,0 +1,6 @@
+use fafnir::sources::openmaptiles::relocate::relocate_pois;
+
+#[tokio::main]
+async fn main() {
+    fafnir::cli::run(relocate_pois).await
+}
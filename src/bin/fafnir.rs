@@ -0,0 +1,156 @@
+use mimir::adapters::secondary::elasticsearch::ElasticsearchStorage;
+use mimirsbrunn::utils::logger::logger_init;
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use fafnir::admins::load_admins;
+use fafnir::area::Area;
+use fafnir::check::{check_elasticsearch, check_postgres};
+use fafnir::cli::{exit_with_problems, load_settings, load_settings_or_exit};
+use fafnir::indexes::{run_indexes_command, IndexesCommand};
+use fafnir::settings::{AdminsSettings, ElasticsearchSettings, FafnirSettings};
+use fafnir::sources::openmaptiles::import::{import_pois, ImportSettings as OsmSettings};
+use fafnir::sources::openmaptiles::import_query;
+use fafnir::sources::openmaptiles::relocate::relocate_pois;
+use fafnir::sources::tripadvisor::import::{
+    import_tripadvisor, ImportSettings as TripAdvisorImportSettings,
+};
+use fafnir::utils::start_es_client;
+use fafnir::validation::{validate_fields, Problem, Validate};
+
+/// Import POIs into mimir's elasticsearch and manage the indexes created by
+/// imports.
+#[derive(StructOpt, Debug)]
+#[structopt(setting = structopt::clap::AppSettings::ColoredHelp)]
+struct Args {
+    #[structopt(flatten)]
+    config: fafnir::cli::Args,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Import POIs from one of the sources.
+    Import(Source),
+    /// Check that settings of an import are valid and that the services it
+    /// uses can be reached.
+    CheckConfig(Source),
+    /// Print the SQL query used to fetch POIs from postgres.
+    PrintSql,
+    /// Search again for admins and addresses of indexed POIs.
+    Relocate,
+    #[structopt(flatten)]
+    Indexes(IndexesCommand),
+}
+
+#[derive(StructOpt, Debug)]
+enum Source {
+    /// Import POIs from a postgres database populated with the openmaptiles
+    /// schema.
+    Osm,
+    /// Import POIs, photos and reviews from TripAdvisor's dumps.
+    Tripadvisor,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PrintSqlSettings {
+    fafnir: FafnirSettings,
+    elasticsearch: ElasticsearchSettings,
+    #[serde(default)]
+    admins: AdminsSettings,
+}
+
+impl Validate for PrintSqlSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        validate_fields(
+            key,
+            &[
+                ("fafnir", &self.fafnir),
+                ("elasticsearch", &self.elasticsearch),
+                ("admins", &self.admins),
+            ],
+            problems,
+        );
+    }
+}
+
+/// Validate settings of an import, then check that services can be reached if
+/// settings are valid.
+async fn check_config(args: fafnir::cli::Args, source: Source) {
+    let problems = match source {
+        Source::Osm => check_osm_config(args).await,
+        Source::Tripadvisor => check_tripadvisor_config(args).await,
+    };
+
+    if !problems.is_empty() {
+        exit_with_problems(&problems);
+    }
+
+    println!("Configuration is valid");
+}
+
+async fn check_osm_config(args: fafnir::cli::Args) -> Vec<Problem> {
+    let settings: OsmSettings = match load_settings(args) {
+        Ok(settings) => settings,
+        Err(problems) => return problems,
+    };
+
+    let mut problems = check_postgres(&settings.postgres).await;
+    problems.extend(check_elasticsearch(&settings.elasticsearch).await);
+    problems
+}
+
+async fn check_tripadvisor_config(args: fafnir::cli::Args) -> Vec<Problem> {
+    let settings: TripAdvisorImportSettings = match load_settings(args) {
+        Ok(settings) => settings,
+        Err(problems) => return problems,
+    };
+
+    check_elasticsearch(&settings.elasticsearch).await
+}
+
+async fn print_sql(settings: PrintSqlSettings) {
+    let area_settings = &settings.fafnir.area;
+
+    // Admins are only required if the area is made of admins or countries
+    let admins = {
+        if area_settings.admins.is_empty() && area_settings.countries.is_empty() {
+            Vec::new()
+        } else {
+            let es = start_es_client(&settings.elasticsearch);
+
+            let mimir_es = ElasticsearchStorage {
+                client: es.clone(),
+                config: settings.elasticsearch.storage,
+            };
+
+            load_admins(&es, &mimir_es, &settings.admins).await
+        }
+    };
+
+    let area = Area::from_settings(area_settings, "fafnir.area", &admins)
+        .unwrap_or_else(|problems| exit_with_problems(&problems));
+    println!("{}", import_query(area.as_ref(), &settings.fafnir).build());
+}
+
+#[tokio::main]
+async fn main() {
+    let _log_guard = logger_init().expect("could not init logger");
+    let args = Args::from_args();
+
+    match args.command {
+        Command::Import(Source::Osm) => import_pois(load_settings_or_exit(args.config)).await,
+        Command::Import(Source::Tripadvisor) => {
+            import_tripadvisor(load_settings_or_exit(args.config)).await
+        }
+        Command::CheckConfig(source) => check_config(args.config, source).await,
+        Command::PrintSql => print_sql(load_settings_or_exit(args.config)).await,
+        Command::Relocate => relocate_pois(load_settings_or_exit(args.config)).await,
+        Command::Indexes(command) => {
+            run_indexes_command(load_settings_or_exit(args.config), command).await
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Check that services used by imports can be reached, before an import is
+//! started. Settings themselves are checked in `validation`.
+
+use crate::settings::{ElasticsearchSettings, PostgresSettings};
+use crate::utils::{start_es_client, start_postgres_pool};
+use crate::validation::Problem;
+
+/// Check that elasticsearch answers requests.
+pub async fn check_elasticsearch(settings: &ElasticsearchSettings) -> Vec<Problem> {
+    let res = start_es_client(settings)
+        .ping()
+        .send()
+        .await
+        .and_then(|res| res.error_for_status_code());
+
+    match res {
+        Ok(_) => Vec::new(),
+        Err(err) => vec![Problem::new(
+            "elasticsearch.url",
+            format!("could not reach elasticsearch: {err}"),
+        )],
+    }
+}
+
+/// Check that a query can be run on postgres.
+pub async fn check_postgres(settings: &PostgresSettings) -> Vec<Problem> {
+    if let Err(err) = settings.url.parse::<tokio_postgres::Config>() {
+        return vec![Problem::new("postgres.url", format!("invalid url: {err}"))];
+    }
+
+    let pool = start_postgres_pool(settings);
+
+    let res: Result<_, String> = async {
+        let client = pool.get().await.map_err(|err| err.to_string())?;
+        let res = client.simple_query("SELECT 1").await;
+        res.map_err(|err| err.to_string())
+    }
+    .await;
+
+    match res {
+        Ok(_) => Vec::new(),
+        Err(err) => vec![Problem::new(
+            "postgres.url",
+            format!("could not reach postgres: {err}"),
+        )],
+    }
+}
@@ -7,9 +7,12 @@ use serde::de::DeserializeOwned;
 use structopt::StructOpt;
 use tracing::info;
 
-#[derive(StructOpt, Debug)]
+use crate::validation::{validate_settings, Problem, Validate};
+
+// Arguments used to load fafnir's configuration, shared by all binaries.
+#[derive(StructOpt, Clone, Debug)]
 #[structopt(setting = structopt::clap::AppSettings::ColoredHelp)]
-struct Args {
+pub struct Args {
     /// Defines the config directories
     #[structopt(parse(from_os_str), short = "c", long = "config-dir")]
     pub config_dir: PathBuf,
@@ -25,10 +28,49 @@ struct Args {
     pub settings: Vec<String>,
 }
 
-pub async fn run<S: DeserializeOwned, R: Future>(f: impl FnOnce(S) -> R) -> R::Output {
+pub async fn run<S: DeserializeOwned + Validate, R: Future>(f: impl FnOnce(S) -> R) -> R::Output {
     let _log_guard = logger_init().expect("could not init logger");
     let args = Args::from_args();
+    f(load_settings_or_exit(args)).await
+}
+
+/// Build settings from the config directory and overrides given in command
+/// line arguments. All problems found while settings are validated are
+/// returned.
+pub fn load_settings<S: DeserializeOwned + Validate>(args: Args) -> Result<S, Vec<Problem>> {
+    let settings = read_settings(args).map_err(|problem| vec![problem])?;
+    let problems = validate_settings(&settings);
+
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+
+    Ok(settings)
+}
+
+/// Same as `load_settings`, but exit if any problem is found.
+pub fn load_settings_or_exit<S: DeserializeOwned + Validate>(args: Args) -> S {
+    load_settings(args).unwrap_or_else(|problems| exit_with_problems(&problems))
+}
+
+/// Print all problems found in settings before exiting with an error code.
+/// They are not logged as pending logs are not flushed on exit.
+pub fn exit_with_problems(problems: &[Problem]) -> ! {
+    for problem in problems {
+        eprintln!("error: invalid config: {problem}");
+    }
+
+    eprintln!(
+        "error: {} problem(s) found in fafnir config",
+        problems.len()
+    );
+    std::process::exit(1);
+}
 
+/// Build settings from the config directory and overrides given in command
+/// line arguments, without validating them. A problem is returned if the
+/// configuration can't be read or doesn't match expected settings.
+pub fn read_settings<S: DeserializeOwned>(args: Args) -> Result<S, Problem> {
     let raw_config = config_from(
         &args.config_dir,
         &["elasticsearch", "fafnir"],
@@ -36,7 +78,7 @@ pub async fn run<S: DeserializeOwned, R: Future>(f: impl FnOnce(S) -> R) -> R::O
         "MIMIR",
         args.settings,
     )
-    .expect("could not build fafnir config");
+    .map_err(|err| Problem::new("", format!("could not read configuration: {err}")))?;
 
     info!(
         "Full configuration:\n{}",
@@ -49,6 +91,45 @@ pub async fn run<S: DeserializeOwned, R: Future>(f: impl FnOnce(S) -> R) -> R::O
         .expect("could not serialize config"),
     );
 
-    let settings: S = raw_config.try_into().expect("invalid fafnir config");
-    f(settings).await
+    (raw_config.try_into()).map_err(|err| Problem::new("", format!("unexpected settings: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexes::IndexesSettings;
+
+    fn args(settings: &[&str]) -> Args {
+        Args {
+            config_dir: concat!(env!("CARGO_MANIFEST_DIR"), "/config").into(),
+            run_mode: None,
+            settings: settings.iter().map(|setting| setting.to_string()).collect(),
+        }
+    }
+
+    fn problem_keys(settings: &[&str]) -> Vec<String> {
+        let problems = load_settings::<IndexesSettings>(args(settings))
+            .err()
+            .expect("settings are valid");
+
+        problems.into_iter().map(|problem| problem.key).collect()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(load_settings::<IndexesSettings>(args(&[])).is_ok());
+    }
+
+    #[test]
+    fn invalid_settings_are_returned() {
+        assert_eq!(
+            problem_keys(&["retention.keep_previous=0"]),
+            ["retention.keep_previous"]
+        );
+    }
+
+    #[test]
+    fn unexpected_settings_are_returned() {
+        assert_eq!(problem_keys(&["retention.keep_previous=many"]), [""]);
+    }
 }
@@ -0,0 +1,369 @@
+//! Normalization of contact properties: phone numbers, websites and emails.
+//!
+//! Normalized values replace the original ones, which are kept under the
+//! `raw:` namespace when they differ. Values that can't be normalized are
+//! discarded from lists of values and only kept under the `raw:` namespace.
+
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use url::{Host, Url};
+
+/// Prefix of the keys used to store raw values of normalized properties.
+pub const RAW_PREFIX: &str = "raw:";
+
+const PHONE_KEYS: &[&str] = &["phone", "contact:phone", "contact:mobile"];
+const WEBSITE_KEYS: &[&str] = &["website", "contact:website"];
+const EMAIL_KEYS: &[&str] = &["email", "contact:email"];
+
+/// Maximum number of digits in an E.164 phone number.
+const E164_MAX_DIGITS: usize = 15;
+
+/// Minimum number of digits we expect in an E.164 phone number, shorter
+/// numbers are usually special services numbers.
+const E164_MIN_DIGITS: usize = 7;
+
+/// Map country codes to their international calling code and national trunk
+/// prefix (the prefix which must be dropped when dialing from abroad).
+static CALLING_CODES: Lazy<HashMap<&str, (&str, Option<&str>)>> = Lazy::new(|| {
+    [
+        ("AD", ("376", None)),      // andorra
+        ("AT", ("43", Some("0"))),  // austria
+        ("AU", ("61", Some("0"))),  // australia
+        ("BE", ("32", Some("0"))),  // belgium
+        ("BG", ("359", Some("0"))), // bulgaria
+        ("BR", ("55", Some("0"))),  // brazil
+        ("BY", ("375", Some("8"))), // belarus
+        ("CA", ("1", Some("1"))),   // canada
+        ("CH", ("41", Some("0"))),  // switzerland
+        ("CN", ("86", Some("0"))),  // china
+        ("CY", ("357", None)),      // cyprus
+        ("CZ", ("420", None)),      // czechia
+        ("DE", ("49", Some("0"))),  // germany
+        ("DK", ("45", None)),       // denmark
+        ("EE", ("372", None)),      // estonia
+        ("ES", ("34", None)),       // spain
+        ("FI", ("358", Some("0"))), // finland
+        ("FR", ("33", Some("0"))),  // france
+        ("GB", ("44", Some("0"))),  // united kingdom
+        ("GR", ("30", None)),       // greece
+        ("HR", ("385", Some("0"))), // croatia
+        ("HU", ("36", Some("06"))), // hungary
+        ("IE", ("353", Some("0"))), // ireland
+        ("IS", ("354", None)),      // iceland
+        ("IT", ("39", None)),       // italy
+        ("JP", ("81", Some("0"))),  // japan
+        ("KR", ("82", Some("0"))),  // south korea
+        ("LI", ("423", None)),      // liechtenstein
+        ("LT", ("370", Some("8"))), // lithuania
+        ("LU", ("352", None)),      // luxembourg
+        ("LV", ("371", None)),      // latvia
+        ("MC", ("377", None)),      // monaco
+        ("MD", ("373", Some("0"))), // moldova
+        ("MK", ("389", Some("0"))), // north macedonia
+        ("MT", ("356", None)),      // malta
+        ("MX", ("52", None)),       // mexico
+        ("NL", ("31", Some("0"))),  // netherlands
+        ("NO", ("47", None)),       // norway
+        ("NZ", ("64", Some("0"))),  // new zealand
+        ("PL", ("48", None)),       // poland
+        ("PT", ("351", None)),      // portugal
+        ("RO", ("40", Some("0"))),  // romania
+        ("RS", ("381", Some("0"))), // serbia
+        ("RU", ("7", Some("8"))),   // russia
+        ("SE", ("46", Some("0"))),  // sweden
+        ("SG", ("65", None)),       // singapour
+        ("SI", ("386", Some("0"))), // slovenia
+        ("SK", ("421", Some("0"))), // slovakia
+        ("TH", ("66", Some("0"))),  // thailand
+        ("TN", ("216", None)),      // tunisia
+        ("TR", ("90", Some("0"))),  // turkey
+        ("UA", ("380", Some("0"))), // ukraine
+        ("US", ("1", Some("1"))),   // usa
+        ("UY", ("598", Some("0"))), // uruguay
+        ("UZ", ("998", Some("8"))), // uzbekistan
+        ("VE", ("58", Some("0"))),  // venezuela
+        ("VN", ("84", Some("0"))),  // viet nam
+    ]
+    .into_iter()
+    .collect()
+});
+
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct InvalidContact;
+
+type Normalizer<'a> = &'a dyn Fn(&str) -> Result<String, InvalidContact>;
+
+/// Normalize all contact properties of a POI, the POI's country codes are
+/// used to format national phone numbers.
+///
+/// Return the keys of the properties for which some values were discarded
+/// because they are invalid.
+pub fn normalize_contact_properties(
+    properties: &mut BTreeMap<String, String>,
+    country_codes: &[String],
+) -> Vec<&'static str> {
+    let country_code = country_codes.first().map(String::as_str);
+
+    let normalizers: [(&[&'static str], Normalizer); 3] = [
+        (PHONE_KEYS, &|raw| normalize_phone(raw, country_code)),
+        (WEBSITE_KEYS, &normalize_website),
+        (EMAIL_KEYS, &normalize_email),
+    ];
+
+    let mut invalid_values = Vec::new();
+
+    for (keys, normalize) in normalizers {
+        for &key in keys {
+            let raw = match properties.get(key) {
+                Some(raw) => raw.clone(),
+                None => continue,
+            };
+
+            // Invalid items of a list are dropped, valid ones are kept
+            let (values, invalid): (Vec<_>, Vec<_>) = (raw.split(';'))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(normalize)
+                .partition(Result::is_ok);
+
+            let normalized = (values.into_iter())
+                .filter_map(Result::ok)
+                .unique()
+                .join(";");
+
+            if !invalid.is_empty() {
+                invalid_values.push(key);
+            }
+
+            if normalized == raw {
+                continue;
+            }
+
+            if !invalid.is_empty() || !normalized.is_empty() {
+                properties.insert(format!("{RAW_PREFIX}{key}"), raw);
+            }
+
+            if normalized.is_empty() {
+                properties.remove(key);
+            } else {
+                properties.insert(key.to_string(), normalized);
+            }
+        }
+    }
+
+    invalid_values
+}
+
+/// Format a phone number to E.164. National numbers are formatted using the
+/// calling code of input country, if the country is unknown they are kept
+/// untouched.
+///
+/// # Example
+///
+/// ```
+/// # use fafnir::contact::normalize_phone;
+/// assert_eq!(normalize_phone("01.23.45.67.89", Some("fr")).unwrap(), "+33123456789");
+/// assert_eq!(normalize_phone("+33 (0)1 23 45 67 89", None).unwrap(), "+33123456789");
+/// assert_eq!(normalize_phone("0041 44 668 18 00", Some("fr")).unwrap(), "+41446681800");
+/// assert!(normalize_phone("call us!", Some("fr")).is_err());
+/// ```
+pub fn normalize_phone(raw: &str, country_code: Option<&str>) -> Result<String, InvalidContact> {
+    let raw = raw.trim();
+
+    if !raw
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c.is_ascii_digit() || " .-/()".contains(c) || (c == '+' && i == 0))
+    {
+        return Err(InvalidContact);
+    }
+
+    let is_international = raw.starts_with('+') || raw.starts_with("00");
+
+    // Some international numbers are written with the trunk prefix between
+    // brackets, eg. "+33 (0)1 23 45 67 89".
+    let raw = {
+        if is_international {
+            raw.replacen("(0)", "", 1)
+        } else {
+            raw.to_string()
+        }
+    };
+
+    let digits: String = raw.chars().filter(char::is_ascii_digit).collect();
+
+    let international = {
+        if raw.starts_with('+') {
+            digits
+        } else if let Some(digits) = digits.strip_prefix("00") {
+            digits.to_string()
+        } else {
+            let calling = country_code.and_then(|cc| CALLING_CODES.get(cc.to_uppercase().as_str()));
+
+            let (calling_code, trunk_prefix) = match calling {
+                Some(calling) => calling,
+                None => return Ok(raw.trim().to_string()),
+            };
+
+            let national = trunk_prefix
+                .and_then(|prefix| digits.strip_prefix(prefix))
+                .unwrap_or(&digits);
+
+            format!("{calling_code}{national}")
+        }
+    };
+
+    if !(E164_MIN_DIGITS..=E164_MAX_DIGITS).contains(&international.len())
+        || international.starts_with('0')
+    {
+        return Err(InvalidContact);
+    }
+
+    Ok(format!("+{international}"))
+}
+
+/// Normalize a website URL, a default "http" scheme is added if it is
+/// missing.
+///
+/// # Example
+///
+/// ```
+/// # use fafnir::contact::normalize_website;
+/// assert_eq!(normalize_website("www.Qwant.com").unwrap(), "http://www.qwant.com");
+/// assert_eq!(normalize_website("https://qwant.com/maps?q=1").unwrap(), "https://qwant.com/maps?q=1");
+/// assert!(normalize_website("ftp://qwant.com").is_err());
+/// assert!(normalize_website("not a website").is_err());
+/// ```
+pub fn normalize_website(raw: &str) -> Result<String, InvalidContact> {
+    let raw = raw.trim();
+
+    if raw.contains(char::is_whitespace) {
+        return Err(InvalidContact);
+    }
+
+    let url = {
+        if raw.contains("://") {
+            Url::parse(raw)
+        } else {
+            Url::parse(&format!("http://{raw}"))
+        }
+    }
+    .map_err(|_| InvalidContact)?;
+
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err(InvalidContact);
+    }
+
+    match url.host() {
+        Some(Host::Domain(domain)) if domain.contains('.') => {}
+        Some(Host::Ipv4(_) | Host::Ipv6(_)) => {}
+        _ => return Err(InvalidContact),
+    }
+
+    // Don't add a trailing slash to bare domains
+    if url.path() == "/" && url.query().is_none() && url.fragment().is_none() {
+        return Ok(url.as_str().trim_end_matches('/').to_string());
+    }
+
+    Ok(url.to_string())
+}
+
+/// Validate an email address, its domain is normalized to lowercase.
+///
+/// # Example
+///
+/// ```
+/// # use fafnir::contact::normalize_email;
+/// assert_eq!(normalize_email("mailto:Contact@Qwant.COM").unwrap(), "Contact@qwant.com");
+/// assert!(normalize_email("contact@qwant").is_err());
+/// assert!(normalize_email("contact.qwant.com").is_err());
+/// ```
+pub fn normalize_email(raw: &str) -> Result<String, InvalidContact> {
+    let raw = raw.trim();
+    let raw = raw.strip_prefix("mailto:").unwrap_or(raw);
+    let (local, domain) = raw.split_once('@').ok_or(InvalidContact)?;
+
+    let valid_local = !local.is_empty()
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+
+    let labels: Vec<_> = domain.split('.').collect();
+
+    let valid_domain = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(char::is_alphabetic));
+
+    if !valid_local || !valid_domain {
+        return Err(InvalidContact);
+    }
+
+    Ok(format!("{local}@{}", domain.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(props: &[(&str, &str)]) -> (BTreeMap<String, String>, Vec<&'static str>) {
+        let mut properties = (props.iter())
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let invalid = normalize_contact_properties(&mut properties, &["fr".to_string()]);
+        (properties, invalid)
+    }
+
+    #[test]
+    fn rewritten_values_keep_raw_value() {
+        let (props, invalid) = normalized(&[("phone", "01 23 45 67 89")]);
+        assert_eq!(props["phone"], "+33123456789");
+        assert_eq!(props["raw:phone"], "01 23 45 67 89");
+        assert!(invalid.is_empty());
+    }
+
+    #[test]
+    fn unchanged_values_have_no_raw_value() {
+        let (props, invalid) =
+            normalized(&[("phone", "+33123456789"), ("website", "https://qwant.com")]);
+
+        assert_eq!(props.len(), 2);
+        assert_eq!(props["phone"], "+33123456789");
+        assert_eq!(props["website"], "https://qwant.com");
+        assert!(invalid.is_empty());
+    }
+
+    #[test]
+    fn invalid_values_are_removed() {
+        let (props, invalid) = normalized(&[("email", "contact.qwant.com"), ("name", "Bob")]);
+        assert!(!props.contains_key("email"));
+        assert_eq!(props["raw:email"], "contact.qwant.com");
+        assert_eq!(props["name"], "Bob");
+        assert_eq!(invalid, ["email"]);
+    }
+
+    #[test]
+    fn valid_values_of_lists_are_kept() {
+        let (props, invalid) = normalized(&[("contact:phone", "01 23 45 67 89;ext. 12")]);
+        assert_eq!(props["contact:phone"], "+33123456789");
+        assert_eq!(props["raw:contact:phone"], "01 23 45 67 89;ext. 12");
+        assert_eq!(invalid, ["contact:phone"]);
+
+        let (props, invalid) = normalized(&[("phone", "+33123456789; 01.23.45.67.89;0611223344")]);
+        assert_eq!(props["phone"], "+33123456789;+33611223344");
+        assert!(props.contains_key("raw:phone"));
+        assert!(invalid.is_empty());
+    }
+}
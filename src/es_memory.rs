@@ -0,0 +1,286 @@
+//! In-memory implementation of `EsBackend`, which answers queries over a set
+//! of fixture documents. This allows to run computations built with `LazyEs`
+//! without an elasticsearch instance.
+//!
+//! Only the subset of the query DSL used by fafnir is supported: `bool`,
+//! `match_all`, `terms` and `geo_distance` queries, with an optional sort by
+//! `_geo_distance`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::addr_index::{haversine_distance, parse_distance};
+use crate::lazy_es::EsBackend;
+
+// Number of hits returned by elasticsearch if the query doesn't specify it
+const DEFAULT_SIZE: u64 = 10;
+
+/// A document stored in an index of the in-memory backend.
+struct Document {
+    id: String,
+    source: Value,
+}
+
+/// Collection of documents grouped by index.
+#[derive(Default)]
+pub struct MemoryEs {
+    indices: HashMap<String, Vec<Document>>,
+    /// Headers and bodies of the searches received so far.
+    searches: Mutex<Vec<(Value, Value)>>,
+}
+
+impl MemoryEs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a document to an index, the index is created if it doesn't exist.
+    pub fn insert(&mut self, index: &str, id: impl Into<String>, source: Value) {
+        self.indices
+            .entry(index.to_string())
+            .or_default()
+            .push(Document {
+                id: id.into(),
+                source,
+            });
+    }
+
+    /// Get the searches received since the last call, in order.
+    pub fn take_searches(&self) -> Vec<(Value, Value)> {
+        let mut searches = self.searches.lock().expect("poisoned searches");
+        std::mem::take(&mut *searches)
+    }
+
+    /// Answer a single search, with the format of an item of the multi search
+    /// response.
+    fn search(&self, header: &Value, query: &Value) -> Value {
+        self.try_search(header, query)
+            .unwrap_or_else(|reason| json!({ "error": { "reason": reason } }))
+    }
+
+    fn try_search(&self, header: &Value, query: &Value) -> Result<Value, String> {
+        let index_names: Vec<&str> = match &header["index"] {
+            Value::String(index) => vec![index.as_str()],
+            Value::Array(indices) => indices.iter().filter_map(Value::as_str).collect(),
+            other => return Err(format!("unsupported index in header: {other}")),
+        };
+
+        let ignore_unavailable = header["ignore_unavailable"].as_bool().unwrap_or(false);
+        let mut hits = Vec::new();
+
+        for name in index_names {
+            match self.indices.get(name) {
+                Some(documents) => {
+                    for doc in documents {
+                        if query_matches(&query["query"], doc)? {
+                            hits.push(doc);
+                        }
+                    }
+                }
+                None if ignore_unavailable => {}
+                None => return Err(format!("no such index [{name}]")),
+            }
+        }
+
+        match &query["sort"] {
+            Value::Null => {}
+            Value::Array(sorts) => {
+                for sort in sorts.iter().rev() {
+                    let (field, point, descending) = parse_geo_distance_sort(sort)?;
+
+                    let mut keyed = (hits.into_iter())
+                        .map(|doc| Ok((distance_to(doc, field, point)?, doc)))
+                        .collect::<Result<Vec<_>, String>>()?;
+
+                    keyed.sort_by(|(x, _), (y, _)| {
+                        if descending {
+                            y.total_cmp(x)
+                        } else {
+                            x.total_cmp(y)
+                        }
+                    });
+
+                    hits = keyed.into_iter().map(|(_, doc)| doc).collect();
+                }
+            }
+            other => return Err(format!("unsupported sort: {other}")),
+        }
+
+        let size = query["size"].as_u64().unwrap_or(DEFAULT_SIZE);
+
+        let hits: Vec<_> = (hits.into_iter())
+            .take(size as usize)
+            .map(|doc| {
+                json!({
+                    "_id": doc.id,
+                    "_source": filter_source(&doc.source, &query["_source"]),
+                })
+            })
+            .collect();
+
+        Ok(json!({ "hits": { "hits": hits } }))
+    }
+}
+
+#[async_trait]
+impl EsBackend for MemoryEs {
+    async fn msearch(&self, queries: &[(Value, Value)]) -> String {
+        (self.searches.lock().expect("poisoned searches")).extend_from_slice(queries);
+
+        let responses: Vec<_> = (queries.iter())
+            .map(|(header, query)| self.search(header, query))
+            .collect();
+
+        json!({ "responses": responses }).to_string()
+    }
+}
+
+/// Check if a document matches a query, an empty query matches all
+/// documents.
+fn query_matches(query: &Value, doc: &Document) -> Result<bool, String> {
+    let query = match query {
+        Value::Null => return Ok(true),
+        Value::Object(query) if query.len() == 1 => query,
+        other => return Err(format!("unsupported query: {other}")),
+    };
+
+    let (kind, params) = query.iter().next().expect("query has one key");
+
+    match kind.as_str() {
+        "match_all" => Ok(true),
+        "bool" => {
+            let clauses = |name| match &params[name] {
+                Value::Null => Vec::new(),
+                Value::Array(clauses) => clauses.iter().collect(),
+                clause => vec![clause],
+            };
+
+            for clause in clauses("must").into_iter().chain(clauses("filter")) {
+                if !query_matches(clause, doc)? {
+                    return Ok(false);
+                }
+            }
+
+            for clause in clauses("must_not") {
+                if query_matches(clause, doc)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }
+        "terms" => {
+            let (field, values) = single_field(params)?;
+
+            let values = values
+                .as_array()
+                .ok_or_else(|| format!("terms must be a list: {values}"))?;
+
+            if field == "_id" {
+                return Ok(values
+                    .iter()
+                    .any(|value| value.as_str() == Some(doc.id.as_str())));
+            }
+
+            Ok(match get_field(&doc.source, field) {
+                Some(Value::Array(doc_values)) => doc_values.iter().any(|x| values.contains(x)),
+                Some(doc_value) => values.contains(doc_value),
+                None => false,
+            })
+        }
+        "geo_distance" => {
+            let max_distance = params["distance"]
+                .as_str()
+                .and_then(parse_distance)
+                .ok_or_else(|| format!("invalid distance in {params}"))?;
+
+            let (field, point) = (params.as_object().into_iter().flatten())
+                .find(|(key, _)| key.as_str() != "distance")
+                .ok_or_else(|| format!("missing field in {params}"))?;
+
+            let point = parse_point(point).ok_or_else(|| format!("invalid point: {point}"))?;
+            let distance = distance_to(doc, field, point);
+            Ok(matches!(distance, Ok(distance) if distance <= max_distance))
+        }
+        _ => Err(format!("unsupported query: {kind}")),
+    }
+}
+
+/// Read a `{"_geo_distance": {<field>: <point>, "order": ...}}` sort clause.
+fn parse_geo_distance_sort(sort: &Value) -> Result<(&str, [f64; 2], bool), String> {
+    let params = sort["_geo_distance"]
+        .as_object()
+        .ok_or_else(|| format!("unsupported sort: {sort}"))?;
+
+    let descending = params.get("order").and_then(Value::as_str) == Some("desc");
+
+    let (field, point) = params
+        .iter()
+        .find(|(key, _)| {
+            !["order", "unit", "distance_type", "mode", "ignore_unmapped"].contains(&key.as_str())
+        })
+        .ok_or_else(|| format!("missing field in sort: {sort}"))?;
+
+    let point = parse_point(point).ok_or_else(|| format!("invalid point: {point}"))?;
+    Ok((field, point, descending))
+}
+
+/// Distance in meters between a point and the coordinates stored in a field
+/// of a document.
+fn distance_to(doc: &Document, field: &str, point: [f64; 2]) -> Result<f64, String> {
+    let doc_point = get_field(&doc.source, field)
+        .and_then(parse_point)
+        .ok_or_else(|| format!("document {} has no valid point in {field}", doc.id))?;
+
+    Ok(haversine_distance(point, doc_point))
+}
+
+/// Read a geo point written either as an object `{"lat": _, "lon": _}` or as
+/// an array `[lon, lat]`.
+fn parse_point(point: &Value) -> Option<[f64; 2]> {
+    match point {
+        Value::Object(_) => Some([point["lon"].as_f64()?, point["lat"].as_f64()?]),
+        Value::Array(coords) if coords.len() == 2 => {
+            Some([coords[0].as_f64()?, coords[1].as_f64()?])
+        }
+        _ => None,
+    }
+}
+
+/// Read parameters of a query that only contain one field name.
+fn single_field(params: &Value) -> Result<(&str, &Value), String> {
+    match params.as_object() {
+        Some(params) if params.len() == 1 => {
+            let (field, value) = params.iter().next().expect("params have one key");
+            Ok((field, value))
+        }
+        _ => Err(format!("expected a single field in {params}")),
+    }
+}
+
+/// Read a field from a document, nested fields are separated with dots.
+fn get_field<'a>(source: &'a Value, field: &str) -> Option<&'a Value> {
+    field
+        .split('.')
+        .try_fold(source, |value, key| value.as_object()?.get(key))
+}
+
+/// Only keep fields listed in the `_source` parameter of a query.
+fn filter_source(source: &Value, fields: &Value) -> Value {
+    match (source, fields) {
+        (Value::Object(source), Value::Array(fields)) => Value::Object(
+            (source.iter())
+                .filter(|(key, _)| {
+                    fields
+                        .iter()
+                        .any(|field| field.as_str() == Some(key.as_str()))
+                })
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        _ => source.clone(),
+    }
+}
@@ -0,0 +1,157 @@
+//! Fingerprints of the inputs used to build a POI, they are stored in its
+//! document so that the next import can tell if the work performed for this
+//! POI can be reused.
+//!
+//! Fingerprints are built with SipHash-1-3 and constant keys, so that they
+//! don't depend on the version of Rust fafnir is built with.
+
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use places::admin::Admin;
+use places::poi::Poi;
+use siphasher::sip::SipHasher13;
+
+use crate::addresses::is_address_tag;
+use crate::settings::ReverseDistances;
+
+/// Property holding the fingerprint of the inputs of the address search.
+pub const ADDRESS_FINGERPRINT_KEY: &str = "fafnir:address_fingerprint";
+
+/// Property holding the fingerprint of all inputs of the document.
+pub const DOCUMENT_FINGERPRINT_KEY: &str = "fafnir:fingerprint";
+
+// Coordinates are rounded to about 10cm before being hashed
+const COORD_PRECISION: f64 = 1e6;
+
+/// Fingerprint of the inputs used to search for the address of a POI: its
+/// coordinates, address tags, admins and the distances of the reverse.
+pub fn address_fingerprint(
+    poi: &Poi,
+    inherited_address: &BTreeMap<String, String>,
+    admins: &[Arc<Admin>],
+    distances: &ReverseDistances,
+) -> String {
+    let mut hasher = SipHasher13::new();
+
+    for coord in [poi.coord.lon(), poi.coord.lat()] {
+        ((coord * COORD_PRECISION).round() as i64).hash(&mut hasher);
+    }
+
+    (poi.properties.iter())
+        .filter(|(key, _)| key.as_str() == "poi_class" || is_address_tag(key))
+        .for_each(|tag| tag.hash(&mut hasher));
+
+    inherited_address.hash(&mut hasher);
+
+    let mut admin_ids: Vec<_> = admins.iter().map(|admin| admin.id.as_str()).collect();
+    admin_ids.sort_unstable();
+    admin_ids.hash(&mut hasher);
+
+    distances.addr_distance.as_str().hash(&mut hasher);
+    distances.street_distance.as_str().hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+/// Fingerprint of all inputs of the document of a POI, it must be computed
+/// before admins and address are added to the POI. As labels also depend on
+/// how fafnir builds them, the version of fafnir is part of the fingerprint.
+pub fn document_fingerprint(poi: &Poi, address_fingerprint: &str, langs: &[String]) -> String {
+    let mut hasher = SipHasher13::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    address_fingerprint.hash(&mut hasher);
+    langs.hash(&mut hasher);
+
+    serde_json::to_string(poi)
+        .expect("could not serialize POI")
+        .hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+/// Read a fingerprint stored in the properties of an indexed POI.
+pub fn stored_fingerprint<'a>(poi: &'a Poi, key: &str) -> Option<&'a str> {
+    poi.properties.get(key).map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::addr_index::Distance;
+    use places::coord::Coord;
+
+    fn poi(lon: f64, tags: &[(&str, &str)]) -> Poi {
+        Poi {
+            id: "osm:node:1".to_string(),
+            coord: Coord::new(lon, 5.),
+            properties: (tags.iter())
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn fingerprint(poi: &Poi) -> String {
+        let distances = ReverseDistances {
+            addr_distance: Distance::parse("50m").unwrap(),
+            street_distance: Distance::parse("1km").unwrap(),
+        };
+
+        let admins = [Arc::new(Admin {
+            id: "admin:bobtown".to_string(),
+            ..Default::default()
+        })];
+
+        address_fingerprint(poi, &BTreeMap::new(), &admins, &distances)
+    }
+
+    #[test]
+    fn address_fingerprint_only_depends_on_address_inputs() {
+        let base = fingerprint(&poi(5., &[("addr:street", "Rue Bob")]));
+        assert_eq!(base.len(), 16);
+
+        let same = [
+            poi(5.00000001, &[("addr:street", "Rue Bob")]),
+            poi(
+                5.,
+                &[("addr:street", "Rue Bob"), ("contact:phone", "+33 1 23")],
+            ),
+            poi(5., &[("addr:street", "Rue Bob"), ("name", "Bob's shop")]),
+        ];
+
+        for poi in &same {
+            assert_eq!(fingerprint(poi), base, "{:?}", poi.properties);
+        }
+
+        let changed = [
+            poi(5.001, &[("addr:street", "Rue Bob")]),
+            poi(5., &[("addr:street", "Rue Alice")]),
+            poi(5., &[("contact:street", "Rue Bob")]),
+            poi(
+                5.,
+                &[("addr:street", "Rue Bob"), ("addr:postcode", "12345")],
+            ),
+            poi(5., &[("addr:street", "Rue Bob"), ("poi_class", "shop")]),
+        ];
+
+        for poi in &changed {
+            assert_ne!(fingerprint(poi), base, "{:?}", poi.properties);
+        }
+    }
+
+    #[test]
+    fn document_fingerprint_depends_on_all_inputs() {
+        let langs = ["fr".to_string()];
+        let shop = poi(5., &[("name", "Bob's shop")]);
+        let base = document_fingerprint(&shop, "0", &langs);
+
+        assert_eq!(document_fingerprint(&shop, "0", &langs), base);
+        assert_ne!(document_fingerprint(&shop, "1", &langs), base);
+        assert_ne!(document_fingerprint(&shop, "0", &[]), base);
+
+        let renamed = poi(5., &[("name", "Alice's shop")]);
+        assert_ne!(document_fingerprint(&renamed, "0", &langs), base);
+    }
+}
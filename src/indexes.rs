@@ -0,0 +1,328 @@
+//! Maintenance of the indexes created by fafnir: list the indexes of a
+//! container, publish back a previous index and prune old indexes.
+//!
+//! Each import creates a new index named `munin_{name}_{dataset}_{timestamp}`
+//! which is published through the alias `munin_{name}_{dataset}`, and through
+//! `munin_{name}` if the container is public.
+
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+
+use elasticsearch::indices::{IndicesDeleteParts, IndicesGetAliasParts};
+use elasticsearch::Elasticsearch;
+use futures::future::join_all;
+use mimir::domain::model::configuration::ContainerConfig;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+use tracing::info;
+
+use crate::mimir::MIMIR_PREFIX;
+use crate::settings::{ElasticsearchSettings, RetentionSettings};
+use crate::sources::openmaptiles::routing::{DEFAULT_NOSEARCH_CONTAINER, DEFAULT_SEARCH_CONTAINER};
+use crate::utils::{
+    format_timestamp, get_index_creation_date, get_index_docs_count, start_es_client,
+};
+use crate::validation::{validate_fields, Problem, Validate};
+
+/// Settings required to manage indexes of all containers.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IndexesSettings {
+    pub elasticsearch: ElasticsearchSettings,
+    pub retention: RetentionSettings,
+    pub container_search: ContainerConfig,
+    pub container_nosearch: ContainerConfig,
+    pub container_tripadvisor: ContainerConfig,
+    #[serde(default)]
+    pub containers: BTreeMap<String, ContainerConfig>,
+}
+
+impl IndexesSettings {
+    /// All containers fafnir creates indexes for, with the name used to
+    /// refer to them in command line.
+    pub fn containers(&self) -> impl Iterator<Item = (&str, &ContainerConfig)> {
+        [
+            (DEFAULT_SEARCH_CONTAINER, &self.container_search),
+            (DEFAULT_NOSEARCH_CONTAINER, &self.container_nosearch),
+            ("tripadvisor", &self.container_tripadvisor),
+        ]
+        .into_iter()
+        .chain((self.containers.iter()).map(|(name, container)| (name.as_str(), container)))
+    }
+
+    pub fn container(&self, name: &str) -> &ContainerConfig {
+        self.containers()
+            .find(|(key, _)| *key == name)
+            .map(|(_, container)| container)
+            .unwrap_or_else(|| panic!("unknown container `{name}`"))
+    }
+}
+
+impl Validate for IndexesSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        validate_fields(
+            key,
+            &[
+                ("elasticsearch", &self.elasticsearch),
+                ("retention", &self.retention),
+                ("container-search", &self.container_search),
+                ("container-nosearch", &self.container_nosearch),
+                ("container-tripadvisor", &self.container_tripadvisor),
+                ("containers", &self.containers),
+            ],
+            problems,
+        );
+    }
+}
+
+/// Commands managing indexes, they are available through `fafnir-indexes`
+/// and `fafnir`.
+#[derive(StructOpt, Debug)]
+pub enum IndexesCommand {
+    /// List indexes of each container with their creation date and number of
+    /// documents.
+    #[structopt(visible_alias = "stats")]
+    List,
+    /// Publish a previous index of a container instead of the current one.
+    Rollback {
+        /// Container to rollback (search, nosearch, tripadvisor or one of
+        /// the additional containers).
+        #[structopt(long)]
+        container: String,
+        /// Index to publish, defaults to the one preceding the published index.
+        #[structopt(long)]
+        index: Option<String>,
+    },
+    /// Delete old indexes according to the retention policy.
+    Prune {
+        /// Only prune indexes of this container.
+        #[structopt(long)]
+        container: Option<String>,
+        /// Override the number of unpublished indexes kept for each container.
+        #[structopt(long)]
+        keep_previous: Option<NonZeroUsize>,
+        /// List indexes that would be deleted without deleting them.
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
+
+/// Run a command managing indexes.
+pub async fn run_indexes_command(settings: IndexesSettings, command: IndexesCommand) {
+    let es = start_es_client(&settings.elasticsearch);
+
+    match command {
+        IndexesCommand::List => print_indexes(&es, &settings).await,
+        IndexesCommand::Rollback { container, index } => {
+            let published = rollback(&es, settings.container(&container), index.as_deref()).await;
+            info!("Published index {published} for container {container}");
+        }
+        IndexesCommand::Prune {
+            container,
+            keep_previous,
+            dry_run,
+        } => {
+            let keep_previous =
+                (keep_previous.map(NonZeroUsize::get)).unwrap_or(settings.retention.keep_previous);
+
+            let containers: Vec<_> = match &container {
+                Some(name) => vec![(name.as_str(), settings.container(name))],
+                None => settings.containers().collect(),
+            };
+
+            for (name, container) in containers {
+                for index in prune(&es, container, keep_previous, dry_run).await {
+                    if dry_run {
+                        info!("Would delete index {index} of container {name}");
+                    } else {
+                        info!("Deleted index {index} of container {name}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// An index created for a container.
+#[derive(Debug)]
+pub struct IndexInfo {
+    pub name: String,
+    /// Creation date, as a timestamp in milliseconds.
+    pub creation_date: Option<u64>,
+    pub docs_count: Option<u64>,
+    pub aliases: Vec<String>,
+}
+
+impl IndexInfo {
+    /// Check if the index is the one currently published for its container.
+    pub fn is_published(&self, container: &ContainerConfig) -> bool {
+        let alias = container_alias(container);
+        self.aliases.iter().any(|name| *name == alias)
+    }
+}
+
+/// Name of the alias through which the index of a container is published.
+pub fn container_alias(container: &ContainerConfig) -> String {
+    format!("{MIMIR_PREFIX}_{}_{}", container.name, container.dataset)
+}
+
+/// List indexes created for a container, from the most recent to the oldest.
+pub async fn list_indexes(es: &Elasticsearch, container: &ContainerConfig) -> Vec<IndexInfo> {
+    let alias = container_alias(container);
+    let pattern = format!("{alias}_*");
+
+    let raw: Value = es
+        .indices()
+        .get_alias(IndicesGetAliasParts::Index(&[&pattern]))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status_code())
+        .unwrap_or_else(|err| panic!("could not list indexes of `{alias}`: {err}"))
+        .json()
+        .await
+        .expect("could not parse list of aliases");
+
+    // Indexes of other datasets may share the same prefix (eg. `fr` and
+    // `fr_north`), only keep the ones that are directly followed by a
+    // timestamp.
+    let names = (raw.as_object().into_iter().flatten())
+        .filter(|(name, _)| {
+            name[pattern.len() - 1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_digit())
+        })
+        .map(|(name, index)| {
+            let aliases = (index["aliases"].as_object().into_iter().flatten())
+                .map(|(alias, _)| alias.clone())
+                .collect();
+
+            (name.clone(), aliases)
+        });
+
+    let mut indexes = join_all(names.map(|(name, aliases)| async move {
+        IndexInfo {
+            creation_date: get_index_creation_date(es, &name).await,
+            docs_count: get_index_docs_count(es, &name).await,
+            name,
+            aliases,
+        }
+    }))
+    .await;
+
+    indexes.sort_by(|x, y| (y.creation_date, &y.name).cmp(&(x.creation_date, &x.name)));
+
+    indexes
+}
+
+/// Print indexes of each container with their creation date and number of
+/// documents.
+pub async fn print_indexes(es: &Elasticsearch, settings: &IndexesSettings) {
+    for (name, container) in settings.containers() {
+        println!("{name} ({}):", container.dataset);
+
+        for index in list_indexes(es, container).await {
+            let creation_date = (index.creation_date.map(format_timestamp))
+                .unwrap_or_else(|| "unknown date".to_string());
+
+            let docs_count = (index.docs_count.map(|count| count.to_string()))
+                .unwrap_or_else(|| "?".to_string());
+
+            let published = if index.is_published(container) {
+                " [published]"
+            } else {
+                ""
+            };
+
+            println!(
+                "  {}  {creation_date}  {docs_count} docs{published}",
+                index.name
+            );
+        }
+    }
+}
+
+/// Publish a previous index of a container instead of the current one. If no
+/// index is specified, the most recent index older than the published one is
+/// selected. Returns the name of the index that is now published.
+pub async fn rollback(
+    es: &Elasticsearch,
+    container: &ContainerConfig,
+    target: Option<&str>,
+) -> String {
+    let alias = container_alias(container);
+    let indexes = list_indexes(es, container).await;
+
+    let current_pos = indexes
+        .iter()
+        .position(|index| index.is_published(container))
+        .unwrap_or_else(|| panic!("no index is published for `{alias}`"));
+
+    let current = &indexes[current_pos];
+
+    let target = match target {
+        Some(name) => indexes
+            .iter()
+            .find(|index| index.name == name)
+            .unwrap_or_else(|| panic!("unknown index `{name}` for `{alias}`")),
+        None => indexes
+            .get(current_pos + 1)
+            .unwrap_or_else(|| panic!("no index older than `{}` to rollback to", current.name)),
+    };
+
+    if target.name == current.name {
+        panic!("index `{}` is already published", target.name);
+    }
+
+    // All aliases of the current index are moved in a single atomic update.
+    let actions: Vec<_> = (current.aliases.iter())
+        .flat_map(|alias| {
+            [
+                json!({ "remove": { "index": current.name, "alias": alias } }),
+                json!({ "add": { "index": target.name, "alias": alias } }),
+            ]
+        })
+        .collect();
+
+    es.indices()
+        .update_aliases()
+        .body(json!({ "actions": actions }))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status_code())
+        .unwrap_or_else(|err| panic!("could not update aliases of `{alias}`: {err}"));
+
+    target.name.clone()
+}
+
+/// Delete indexes of a container that are not published, except for the
+/// `keep_previous` most recent ones. Returns the list of deleted indexes,
+/// which are only listed if `dry_run` is set.
+pub async fn prune(
+    es: &Elasticsearch,
+    container: &ContainerConfig,
+    keep_previous: usize,
+    dry_run: bool,
+) -> Vec<String> {
+    let to_delete: Vec<_> = list_indexes(es, container)
+        .await
+        .into_iter()
+        .filter(|index| index.aliases.is_empty())
+        .skip(keep_previous)
+        .map(|index| index.name)
+        .collect();
+
+    if !dry_run && !to_delete.is_empty() {
+        let names: Vec<_> = to_delete.iter().map(String::as_str).collect();
+
+        es.indices()
+            .delete(IndicesDeleteParts::Index(&names))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status_code())
+            .unwrap_or_else(|err| panic!("could not delete indexes {names:?}: {err}"));
+    }
+
+    to_delete
+}
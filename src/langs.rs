@@ -57,3 +57,47 @@ pub static COUNTRIES_LANGS: Lazy<HashMap<&str, &[&str]>> = Lazy::new(|| {
     .into_iter()
     .collect()
 });
+
+/// Two-letter language codes from https://en.wikipedia.org/wiki/List_of_ISO_639-1_codes
+const ISO_639_1_CODES: &[&str] = &[
+    "aa", "ab", "ae", "af", "ak", "am", "an", "ar", "as", "av", "ay", "az", "ba", "be", "bg", "bh",
+    "bi", "bm", "bn", "bo", "br", "bs", "ca", "ce", "ch", "co", "cr", "cs", "cu", "cv", "cy", "da",
+    "de", "dv", "dz", "ee", "el", "en", "eo", "es", "et", "eu", "fa", "ff", "fi", "fj", "fo", "fr",
+    "fy", "ga", "gd", "gl", "gn", "gu", "gv", "ha", "he", "hi", "ho", "hr", "ht", "hu", "hy", "hz",
+    "ia", "id", "ie", "ig", "ii", "ik", "io", "is", "it", "iu", "ja", "jv", "ka", "kg", "ki", "kj",
+    "kk", "kl", "km", "kn", "ko", "kr", "ks", "ku", "kv", "kw", "ky", "la", "lb", "lg", "li", "ln",
+    "lo", "lt", "lu", "lv", "mg", "mh", "mi", "mk", "ml", "mn", "mr", "ms", "mt", "my", "na", "nb",
+    "nd", "ne", "ng", "nl", "nn", "no", "nr", "nv", "ny", "oc", "oj", "om", "or", "os", "pa", "pi",
+    "pl", "ps", "pt", "qu", "rm", "rn", "ro", "ru", "rw", "sa", "sc", "sd", "se", "sg", "si", "sk",
+    "sl", "sm", "sn", "so", "sq", "sr", "ss", "st", "su", "sv", "sw", "ta", "te", "tg", "th", "ti",
+    "tk", "tl", "tn", "to", "tr", "ts", "tt", "tw", "ty", "ug", "uk", "ur", "uz", "ve", "vi", "vo",
+    "wa", "wo", "xh", "yi", "yo", "za", "zh", "zu",
+];
+
+/// Check if a language code is known, it may be followed by a script or a
+/// region, eg. `zh-Hant`.
+pub fn is_known_lang(lang: &str) -> bool {
+    let primary = lang.split(['-', '_']).next().unwrap_or_default();
+    ISO_639_1_CODES.contains(&primary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso_639_1_codes_are_known() {
+        assert!(is_known_lang("fr"));
+        assert!(is_known_lang("en"));
+        assert!(!is_known_lang("xx"));
+        assert!(!is_known_lang("fre"));
+        assert!(!is_known_lang(""));
+    }
+
+    #[test]
+    fn script_and_region_are_ignored() {
+        assert!(is_known_lang("zh-Hant"));
+        assert!(is_known_lang("pt_BR"));
+        assert!(!is_known_lang("xx-Latn"));
+    }
+}
@@ -1,13 +1,21 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::fmt;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
 use elasticsearch::http::request::JsonBody;
 use elasticsearch::{Elasticsearch, MsearchParts};
 use futures::lock::Mutex;
+use linked_hash_map::LinkedHashMap;
 use mimir::utils::futures::with_backoff;
 use serde::Deserialize;
 use serde_json::value::RawValue;
-use tracing::warn;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::metrics::METRICS;
 
 const BACKOFF_RETRIES: u8 = 6;
 const BACKOFF_DELAY: Duration = Duration::from_secs(1);
@@ -16,6 +24,14 @@ const BACKOFF_DELAY: Duration = Duration::from_secs(1);
 // --- LazyEs
 // ---
 
+/// Progress function of a computation waiting for the answers to several
+/// independent requests, hits are given in the same order as the requests.
+type MultiProgress<'p, T> =
+    Box<dyn FnOnce(Vec<Vec<EsHit<&RawValue>>>) -> LazyEs<'p, T> + 'p + Send>;
+
+/// A request to elasticsearch, with the cache key it may be shared with.
+pub type EsQuery = (serde_json::Value, serde_json::Value, Option<String>);
+
 /// Computation result that may lazily rely on an elasticsearch "search"
 /// request.
 pub enum LazyEs<'p, T> {
@@ -28,8 +44,20 @@ pub enum LazyEs<'p, T> {
         // TODO: Isn't RawValue enough ?
         header: serde_json::Value,
         query: serde_json::Value,
+        /// Queries sharing a cache key are considered to be equivalent: only
+        /// one of them is sent and their hits are kept in the cache. Queries
+        /// without a key are only shared with identical queries of the same
+        /// batch and are never cached.
+        cache_key: Option<String>,
         progress: Box<dyn FnOnce(Vec<EsHit<&RawValue>>) -> LazyEs<'p, T> + 'p + Send>,
     },
+    /// The computation needs several independent requests to elasticsearch
+    /// in order to make progress, they will be sent in the same batch. This
+    /// is built by combinators such as `join`.
+    NeedEsQueries {
+        queries: Vec<EsQuery>,
+        progress: MultiProgress<'p, T>,
+    },
 }
 
 impl<'p, T: 'p> LazyEs<'p, T> {
@@ -49,12 +77,21 @@ impl<'p, T: 'p> LazyEs<'p, T> {
         }
     }
 
-    /// Read the header and query that have to be sent to elasticsearch if it
-    /// is required.
-    pub fn header_and_query(&self) -> Option<(&serde_json::Value, &serde_json::Value)> {
+    /// Read the header, query and cache key of requests that have to be sent
+    /// to elasticsearch to make progress, this is empty if the value is ready.
+    pub fn queries(&self) -> Vec<(&serde_json::Value, &serde_json::Value, Option<&str>)> {
         match self {
-            Self::NeedEsQuery { header, query, .. } => Some((header, query)),
-            _ => None,
+            Self::Value(_) => Vec::new(),
+            Self::NeedEsQuery {
+                header,
+                query,
+                cache_key,
+                ..
+            } => vec![(header, query, cache_key.as_deref())],
+            Self::NeedEsQueries { queries, .. } => queries
+                .iter()
+                .map(|(header, query, cache_key)| (header, query, cache_key.as_deref()))
+                .collect(),
         }
     }
 
@@ -73,88 +110,279 @@ impl<'p, T: 'p> LazyEs<'p, T> {
             Self::NeedEsQuery {
                 header,
                 query,
+                cache_key,
                 progress,
             } => LazyEs::NeedEsQuery {
                 header,
                 query,
+                cache_key,
+                progress: Box::new(move |val| progress(val).then(func)),
+            },
+            Self::NeedEsQueries { queries, progress } => LazyEs::NeedEsQueries {
+                queries,
                 progress: Box::new(move |val| progress(val).then(func)),
             },
         }
     }
 
+    /// Run two computations side by side: at each step, the requests needed
+    /// by both of them are sent to elasticsearch in the same batch.
+    pub fn join<U: Send + 'p>(self, other: LazyEs<'p, U>) -> LazyEs<'p, (T, U)>
+    where
+        T: Send,
+    {
+        match (self, other) {
+            (Self::Value(x), other) => other.map(move |y| (x, y)),
+            (this, LazyEs::Value(y)) => this.map(move |x| (x, y)),
+            (this, other) => {
+                let (this_queries, this_progress) = this.into_queries();
+                let (other_queries, other_progress) = other.into_queries();
+                let split = this_queries.len();
+
+                LazyEs::NeedEsQueries {
+                    queries: this_queries.into_iter().chain(other_queries).collect(),
+                    progress: Box::new(move |mut hits| {
+                        let other_hits = hits.split_off(split);
+                        this_progress(hits).join(other_progress(other_hits))
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Run a list of computations side by side, see `join`.
+    pub fn join_all(partials: impl IntoIterator<Item = Self>) -> LazyEs<'p, Vec<T>>
+    where
+        T: Send,
+    {
+        partials
+            .into_iter()
+            .fold(LazyEs::Value(Vec::new()), |acc, partial| {
+                acc.join(partial).map(|(mut values, value)| {
+                    values.push(value);
+                    values
+                })
+            })
+    }
+
+    /// Split a pending computation into the queries it needs and a function
+    /// that takes the hits for all of these queries.
+    fn into_queries(self) -> (Vec<EsQuery>, MultiProgress<'p, T>)
+    where
+        T: Send,
+    {
+        match self {
+            Self::Value(x) => (Vec::new(), Box::new(move |_| LazyEs::Value(x))),
+            Self::NeedEsQuery {
+                header,
+                query,
+                cache_key,
+                progress,
+            } => (
+                vec![(header, query, cache_key)],
+                Box::new(move |hits| progress(hits.into_iter().next().expect("missing hits"))),
+            ),
+            Self::NeedEsQueries { queries, progress } => (queries, progress),
+        }
+    }
+
+    /// Feed the answers of elasticsearch to a computation that is waiting
+    /// for them, hits are given in the same order as `queries()`.
+    fn progress(&mut self, mut hits: Vec<Vec<EsHit<&RawValue>>>) {
+        *self = match self {
+            LazyEs::Value(_) => unreachable!(),
+            LazyEs::NeedEsQuery { progress, .. } => {
+                let progress = std::mem::replace(progress, Box::new(|_| unreachable!()));
+                progress(hits.pop().expect("missing hits"))
+            }
+            LazyEs::NeedEsQueries { progress, .. } => {
+                let progress = std::mem::replace(progress, Box::new(|_| unreachable!()));
+                progress(hits)
+            }
+        };
+    }
+}
+
+impl<'p, U: Send + 'p> LazyEs<'p, Option<U>> {
+    /// Run two computations side by side and keep the result of the first
+    /// one, unless it is `None`. Note that the requests of the fallback are
+    /// sent in the same batches, unless the first result is already known.
+    pub fn select(self, fallback: Self) -> Self {
+        match self {
+            Self::Value(Some(x)) => Self::Value(Some(x)),
+            this => this.join(fallback).map(|(x, y)| x.or(y)),
+        }
+    }
+}
+
+impl<'p, T: 'p> LazyEs<'p, T> {
     /// Send a request to elasticsearch to make progress for all computations
     /// in `partials` that are not done yet.
-    async fn batch_make_progress<'a>(
-        es: &Elasticsearch,
+    ///
+    /// Identical queries, or queries sharing a cache key, are only sent once
+    /// and their hits are shared among all computations waiting for them. If
+    /// a cache is provided, queries with a cache key that are found in the
+    /// cache are not sent at all. If a throttle is provided, it may reduce the
+    /// size of the batch and delay the request.
+    async fn batch_make_progress(
+        backend: &impl EsBackend,
         partials: &mut [Self],
         max_batch_size: usize,
+        cache: Option<&EsCache>,
+        throttle: Option<&EsThrottle>,
     ) -> Result<usize, EsError> {
-        let need_progress: Vec<_> = partials
-            .iter_mut()
-            .filter(|partial| partial.value().is_none())
-            .take(max_batch_size)
-            .collect();
+        let mut progress_count = 0;
 
-        if need_progress.is_empty() {
-            return Ok(0);
-        }
+        let max_batch_size = throttle.map_or(max_batch_size, |throttle| {
+            throttle.batch_size().min(max_batch_size)
+        });
 
-        let body: Vec<_> = {
-            need_progress
-                .iter()
-                .flat_map(|partial| {
-                    partial
-                        .header_and_query()
-                        .map(|(header, query)| [header, query])
+        // Map each distinct query to its position in the request
+        let mut query_ids: HashMap<String, usize> = HashMap::new();
+        let mut body = Vec::new();
+
+        // Keys of queries that can be cached, by position in the request
+        let mut cache_keys = Vec::new();
+
+        // Pairs of computations and the ids of the queries they are waiting for
+        let mut waiting = Vec::new();
+
+        for (partial_id, partial) in partials.iter_mut().enumerate() {
+            let keys: Vec<_> = (partial.queries().iter())
+                .map(|(header, query, cache_key)| match cache_key {
+                    Some(cache_key) => cache_key.to_string(),
+                    None => format!("{header}\n{query}"),
                 })
-                .flatten()
-                .map(JsonBody::new)
-                .collect()
+                .collect();
+
+            if keys.is_empty() {
+                continue;
+            }
+
+            let cached: Option<Vec<_>> = cache.and_then(|cache| {
+                (keys.iter().zip(partial.queries()))
+                    .map(|(key, (_, _, cache_key))| cache_key.and_then(|_| cache.get(key)))
+                    .collect()
+            });
+
+            if let Some(cached) = cached {
+                partial.progress(
+                    cached
+                        .iter()
+                        .map(|hits| {
+                            hits.iter()
+                                .map(|source| EsHit { source: &**source })
+                                .collect()
+                        })
+                        .collect(),
+                );
+                progress_count += 1;
+                continue;
+            }
+
+            // A computation is only delayed to next batch if its queries
+            // don't fit in this one, at least one is accepted in each batch.
+            let new_queries = (keys.iter())
+                .filter(|key| !query_ids.contains_key(*key))
+                .count();
+
+            if !query_ids.is_empty() && query_ids.len() + new_queries > max_batch_size {
+                continue;
+            }
+
+            let mut ids = Vec::with_capacity(keys.len());
+
+            for (key, (header, query, cache_key)) in keys.into_iter().zip(partial.queries()) {
+                let next_id = query_ids.len();
+
+                let query_id = match query_ids.entry(key) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(entry) => {
+                        body.push((header.clone(), query.clone()));
+                        cache_keys.push(cache_key.map(str::to_string));
+                        *entry.insert(next_id)
+                    }
+                };
+
+                ids.push(query_id);
+            }
+
+            waiting.push((partial_id, ids));
+        }
+
+        if body.is_empty() {
+            return Ok(progress_count);
+        }
+
+        let permit = match throttle {
+            Some(throttle) => Some(throttle.acquire(body.len()).await),
+            None => None,
         };
 
-        let es_request = with_backoff(
-            || {
-                es.msearch(MsearchParts::None)
-                    .body(body.iter().collect())
-                    .send()
-            },
-            BACKOFF_RETRIES,
-            BACKOFF_DELAY,
-        );
+        let start = Instant::now();
+        let es_response = backend.msearch(&body).await;
+        let latency = start.elapsed();
+        drop(permit);
+
+        METRICS.msearch_requests.inc();
+        METRICS.msearch_queries.add(body.len() as u64);
+        METRICS.msearch_latency.observe(latency);
+
+        let queries_len = query_ids.len();
+        let responses = match parse_es_multi_response::<&RawValue>(&es_response) {
+            Ok(responses) => responses,
+            Err(err) => {
+                if let Some(throttle) = throttle {
+                    throttle.report(latency, true);
+                }
 
-        let es_response = es_request
-            .await
-            .expect("ES query failed")
-            .text()
-            .await
-            .expect("failed to read ES response");
+                METRICS.errors.get("es_response").inc();
+                return Err(EsError::Parsing(err));
+            }
+        };
 
-        let need_progress_len = need_progress.len();
-        let responses = parse_es_multi_response(&es_response).map_err(EsError::Parsing)?;
-        assert_eq!(responses.len(), need_progress_len);
+        assert_eq!(responses.len(), queries_len);
 
-        let mut progress_count = 0;
         let mut errors = Vec::new();
 
-        for (partial, res) in need_progress.into_iter().zip(responses) {
-            match res.into_hits() {
-                Ok(hits) => {
-                    let progress = match partial {
-                        LazyEs::Value(_) => unreachable!(),
-                        LazyEs::NeedEsQuery { progress, .. } => progress,
-                    };
-
-                    let progress = std::mem::replace(progress, Box::new(|_| unreachable!()));
-                    *partial = progress(hits);
-                    progress_count += 1;
+        let results: Vec<_> = responses
+            .into_iter()
+            .map(|res| res.into_hits().map_err(|err| errors.push(err)).ok())
+            .collect();
+
+        if let Some(throttle) = throttle {
+            throttle.report(latency, !errors.is_empty());
+        }
+
+        if !errors.is_empty() {
+            METRICS.errors.get("es_query").add(errors.len() as u64);
+        }
+
+        if let Some(cache) = cache {
+            for (cache_key, hits) in cache_keys.into_iter().zip(&results) {
+                if let (Some(cache_key), Some(hits)) = (cache_key, hits) {
+                    cache.insert(
+                        cache_key,
+                        hits.iter().map(|hit| hit.source.to_owned()).collect(),
+                    );
                 }
-                Err(err) => errors.push(err),
+            }
+        }
+
+        for (partial_id, query_ids) in waiting {
+            let hits: Option<Vec<_>> = (query_ids.iter())
+                .map(|query_id| results[*query_id].clone())
+                .collect();
+
+            if let Some(hits) = hits {
+                partials[partial_id].progress(hits);
+                progress_count += 1;
             }
         }
 
         if errors.len() > 1 {
             warn!(
-                "got {}/{need_progress_len} errors during bulk progress",
+                "got {}/{queries_len} errors during bulk progress",
                 errors.len(),
             );
         }
@@ -169,9 +397,11 @@ impl<'p, T: 'p> LazyEs<'p, T> {
     /// Run all input computations until they are finished and finally output
     /// the resulting values.
     pub async fn batch_make_progress_until_value(
-        es: &Elasticsearch,
+        backend: &impl EsBackend,
         partials: Vec<Self>,
         max_batch_size: usize,
+        cache: Option<&EsCache>,
+        throttle: Option<&EsThrottle>,
     ) -> Vec<T> {
         // `partials` needs to be wrapped with a `Mutex` (would be a `RefCell` in a single threaded
         // context) because the closure `make_progress` will return a future containing a mutable
@@ -184,7 +414,20 @@ impl<'p, T: 'p> LazyEs<'p, T> {
                 .try_lock()
                 .expect("`make_progress` was called concurrently");
 
-            Self::batch_make_progress(es, partials.as_mut(), max_batch_size).await
+            let res = Self::batch_make_progress(
+                backend,
+                partials.as_mut(),
+                max_batch_size,
+                cache,
+                throttle,
+            )
+            .await;
+
+            if res.is_err() {
+                METRICS.backoff_retries.inc();
+            }
+
+            res
         };
 
         // Don't stop while some progress has been made during the loop condition.
@@ -202,6 +445,249 @@ impl<'p, T: 'p> LazyEs<'p, T> {
     }
 }
 
+// ---
+// --- EsBackend
+// ---
+
+/// Transport used by `LazyEs` to send its requests.
+#[async_trait]
+pub trait EsBackend: Sync {
+    /// Send a batch of searches, given as pairs of header and query, and
+    /// return the raw body of the answer, following the format of
+    /// elasticsearch's multi search API.
+    async fn msearch(&self, queries: &[(serde_json::Value, serde_json::Value)]) -> String;
+}
+
+#[async_trait]
+impl EsBackend for Elasticsearch {
+    async fn msearch(&self, queries: &[(serde_json::Value, serde_json::Value)]) -> String {
+        let body: Vec<_> = (queries.iter())
+            .flat_map(|(header, query)| [JsonBody::new(header), JsonBody::new(query)])
+            .collect();
+
+        let es_request = with_backoff(
+            || async {
+                let res = Elasticsearch::msearch(self, MsearchParts::None)
+                    .body(body.iter().collect())
+                    .send()
+                    .await;
+
+                if res.is_err() {
+                    METRICS.backoff_retries.inc();
+                }
+
+                res
+            },
+            BACKOFF_RETRIES,
+            BACKOFF_DELAY,
+        );
+
+        es_request
+            .await
+            .expect("ES query failed")
+            .text()
+            .await
+            .expect("failed to read ES response")
+    }
+}
+
+// ---
+// --- EsCache
+// ---
+
+/// Cache of the hits returned by elasticsearch for some queries, which can be
+/// shared between batches. Least recently used queries are evicted first.
+pub struct EsCache {
+    capacity: usize,
+    entries: std::sync::Mutex<LinkedHashMap<String, Arc<[Box<RawValue>]>>>,
+}
+
+impl EsCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: std::sync::Mutex::new(LinkedHashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<[Box<RawValue>]>> {
+        let mut entries = self.entries.lock().expect("poisoned cache");
+        entries.get_refresh(key).map(|hits| hits.clone())
+    }
+
+    fn insert(&self, key: String, hits: Vec<Box<RawValue>>) {
+        let mut entries = self.entries.lock().expect("poisoned cache");
+        entries.insert(key, hits.into());
+
+        while entries.len() > self.capacity {
+            entries.pop_front();
+        }
+    }
+}
+
+// ---
+// --- EsThrottle
+// ---
+
+/// Limits the load put on elasticsearch by batches of queries, which can be
+/// shared between concurrent computations.
+///
+/// If adaptive, the size of batches and the number of batches sent
+/// concurrently are reduced when elasticsearch answers slowly or with errors,
+/// and increased back up to their initial maximum otherwise.
+pub struct EsThrottle {
+    max_batch_size: usize,
+    max_concurrency: usize,
+    adaptive: Option<AdaptiveSettings>,
+    /// Delay between two queries, enforcing the max rate of queries.
+    query_interval: Option<Duration>,
+    state: std::sync::Mutex<ThrottleState>,
+    released: Notify,
+}
+
+struct AdaptiveSettings {
+    target_latency: Duration,
+    min_batch_size: usize,
+}
+
+struct ThrottleState {
+    batch_size: usize,
+    concurrency: usize,
+    in_flight: usize,
+    next_query: Instant,
+}
+
+/// Allows to send a batch to elasticsearch, the slot of the batch is released
+/// when this is dropped.
+pub struct ThrottlePermit<'a> {
+    throttle: &'a EsThrottle,
+}
+
+impl EsThrottle {
+    pub fn new(max_batch_size: usize, max_concurrency: usize) -> Self {
+        Self {
+            max_batch_size,
+            max_concurrency,
+            adaptive: None,
+            query_interval: None,
+            state: std::sync::Mutex::new(ThrottleState {
+                batch_size: max_batch_size,
+                concurrency: max_concurrency,
+                in_flight: 0,
+                next_query: Instant::now(),
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Adapt batches to keep the response time of elasticsearch below
+    /// `target_latency`, batches won't be smaller than `min_batch_size`.
+    pub fn adaptive(mut self, target_latency: Duration, min_batch_size: usize) -> Self {
+        self.adaptive = Some(AdaptiveSettings {
+            target_latency,
+            min_batch_size: min_batch_size.clamp(1, self.max_batch_size),
+        });
+
+        self
+    }
+
+    /// Don't send more than `max_queries_per_second` queries per second.
+    pub fn max_queries_per_second(mut self, max_queries_per_second: u32) -> Self {
+        self.query_interval =
+            (max_queries_per_second > 0).then(|| Duration::from_secs(1) / max_queries_per_second);
+
+        self
+    }
+
+    /// Current max number of queries in a batch.
+    pub fn batch_size(&self) -> usize {
+        self.state.lock().expect("poisoned throttle").batch_size
+    }
+
+    /// Wait until a batch of `query_count` queries can be sent.
+    pub async fn acquire(&self, query_count: usize) -> ThrottlePermit<'_> {
+        loop {
+            let released = self.released.notified();
+
+            {
+                let mut state = self.state.lock().expect("poisoned throttle");
+
+                if state.in_flight < state.concurrency {
+                    state.in_flight += 1;
+                    break;
+                }
+            }
+
+            released.await;
+        }
+
+        let permit = ThrottlePermit { throttle: self };
+
+        if let Some(interval) = self.query_interval {
+            let delay = self.reserve_slot(Instant::now(), interval, query_count);
+            tokio::time::sleep(delay).await;
+        }
+
+        permit
+    }
+
+    /// Reserve the next time slot for a batch of `query_count` queries, which
+    /// are spaced by `interval`. Returns the delay until the slot starts.
+    fn reserve_slot(&self, now: Instant, interval: Duration, query_count: usize) -> Duration {
+        let mut state = self.state.lock().expect("poisoned throttle");
+        let slot = state.next_query.max(now);
+        state.next_query = slot + interval * query_count.try_into().unwrap_or(u32::MAX);
+        slot - now
+    }
+
+    /// Update batch size and concurrency from the response time of a batch
+    /// and whether it failed.
+    fn report(&self, latency: Duration, failed: bool) {
+        let adaptive = match &self.adaptive {
+            Some(adaptive) => adaptive,
+            None => return,
+        };
+
+        let mut state = self.state.lock().expect("poisoned throttle");
+        let (old_batch_size, old_concurrency) = (state.batch_size, state.concurrency);
+
+        if failed || latency > 2 * adaptive.target_latency {
+            state.batch_size = (state.batch_size / 2).max(adaptive.min_batch_size);
+            state.concurrency = (state.concurrency / 2).max(1);
+        } else if latency > adaptive.target_latency {
+            state.batch_size = (state.batch_size * 3 / 4).max(adaptive.min_batch_size);
+        } else if state.batch_size < self.max_batch_size {
+            state.batch_size =
+                (state.batch_size + (state.batch_size / 10).max(1)).min(self.max_batch_size);
+        } else {
+            state.concurrency = (state.concurrency + 1).min(self.max_concurrency);
+        }
+
+        if (state.batch_size, state.concurrency) != (old_batch_size, old_concurrency) {
+            debug!(
+                "ES batches resized to {} queries with {} concurrent batches (latency: {latency:?}, failed: {failed})",
+                state.batch_size, state.concurrency,
+            );
+        }
+
+        if state.concurrency > old_concurrency {
+            self.released.notify_waiters();
+        }
+    }
+}
+
+impl Drop for ThrottlePermit<'_> {
+    fn drop(&mut self) {
+        self.throttle
+            .state
+            .lock()
+            .expect("poisoned throttle")
+            .in_flight -= 1;
+
+        self.throttle.released.notify_waiters();
+    }
+}
+
 // ---
 // --- Elasticsearch response structure
 // ---
@@ -234,7 +720,7 @@ pub struct EsHits<U> {
     pub hits: Vec<EsHit<U>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct EsHit<U> {
     #[serde(rename = "_source")]
     pub source: U,
@@ -273,3 +759,221 @@ fn parse_es_multi_response<'a, U: Deserialize<'a>>(
     let res: EsResponses<'a, U> = serde_json::from_str(es_multi_response)?;
     Ok(res.responses)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::FutureExt;
+    use serde_json::json;
+
+    use super::*;
+    use crate::es_memory::MemoryEs;
+
+    /// Backend counting the queries it receives.
+    struct CountingEs {
+        es: MemoryEs,
+        sent: AtomicUsize,
+    }
+
+    impl CountingEs {
+        fn new() -> Self {
+            let mut es = MemoryEs::new();
+
+            for id in ["a", "b"] {
+                es.insert("docs", id, json!({ "name": id }));
+            }
+
+            Self {
+                es,
+                sent: AtomicUsize::new(0),
+            }
+        }
+
+        fn sent(&self) -> usize {
+            self.sent.swap(0, Ordering::Relaxed)
+        }
+    }
+
+    #[async_trait]
+    impl EsBackend for CountingEs {
+        async fn msearch(&self, queries: &[(serde_json::Value, serde_json::Value)]) -> String {
+            self.sent.fetch_add(queries.len(), Ordering::Relaxed);
+            self.es.msearch(queries).await
+        }
+    }
+
+    /// Search for a document by id, it returns the name of found documents.
+    fn get<'p>(id: &str, cache_key: Option<&str>) -> LazyEs<'p, Vec<String>> {
+        LazyEs::NeedEsQuery {
+            header: json!({ "index": "docs" }),
+            query: json!({ "query": { "terms": { "_id": [id] } } }),
+            cache_key: cache_key.map(str::to_string),
+            progress: Box::new(|hits| {
+                LazyEs::Value(
+                    hits.into_iter()
+                        .map(|hit| {
+                            let source: serde_json::Value =
+                                serde_json::from_str(hit.source.get()).unwrap();
+                            source["name"].as_str().unwrap().to_string()
+                        })
+                        .collect(),
+                )
+            }),
+        }
+    }
+
+    async fn run(
+        es: &CountingEs,
+        partials: Vec<LazyEs<'static, Vec<String>>>,
+        cache: Option<&EsCache>,
+    ) -> Vec<Vec<String>> {
+        LazyEs::batch_make_progress_until_value(es, partials, 10, cache, None).await
+    }
+
+    #[tokio::test]
+    async fn identical_queries_are_sent_once() {
+        let es = CountingEs::new();
+        let partials = vec![get("a", None), get("b", None), get("a", None)];
+        let results = run(&es, partials, None).await;
+        assert_eq!(results, [["a"], ["b"], ["a"]]);
+        assert_eq!(es.sent(), 2);
+    }
+
+    #[tokio::test]
+    async fn queries_sharing_a_cache_key_are_sent_once() {
+        let es = CountingEs::new();
+
+        // Both queries are considered to be equivalent, the first one is sent
+        let partials = vec![get("a", Some("key")), get("b", Some("key"))];
+        let results = run(&es, partials, None).await;
+        assert_eq!(results, [["a"], ["a"]]);
+        assert_eq!(es.sent(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_queries_with_a_cache_key_are_cached() {
+        let es = CountingEs::new();
+        let cache = EsCache::new(10);
+
+        let results = run(&es, vec![get("a", Some("key"))], Some(&cache)).await;
+        assert_eq!(results, [["a"]]);
+        assert_eq!(es.sent(), 1);
+
+        let results = run(&es, vec![get("b", Some("key"))], Some(&cache)).await;
+        assert_eq!(results, [["a"]]);
+        assert_eq!(es.sent(), 0);
+
+        for _ in 0..2 {
+            let results = run(&es, vec![get("b", None)], Some(&cache)).await;
+            assert_eq!(results, [["b"]]);
+            assert_eq!(es.sent(), 1);
+        }
+
+        // All queries are sent if some of them are not cached
+        let joined = get("a", Some("key")).join(get("b", None));
+        let partials = vec![joined.map(|(x, y)| [x, y].concat())];
+        let results = run(&es, partials, Some(&cache)).await;
+        assert_eq!(results, [["a", "b"]]);
+        assert_eq!(es.sent(), 2);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let cache = EsCache::new(2);
+        let hits = || vec![RawValue::from_string("{}".to_string()).unwrap()];
+
+        cache.insert("a".to_string(), hits());
+        cache.insert("b".to_string(), hits());
+        assert!(cache.get("a").is_some());
+
+        cache.insert("c".to_string(), hits());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    fn throttle_state(throttle: &EsThrottle) -> (usize, usize) {
+        let state = throttle.state.lock().unwrap();
+        (state.batch_size, state.concurrency)
+    }
+
+    #[test]
+    fn throttle_shrinks_on_errors_and_slow_responses() {
+        let throttle = EsThrottle::new(100, 4).adaptive(Duration::from_millis(100), 10);
+        let [fast, slow, too_slow] = [50, 150, 250].map(Duration::from_millis);
+
+        throttle.report(slow, false);
+        assert_eq!(throttle_state(&throttle), (75, 4));
+
+        throttle.report(too_slow, false);
+        assert_eq!(throttle_state(&throttle), (37, 2));
+
+        throttle.report(fast, true);
+        assert_eq!(throttle_state(&throttle), (18, 1));
+
+        throttle.report(fast, true);
+        assert_eq!(throttle_state(&throttle), (10, 1));
+    }
+
+    #[test]
+    fn throttle_grows_back_on_fast_responses() {
+        let throttle = EsThrottle::new(100, 4).adaptive(Duration::from_millis(100), 10);
+        let fast = Duration::from_millis(50);
+
+        for _ in 0..4 {
+            throttle.report(fast, true);
+        }
+
+        assert_eq!(throttle_state(&throttle), (10, 1));
+
+        // Batches grow back to their max size before concurrency is increased
+        throttle.report(fast, false);
+        assert_eq!(throttle_state(&throttle), (11, 1));
+
+        while throttle.batch_size() < 100 {
+            throttle.report(fast, false);
+            assert_eq!(throttle_state(&throttle).1, 1);
+        }
+
+        for concurrency in [2, 3, 4, 4] {
+            throttle.report(fast, false);
+            assert_eq!(throttle_state(&throttle), (100, concurrency));
+        }
+    }
+
+    #[test]
+    fn throttle_is_not_adapted_by_default() {
+        let throttle = EsThrottle::new(100, 4);
+        throttle.report(Duration::from_secs(10), true);
+        assert_eq!(throttle_state(&throttle), (100, 4));
+    }
+
+    #[test]
+    fn throttle_spaces_query_slots() {
+        let throttle = EsThrottle::new(100, 4);
+        let interval = Duration::from_millis(100);
+        let now = Instant::now() + Duration::from_secs(1);
+        let ms = Duration::from_millis;
+
+        assert_eq!(throttle.reserve_slot(now, interval, 5), ms(0));
+        assert_eq!(throttle.reserve_slot(now, interval, 2), ms(500));
+        assert_eq!(throttle.reserve_slot(now + ms(200), interval, 1), ms(500));
+
+        // Unused slots are not kept for later
+        assert_eq!(throttle.reserve_slot(now + ms(2000), interval, 1), ms(0));
+        assert_eq!(throttle.reserve_slot(now + ms(2000), interval, 1), ms(100));
+    }
+
+    #[test]
+    fn throttle_limits_concurrent_batches() {
+        let throttle = EsThrottle::new(100, 2);
+        let acquire = || throttle.acquire(1).now_or_never();
+        let (first, second) = (acquire(), acquire());
+        assert!(first.is_some() && second.is_some());
+        assert!(acquire().is_none());
+
+        drop(first);
+        assert!(acquire().is_some());
+    }
+}
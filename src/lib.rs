@@ -1,8 +1,21 @@
+mod addr_index;
 mod addresses;
+pub mod admins;
+pub mod area;
+pub mod check;
 pub mod cli;
+pub mod contact;
+pub mod es_memory;
+mod fingerprint;
+pub mod indexes;
 mod langs;
-mod lazy_es;
+pub mod lazy_es;
+pub mod metrics;
 pub mod mimir;
+pub mod opening_hours;
+pub mod progress;
+pub mod provenance;
 pub mod settings;
 pub mod sources;
 pub mod utils;
+pub mod validation;
@@ -0,0 +1,473 @@
+//! Metrics of an import, exposed with Prometheus' text format through a
+//! local HTTP endpoint or a textfile read by node-exporter.
+//!
+//! Metrics are kept in a global registry so that they can be updated from
+//! anywhere in the pipeline, as it is done for logs.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+use crate::settings::MetricsSettings;
+
+// Buckets of the latency histograms, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30.];
+
+/// Global registry of metrics.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+#[derive(Default)]
+pub struct Metrics {
+    /// Rows read from postgres.
+    pub rows_read: Counter,
+    /// Bytes read from compressed input files.
+    pub bytes_read: Counter,
+    /// POIs for which admins and address have been searched.
+    pub pois_located: Counter,
+    /// Multi search requests sent to elasticsearch.
+    pub msearch_requests: Counter,
+    /// Queries sent to elasticsearch through multi search requests.
+    pub msearch_queries: Counter,
+    /// Response time of multi search requests.
+    pub msearch_latency: Histogram,
+    /// Requests to elasticsearch that failed and were retried.
+    pub backoff_retries: Counter,
+    /// Number of documents waiting in the channel of each container.
+    pub channel_fill: Labeled<Gauge>,
+    /// Documents sent to the index of each container.
+    pub container_documents: Labeled<Counter>,
+    /// Errors by kind.
+    pub errors: Labeled<Counter>,
+    /// Last time some POIs were located, as a UNIX timestamp.
+    pub last_progress: Gauge,
+}
+
+impl Metrics {
+    /// Format all metrics with Prometheus' text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            (
+                "rows_read_total",
+                "Rows read from postgres",
+                &self.rows_read,
+            ),
+            (
+                "bytes_read_total",
+                "Bytes read from compressed input files",
+                &self.bytes_read,
+            ),
+            ("pois_located_total", "POIs located", &self.pois_located),
+            (
+                "msearch_requests_total",
+                "Multi search requests sent to elasticsearch",
+                &self.msearch_requests,
+            ),
+            (
+                "msearch_queries_total",
+                "Queries sent to elasticsearch",
+                &self.msearch_queries,
+            ),
+            (
+                "backoff_retries_total",
+                "Failed requests to elasticsearch that were retried",
+                &self.backoff_retries,
+            ),
+        ];
+
+        for (name, help, counter) in counters {
+            write_header(&mut out, name, help, "counter");
+            writeln!(out, "fafnir_{name} {}", counter.get()).unwrap();
+        }
+
+        let name = "msearch_latency_seconds";
+        write_header(
+            &mut out,
+            name,
+            "Response time of multi search requests",
+            "histogram",
+        );
+        self.msearch_latency.render(&mut out, name);
+
+        let name = "channel_fill";
+        write_header(&mut out, name, "Documents waiting to be indexed", "gauge");
+        self.channel_fill
+            .render(&mut out, name, "container", |gauge| gauge.get().to_string());
+
+        let name = "container_documents_total";
+        write_header(
+            &mut out,
+            name,
+            "Documents sent to each container",
+            "counter",
+        );
+        self.container_documents
+            .render(&mut out, name, "container", |counter| {
+                counter.get().to_string()
+            });
+
+        let name = "errors_total";
+        write_header(&mut out, name, "Errors by kind", "counter");
+        self.errors
+            .render(&mut out, name, "kind", |counter| counter.get().to_string());
+
+        let name = "last_progress_timestamp_seconds";
+        write_header(&mut out, name, "Last time some POIs were located", "gauge");
+        writeln!(out, "fafnir_{name} {}", self.last_progress.get()).unwrap();
+
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP fafnir_{name} {help}").unwrap();
+    writeln!(out, "# TYPE fafnir_{name} {kind}").unwrap();
+}
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    /// Set the gauge to current UNIX timestamp.
+    pub fn set_to_now(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before UNIX epoch");
+
+        self.set(now.as_secs() as i64);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Histogram of durations, with buckets defined by `LATENCY_BUCKETS`.
+pub struct Histogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: LATENCY_BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            if secs <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        (self.sum_micros).fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            let value = bucket.load(Ordering::Relaxed);
+            writeln!(out, "fafnir_{name}_bucket{{le=\"{bound}\"}} {value}").unwrap();
+        }
+
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        writeln!(out, "fafnir_{name}_bucket{{le=\"+Inf\"}} {count}").unwrap();
+        writeln!(out, "fafnir_{name}_sum {sum}").unwrap();
+        writeln!(out, "fafnir_{name}_count {count}").unwrap();
+    }
+}
+
+/// A family of metrics distinguished by the value of a label.
+pub struct Labeled<T>(Mutex<BTreeMap<String, Arc<T>>>);
+
+impl<T> Default for Labeled<T> {
+    fn default() -> Self {
+        Self(Mutex::default())
+    }
+}
+
+impl<T: Default> Labeled<T> {
+    /// Get the metric for a value of the label, it is created if it doesn't
+    /// exist yet.
+    pub fn get(&self, label: &str) -> Arc<T> {
+        let mut metrics = self.0.lock().expect("poisoned metrics");
+
+        if let Some(metric) = metrics.get(label) {
+            return metric.clone();
+        }
+
+        let metric = Arc::new(T::default());
+        metrics.insert(label.to_string(), metric.clone());
+        metric
+    }
+
+    fn render(&self, out: &mut String, name: &str, label: &str, value: impl Fn(&T) -> String) {
+        let metrics = self.0.lock().expect("poisoned metrics");
+
+        for (label_value, metric) in metrics.iter() {
+            let label_value = (label_value.replace('\\', "\\\\"))
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+
+            let value = value(metric);
+            writeln!(out, "fafnir_{name}{{{label}=\"{label_value}\"}} {value}").unwrap();
+        }
+    }
+}
+
+impl Labeled<Counter> {
+    /// Sum of the counters for all values of the label.
+    pub fn sum(&self) -> u64 {
+        let metrics = self.0.lock().expect("poisoned metrics");
+        metrics.values().map(|counter| counter.get()).sum()
+    }
+}
+
+/// Start exposing metrics as configured in settings.
+pub fn start(settings: &MetricsSettings) {
+    if let Some(addr) = settings.listen {
+        tokio::spawn(serve_http(addr));
+    }
+
+    if let Some(path) = settings.textfile.clone() {
+        let interval = Duration::from_secs(settings.textfile_interval_secs);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                write_textfile(&path).await;
+            }
+        });
+    }
+}
+
+/// Write final values of metrics once the import is over.
+pub async fn finish(settings: &MetricsSettings) {
+    if let Some(path) = &settings.textfile {
+        write_textfile(path).await;
+    }
+}
+
+/// Expose metrics through a local HTTP endpoint.
+async fn serve_http(addr: SocketAddr) {
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|err| panic!("could not listen on {addr}: {err}"));
+
+    info!("exposing metrics on http://{addr}/metrics");
+    serve_metrics(listener, &METRICS).await
+}
+
+/// Answer any request to the listener with the metrics, the request itself
+/// is not parsed.
+async fn serve_metrics(listener: TcpListener, metrics: &'static Metrics) {
+    loop {
+        let mut socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                warn!("failed to accept connection to metrics endpoint: {err}");
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            let mut request = [0; 1024];
+
+            if let Err(err) = socket.read(&mut request).await {
+                warn!("failed to read request to metrics endpoint: {err}");
+                return;
+            }
+
+            let body = metrics.render();
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len(),
+            );
+
+            if let Err(err) = socket.write_all(response.as_bytes()).await {
+                warn!("failed to answer request to metrics endpoint: {err}");
+            }
+        });
+    }
+}
+
+/// Write metrics to a file, it is written next to its destination and then
+/// moved so that node-exporter never reads a partial file.
+async fn write_textfile(path: &Path) {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let res = async {
+        tokio::fs::write(&tmp_path, METRICS.render()).await?;
+        tokio::fs::rename(&tmp_path, path).await
+    }
+    .await;
+
+    if let Err(err) = res {
+        warn!("could not write metrics to `{}`: {err}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    fn metrics() -> Metrics {
+        let metrics = Metrics::default();
+        metrics.rows_read.add(42);
+        metrics.msearch_latency.observe(Duration::from_millis(300));
+        metrics.msearch_latency.observe(Duration::from_secs(60));
+        metrics.channel_fill.get("search").set(12);
+        metrics.container_documents.get("search").add(3);
+        metrics.container_documents.get("nosearch").inc();
+        metrics.errors.get("invalid \"name\"\n").inc();
+        metrics.last_progress.set(1_650_000_000);
+        metrics
+    }
+
+    fn lines(text: &str) -> Vec<&str> {
+        text.lines().collect()
+    }
+
+    #[test]
+    fn counters_are_rendered_with_their_header() {
+        let text = metrics().render();
+        let lines = lines(&text);
+
+        for expected in [
+            "# HELP fafnir_rows_read_total Rows read from postgres",
+            "# TYPE fafnir_rows_read_total counter",
+            "fafnir_rows_read_total 42",
+            "fafnir_pois_located_total 0",
+            "# TYPE fafnir_last_progress_timestamp_seconds gauge",
+            "fafnir_last_progress_timestamp_seconds 1650000000",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "missing `{expected}` in:\n{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn histograms_have_cumulative_buckets() {
+        let text = metrics().render();
+
+        let histogram: Vec<_> = lines(&text)
+            .into_iter()
+            .filter(|line| line.starts_with("fafnir_msearch_latency_seconds"))
+            .collect();
+
+        assert_eq!(
+            histogram,
+            [
+                "fafnir_msearch_latency_seconds_bucket{le=\"0.05\"} 0",
+                "fafnir_msearch_latency_seconds_bucket{le=\"0.1\"} 0",
+                "fafnir_msearch_latency_seconds_bucket{le=\"0.25\"} 0",
+                "fafnir_msearch_latency_seconds_bucket{le=\"0.5\"} 1",
+                "fafnir_msearch_latency_seconds_bucket{le=\"1\"} 1",
+                "fafnir_msearch_latency_seconds_bucket{le=\"2.5\"} 1",
+                "fafnir_msearch_latency_seconds_bucket{le=\"5\"} 1",
+                "fafnir_msearch_latency_seconds_bucket{le=\"10\"} 1",
+                "fafnir_msearch_latency_seconds_bucket{le=\"30\"} 1",
+                "fafnir_msearch_latency_seconds_bucket{le=\"+Inf\"} 2",
+                "fafnir_msearch_latency_seconds_sum 60.3",
+                "fafnir_msearch_latency_seconds_count 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_are_sorted_and_escaped() {
+        let metrics = metrics();
+        let text = metrics.render();
+        let lines = lines(&text);
+
+        let documents: Vec<_> = (lines.iter())
+            .filter(|line| line.starts_with("fafnir_container_documents_total{"))
+            .collect();
+
+        assert_eq!(
+            documents,
+            [
+                &"fafnir_container_documents_total{container=\"nosearch\"} 1",
+                &"fafnir_container_documents_total{container=\"search\"} 3",
+            ]
+        );
+
+        assert!(lines.contains(&"fafnir_channel_fill{container=\"search\"} 12"));
+        assert!(lines.contains(&"fafnir_errors_total{kind=\"invalid \\\"name\\\"\\n\"} 1"));
+        assert_eq!(metrics.container_documents.sum(), 4);
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_over_http() {
+        let metrics: &'static Metrics = Box::leak(Box::new(metrics()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics(listener, metrics));
+
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .expect("no body in response");
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert_eq!(body, metrics.render());
+    }
+}
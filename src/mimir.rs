@@ -27,7 +27,7 @@ pub async fn address_updated_after_pois(es: &Elasticsearch) -> bool {
 }
 
 /// Fetch administrative regions.
-pub async fn build_admin_geofinder<G: ListDocuments<Admin>>(mimir: &G) -> AdminGeoFinder {
+pub async fn fetch_admins<G: ListDocuments<Admin>>(mimir: &G) -> Vec<Admin> {
     mimir
         .list_documents()
         .await
@@ -36,3 +36,15 @@ pub async fn build_admin_geofinder<G: ListDocuments<Admin>>(mimir: &G) -> AdminG
         .collect()
         .await
 }
+
+/// Fetch administrative regions and index them.
+pub async fn build_admin_geofinder<G: ListDocuments<Admin>>(mimir: &G) -> AdminGeoFinder {
+    admin_geofinder_from(fetch_admins(mimir).await)
+}
+
+/// Index a list of administrative regions.
+pub fn admin_geofinder_from(admins: Vec<Admin>) -> AdminGeoFinder {
+    let mut geofinder = AdminGeoFinder::default();
+    geofinder.extend(admins);
+    geofinder
+}
@@ -0,0 +1,1357 @@
+//! Parsing, validation and normalization of `opening_hours` values.
+//!
+//! The parser supports most of the OSM specification (see
+//! https://wiki.openstreetmap.org/wiki/Key:opening_hours/specification) and
+//! outputs a canonical form of the input: weekdays are merged into ranges,
+//! time ranges are sorted and merged, full-time schedules are written "24/7"
+//! and "closed" modifiers are written "off".
+
+use std::fmt;
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const MONTHS_FULL_NAMES: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+const EVENTS: [&str; 4] = ["dawn", "sunrise", "sunset", "dusk"];
+
+/// Bitset of weekdays covering the whole week.
+const FULL_WEEK: u8 = 0b111_1111;
+
+/// Minutes in a day.
+const DAY_MINUTES: u16 = 24 * 60;
+
+/// Normalize a raw `opening_hours` value into its canonical form.
+///
+/// # Example
+///
+/// ```
+/// # use fafnir::opening_hours::normalize;
+/// assert_eq!(normalize("Mo 09:00-12:00; Tu 09:00-12:00").unwrap(), "Mo,Tu 09:00-12:00");
+/// assert_eq!(normalize("Monday-Sunday 00:00-24:00").unwrap(), "24/7");
+/// assert_eq!(normalize("PH closed").unwrap(), "PH off");
+/// assert!(normalize("Mo-Fr 25:00-18:00").is_err());
+/// ```
+pub fn normalize(raw: &str) -> Result<String, ParseError> {
+    Ok(raw.parse::<OpeningHours>()?.to_string())
+}
+
+/// A parsed `opening_hours` value, its `Display` implementation outputs the
+/// canonical form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpeningHours {
+    rules: Vec<Rule>,
+}
+
+impl FromStr for OpeningHours {
+    type Err = ParseError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let mut rules = Parser::new(raw).parse()?;
+        rules.iter_mut().for_each(Rule::normalize);
+        let mut rules = merge_weekday_rules(rules);
+        rules.iter_mut().for_each(Rule::detect_always_open);
+        Ok(Self { rules })
+    }
+}
+
+impl fmt::Display for OpeningHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if i > 0 {
+                match rule.separator {
+                    Separator::Normal => write!(f, "; ")?,
+                    Separator::Additional => write!(f, ", ")?,
+                    Separator::Fallback => write!(f, " || ")?,
+                }
+            }
+
+            write!(f, "{rule}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct ParseError {
+    /// Byte offset in the input where the error occurred.
+    pub position: usize,
+    /// Description of what was expected at this position.
+    pub expected: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected {} at position {}",
+            self.expected, self.position
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// ---
+// --- Syntax tree
+// ---
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Separator {
+    Normal,
+    Additional,
+    Fallback,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Modifier {
+    Open,
+    Closed,
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rule {
+    /// Separator between this rule and the previous one.
+    separator: Separator,
+    always_open: bool,
+    years: Vec<YearRange>,
+    monthdays: Vec<MonthdayRange>,
+    weeks: Vec<WeekRange>,
+    weekdays: Vec<WeekdayRange>,
+    holidays: Vec<Holiday>,
+    times: Vec<TimeSpan>,
+    modifier: Option<Modifier>,
+    comment: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct YearRange {
+    start: u16,
+    end: Option<u16>,
+    step: Option<u16>,
+    open_end: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct WeekRange {
+    start: u8,
+    end: Option<u8>,
+    step: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Date {
+    Fixed { month: u8, day: Option<u8> },
+    Easter,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct MonthdayRange {
+    start: Date,
+    end: Option<Date>,
+    open_end: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum WeekdayRange {
+    Span {
+        start: u8,
+        end: u8,
+    },
+    Nth {
+        day: u8,
+        nth: Vec<(i8, Option<i8>)>,
+        offset: i16,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Holiday {
+    /// Either "PH" or "SH".
+    kind: &'static str,
+    offset: i16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TimePoint {
+    /// Minutes since midnight.
+    Clock(u16),
+    Event {
+        event: &'static str,
+        offset: i16,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TimeSpan {
+    start: TimePoint,
+    end: Option<TimePoint>,
+    open_end: bool,
+    period: Option<u16>,
+}
+
+impl Rule {
+    fn new(separator: Separator) -> Self {
+        Self {
+            separator,
+            always_open: false,
+            years: Vec::new(),
+            monthdays: Vec::new(),
+            weeks: Vec::new(),
+            weekdays: Vec::new(),
+            holidays: Vec::new(),
+            times: Vec::new(),
+            modifier: None,
+            comment: None,
+        }
+    }
+
+    fn has_wide_range(&self) -> bool {
+        !(self.years.is_empty() && self.monthdays.is_empty() && self.weeks.is_empty())
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.always_open
+            && !self.has_wide_range()
+            && self.weekdays.is_empty()
+            && self.holidays.is_empty()
+            && self.times.is_empty()
+            && self.modifier.is_none()
+            && self.comment.is_none()
+    }
+
+    /// Bitset of weekdays selected by this rule if it only contains plain
+    /// weekday ranges.
+    fn weekdays_set(&self) -> Option<u8> {
+        if self.weekdays.is_empty() {
+            return None;
+        }
+
+        self.weekdays.iter().try_fold(0, |acc, range| match range {
+            WeekdayRange::Span { start, end } => Some(acc | weekdays_span_set(*start, *end)),
+            WeekdayRange::Nth { .. } => None,
+        })
+    }
+
+    /// Bitset of weekdays selected by this rule if nothing else than plain
+    /// weekdays is used to select when the rule applies.
+    fn weekday_only_set(&self) -> Option<u8> {
+        if self.separator != Separator::Normal
+            || self.always_open
+            || self.has_wide_range()
+            || !self.holidays.is_empty()
+        {
+            return None;
+        }
+
+        self.weekdays_set()
+    }
+
+    fn set_weekdays(&mut self, set: u8) {
+        self.weekdays = (0..7u8).filter(|day| set & (1 << day) != 0).fold(
+            Vec::new(),
+            |mut acc: Vec<WeekdayRange>, day| {
+                match acc.last_mut() {
+                    Some(WeekdayRange::Span { end, .. }) if *end + 1 == day => *end = day,
+                    _ => acc.push(WeekdayRange::Span {
+                        start: day,
+                        end: day,
+                    }),
+                }
+                acc
+            },
+        );
+    }
+
+    /// Check if two rules open at the same times of the day they apply to.
+    fn same_schedule(&self, other: &Self) -> bool {
+        self.times == other.times
+            && self.modifier == other.modifier
+            && self.comment == other.comment
+    }
+
+    fn normalize(&mut self) {
+        if let Some(set) = self.weekdays_set() {
+            self.set_weekdays(set);
+        }
+
+        for span in &mut self.times {
+            if let (TimePoint::Clock(_), Some(TimePoint::Clock(0))) = (span.start, span.end) {
+                span.end = Some(TimePoint::Clock(DAY_MINUTES));
+            }
+        }
+
+        if self.times.iter().all(TimeSpan::is_plain) {
+            self.times.sort_by_key(|span| span.start_minutes());
+            self.times =
+                std::mem::take(&mut self.times)
+                    .into_iter()
+                    .fold(Vec::new(), |mut acc, span| {
+                        match acc.last_mut() {
+                            Some(last) if span.start_minutes() <= last.end_minutes() => {
+                                if span.end_minutes() > last.end_minutes() {
+                                    last.end = span.end;
+                                }
+                            }
+                            _ => acc.push(span),
+                        }
+                        acc
+                    });
+        }
+
+        // "open" is the default modifier, it is only kept if it is the only
+        // information of the rule.
+        if self.modifier == Some(Modifier::Open) {
+            self.modifier = None;
+
+            if self.is_empty() {
+                self.modifier = Some(Modifier::Open);
+            }
+        }
+    }
+
+    fn detect_always_open(&mut self) {
+        let full_day = [TimeSpan {
+            start: TimePoint::Clock(0),
+            end: Some(TimePoint::Clock(DAY_MINUTES)),
+            open_end: false,
+            period: None,
+        }];
+
+        let full_week = self.weekdays.is_empty() || self.weekdays_set() == Some(FULL_WEEK);
+
+        if full_week
+            && !self.has_wide_range()
+            && self.holidays.is_empty()
+            && self.times == full_day
+            && self.modifier.is_none()
+            && self.comment.is_none()
+        {
+            self.always_open = true;
+            self.weekdays.clear();
+            self.times.clear();
+        }
+    }
+}
+
+impl TimeSpan {
+    /// A time span which is fully defined with clock times within a single
+    /// day.
+    fn is_plain(&self) -> bool {
+        match (self.start, self.end) {
+            (TimePoint::Clock(start), Some(TimePoint::Clock(end))) => {
+                end > start && !self.open_end && self.period.is_none()
+            }
+            _ => false,
+        }
+    }
+
+    fn start_minutes(&self) -> u16 {
+        match self.start {
+            TimePoint::Clock(minutes) => minutes,
+            TimePoint::Event { .. } => 0,
+        }
+    }
+
+    fn end_minutes(&self) -> u16 {
+        match self.end {
+            Some(TimePoint::Clock(minutes)) => minutes,
+            _ => DAY_MINUTES,
+        }
+    }
+}
+
+fn weekdays_span_set(start: u8, end: u8) -> u8 {
+    if start <= end {
+        (start..=end).fold(0, |acc, day| acc | (1 << day))
+    } else {
+        // Ranges such as "Fr-Mo" wrap around the end of the week.
+        weekdays_span_set(start, 6) | weekdays_span_set(0, end)
+    }
+}
+
+/// Merge rules that only select weekdays and share the same schedule, if no
+/// rule in between overrides one of the merged days.
+fn merge_weekday_rules(rules: Vec<Rule>) -> Vec<Rule> {
+    let mut merged: Vec<Rule> = Vec::with_capacity(rules.len());
+
+    'rules: for rule in rules {
+        if let Some(days) = rule.weekday_only_set() {
+            for candidate in merged.iter_mut().rev() {
+                let candidate_days = candidate.weekday_only_set();
+
+                match candidate_days {
+                    Some(candidate_days) if candidate.same_schedule(&rule) => {
+                        candidate.set_weekdays(candidate_days | days);
+                        continue 'rules;
+                    }
+                    Some(candidate_days) if candidate_days & days == 0 => {}
+                    _ => break,
+                }
+            }
+        }
+
+        merged.push(rule);
+    }
+
+    merged
+}
+
+// ---
+// --- Canonical output
+// ---
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+
+        if self.always_open {
+            parts.push("24/7".to_string());
+        }
+
+        if !self.years.is_empty() {
+            parts.push(self.years.iter().join(","));
+        }
+
+        if !self.monthdays.is_empty() {
+            parts.push(self.monthdays.iter().join(","));
+        }
+
+        if !self.weeks.is_empty() {
+            parts.push(format!("week {}", self.weeks.iter().join(",")));
+        }
+
+        if !self.weekdays.is_empty() || !self.holidays.is_empty() {
+            parts.push(
+                (self.weekdays.iter().map(ToString::to_string))
+                    .chain(self.holidays.iter().map(ToString::to_string))
+                    .join(","),
+            );
+        }
+
+        if !self.times.is_empty() {
+            parts.push(self.times.iter().join(","));
+        }
+
+        match self.modifier {
+            Some(Modifier::Open) => parts.push("open".to_string()),
+            Some(Modifier::Closed) => parts.push("off".to_string()),
+            Some(Modifier::Unknown) => parts.push("unknown".to_string()),
+            None => {}
+        }
+
+        if let Some(comment) = &self.comment {
+            parts.push(format!("\"{comment}\""));
+        }
+
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl fmt::Display for YearRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)?;
+
+        if let Some(end) = self.end {
+            write!(f, "-{end}")?;
+        }
+
+        if let Some(step) = self.step {
+            write!(f, "/{step}")?;
+        }
+
+        if self.open_end {
+            write!(f, "+")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for WeekRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}", self.start)?;
+
+        if let Some(end) = self.end {
+            write!(f, "-{end:02}")?;
+        }
+
+        if let Some(step) = self.step {
+            write!(f, "/{step}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Date::Fixed { month, day: None } => write!(f, "{}", MONTHS[usize::from(*month)]),
+            Date::Fixed {
+                month,
+                day: Some(day),
+            } => write!(f, "{} {day:02}", MONTHS[usize::from(*month)]),
+            Date::Easter => write!(f, "easter"),
+        }
+    }
+}
+
+impl fmt::Display for MonthdayRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)?;
+
+        match (self.start, self.end) {
+            (
+                Date::Fixed {
+                    month: start_month,
+                    day: Some(_),
+                },
+                Some(Date::Fixed {
+                    month: end_month,
+                    day: Some(end_day),
+                }),
+            ) if start_month == end_month => write!(f, "-{end_day:02}")?,
+            (_, Some(end)) => write!(f, "-{end}")?,
+            (_, None) => {}
+        }
+
+        if self.open_end {
+            write!(f, "+")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for WeekdayRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WeekdayRange::Span { start, end } if start == end => {
+                write!(f, "{}", WEEKDAYS[usize::from(*start)])
+            }
+            WeekdayRange::Span { start, end } if start + 1 == *end => write!(
+                f,
+                "{},{}",
+                WEEKDAYS[usize::from(*start)],
+                WEEKDAYS[usize::from(*end)]
+            ),
+            WeekdayRange::Span { start, end } => write!(
+                f,
+                "{}-{}",
+                WEEKDAYS[usize::from(*start)],
+                WEEKDAYS[usize::from(*end)]
+            ),
+            WeekdayRange::Nth { day, nth, offset } => {
+                let nth = nth
+                    .iter()
+                    .map(|(start, end)| match end {
+                        Some(end) => format!("{start}-{end}"),
+                        None => start.to_string(),
+                    })
+                    .join(",");
+
+                write!(f, "{}[{nth}]", WEEKDAYS[usize::from(*day)])?;
+                fmt_day_offset(f, *offset)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Holiday {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        fmt_day_offset(f, self.offset)
+    }
+}
+
+impl fmt::Display for TimePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimePoint::Clock(minutes) => write!(f, "{:02}:{:02}", minutes / 60, minutes % 60),
+            TimePoint::Event { event, offset: 0 } => write!(f, "{event}"),
+            TimePoint::Event { event, offset } => {
+                let sign = if *offset < 0 { '-' } else { '+' };
+                let offset = offset.unsigned_abs();
+                write!(f, "({event}{sign}{:02}:{:02})", offset / 60, offset % 60)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)?;
+
+        if let Some(end) = self.end {
+            write!(f, "-{end}")?;
+        }
+
+        if self.open_end {
+            write!(f, "+")?;
+        }
+
+        if let Some(period) = self.period {
+            write!(f, "/{:02}:{:02}", period / 60, period % 60)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn fmt_day_offset(f: &mut fmt::Formatter<'_>, offset: i16) -> fmt::Result {
+    match offset {
+        0 => Ok(()),
+        1 | -1 => write!(f, " {offset:+} day"),
+        _ => write!(f, " {offset:+} days"),
+    }
+}
+
+// ---
+// --- Parser
+// ---
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn error(&self, expected: &'static str) -> ParseError {
+        ParseError {
+            position: self.pos,
+            expected,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.rest().is_empty()
+    }
+
+    fn skip_ws(&mut self) {
+        self.pos = self.input.len() - self.rest().trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Consume `token` if it is a prefix of remaining input, ignoring ASCII
+    /// case.
+    fn eat(&mut self, token: &str) -> bool {
+        let rest = self.rest();
+
+        if rest.len() >= token.len()
+            && rest.is_char_boundary(token.len())
+            && rest[..token.len()].eq_ignore_ascii_case(token)
+        {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume a range separator after optional whitespaces.
+    fn eat_dash(&mut self) -> bool {
+        let save = self.pos;
+        self.skip_ws();
+
+        if self.eat("-") || self.eat("–") || self.eat("—") {
+            self.skip_ws();
+            true
+        } else {
+            self.pos = save;
+            false
+        }
+    }
+
+    /// Consume a list separator if it is followed by an item accepted by
+    /// `next_item`.
+    fn eat_list_separator(&mut self, next_item: impl Fn(&Self) -> bool) -> bool {
+        let save = self.pos;
+        self.skip_ws();
+
+        if self.eat(",") {
+            self.skip_ws();
+
+            if next_item(self) {
+                return true;
+            }
+        }
+
+        self.pos = save;
+        false
+    }
+
+    fn peek_word(&self) -> &'a str {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    fn peek_digits(&self) -> &'a str {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    fn parse_number<T: FromStr>(&mut self, max_digits: usize) -> Result<T, ParseError> {
+        let digits = self.peek_digits();
+
+        if digits.is_empty() || digits.len() > max_digits {
+            return Err(self.error("a number"));
+        }
+
+        let res = digits.parse().map_err(|_| self.error("a number"))?;
+        self.pos += digits.len();
+        Ok(res)
+    }
+
+    fn at_year(&self) -> bool {
+        self.peek_digits().len() == 4
+    }
+
+    /// Check if a time such as "9:30" starts at current position.
+    fn at_clock(&self) -> bool {
+        let digits = self.peek_digits();
+        let after = &self.rest()[digits.len()..];
+
+        (1..=2).contains(&digits.len())
+            && after.starts_with(':')
+            && after[1..].starts_with(|c: char| c.is_ascii_digit())
+    }
+
+    fn at_time(&self) -> bool {
+        self.at_clock() || self.peek() == Some('(') || event_name(self.peek_word()).is_some()
+    }
+
+    fn at_date(&self) -> bool {
+        let word = self.peek_word();
+        word.eq_ignore_ascii_case("easter") || month_index(word).is_some()
+    }
+
+    fn at_weekday_or_holiday(&self) -> bool {
+        let word = self.peek_word();
+        weekday_index(word).is_some() || holiday_kind(word).is_some()
+    }
+
+    fn parse(mut self) -> Result<Vec<Rule>, ParseError> {
+        let mut rules = Vec::new();
+        let mut separator = Separator::Normal;
+
+        loop {
+            self.skip_ws();
+
+            if self.at_end() {
+                break;
+            }
+
+            rules.push(self.parse_rule(separator)?);
+            self.skip_ws();
+
+            if self.at_end() {
+                break;
+            }
+
+            separator = {
+                if self.eat("||") {
+                    Separator::Fallback
+                } else if self.eat(";") {
+                    Separator::Normal
+                } else if self.eat(",") {
+                    Separator::Additional
+                } else {
+                    return Err(self.error("a rule separator"));
+                }
+            };
+        }
+
+        if rules.is_empty() {
+            return Err(self.error("a rule"));
+        }
+
+        Ok(rules)
+    }
+
+    fn parse_rule(&mut self, separator: Separator) -> Result<Rule, ParseError> {
+        let mut rule = Rule::new(separator);
+
+        if self.eat("24/7") {
+            rule.always_open = true;
+        } else {
+            self.parse_years(&mut rule.years)?;
+            self.parse_monthdays(&mut rule.monthdays)?;
+            self.parse_weeks(&mut rule.weeks)?;
+
+            if rule.has_wide_range() {
+                // An optional colon may separate wide ranges from the rest
+                // of the rule.
+                self.skip_ws();
+                self.eat(":");
+            }
+
+            self.parse_weekdays(&mut rule)?;
+            self.parse_times(&mut rule.times)?;
+        }
+
+        self.parse_modifier(&mut rule)?;
+
+        if rule.is_empty() {
+            return Err(self.error("a rule"));
+        }
+
+        Ok(rule)
+    }
+
+    fn parse_years(&mut self, years: &mut Vec<YearRange>) -> Result<(), ParseError> {
+        self.skip_ws();
+
+        if !self.at_year() {
+            return Ok(());
+        }
+
+        loop {
+            let mut range = YearRange {
+                start: self.parse_number(4)?,
+                end: None,
+                step: None,
+                open_end: false,
+            };
+
+            if self.eat_dash() {
+                range.end = Some(self.parse_number(4)?);
+
+                if self.eat("/") {
+                    range.step = Some(self.parse_number(3)?);
+                }
+            } else if self.eat("+") {
+                range.open_end = true;
+            }
+
+            years.push(range);
+
+            if !self.eat_list_separator(Self::at_year) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_date(&mut self) -> Result<Date, ParseError> {
+        self.skip_ws();
+        let word = self.peek_word();
+
+        if word.eq_ignore_ascii_case("easter") {
+            self.pos += word.len();
+            return Ok(Date::Easter);
+        }
+
+        let month = month_index(word).ok_or_else(|| self.error("a month"))?;
+        self.pos += word.len();
+        let save = self.pos;
+        self.skip_ws();
+
+        let day = {
+            if !self.peek_digits().is_empty() && !self.at_clock() {
+                let day: u8 = self.parse_number(2)?;
+
+                if !(1..=31).contains(&day) {
+                    return Err(self.error("a day of the month"));
+                }
+
+                Some(day)
+            } else {
+                self.pos = save;
+                None
+            }
+        };
+
+        Ok(Date::Fixed { month, day })
+    }
+
+    fn parse_monthdays(&mut self, monthdays: &mut Vec<MonthdayRange>) -> Result<(), ParseError> {
+        self.skip_ws();
+
+        if !self.at_date() {
+            return Ok(());
+        }
+
+        loop {
+            let start = self.parse_date()?;
+            let mut range = MonthdayRange {
+                start,
+                end: None,
+                open_end: false,
+            };
+
+            let save = self.pos;
+
+            if self.eat_dash() {
+                if self.at_date() {
+                    range.end = Some(self.parse_date()?);
+                } else if let Date::Fixed {
+                    month,
+                    day: Some(_),
+                } = start
+                {
+                    let day: u8 = self.parse_number(2)?;
+
+                    if !(1..=31).contains(&day) {
+                        return Err(self.error("a day of the month"));
+                    }
+
+                    range.end = Some(Date::Fixed {
+                        month,
+                        day: Some(day),
+                    });
+                } else {
+                    // The dash may introduce something else, such as a time
+                    // range: "Jan-Feb" is never followed by a day.
+                    self.pos = save;
+                }
+            } else if self.eat("+") {
+                range.open_end = true;
+            }
+
+            monthdays.push(range);
+
+            if !self.eat_list_separator(Self::at_date) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_weeks(&mut self, weeks: &mut Vec<WeekRange>) -> Result<(), ParseError> {
+        self.skip_ws();
+
+        if !self.peek_word().eq_ignore_ascii_case("week") {
+            return Ok(());
+        }
+
+        self.pos += "week".len();
+        self.skip_ws();
+
+        loop {
+            let parse_week = |parser: &mut Self| {
+                let week: u8 = parser.parse_number(2)?;
+
+                if !(1..=53).contains(&week) {
+                    return Err(parser.error("a week number"));
+                }
+
+                Ok(week)
+            };
+
+            let mut range = WeekRange {
+                start: parse_week(self)?,
+                end: None,
+                step: None,
+            };
+
+            if self.eat_dash() {
+                range.end = Some(parse_week(self)?);
+
+                if self.eat("/") {
+                    range.step = Some(self.parse_number(2)?);
+                }
+            }
+
+            weeks.push(range);
+
+            if !self.eat_list_separator(|parser| !parser.peek_digits().is_empty()) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parse an offset in days, such as "+1 day" or "-2 days".
+    fn parse_day_offset(&mut self) -> i16 {
+        let save = self.pos;
+        self.skip_ws();
+
+        let sign = {
+            if self.eat("+") {
+                1
+            } else if self.eat("-") {
+                -1
+            } else {
+                self.pos = save;
+                return 0;
+            }
+        };
+
+        let offset = match self.parse_number::<i16>(3) {
+            Ok(offset) => offset,
+            Err(_) => {
+                self.pos = save;
+                return 0;
+            }
+        };
+
+        self.skip_ws();
+
+        if !self.eat("days") && !self.eat("day") {
+            self.pos = save;
+            return 0;
+        }
+
+        sign * offset
+    }
+
+    fn parse_nth(&mut self) -> Result<Vec<(i8, Option<i8>)>, ParseError> {
+        let mut nth = Vec::new();
+
+        loop {
+            self.skip_ws();
+            let negative = self.eat("-");
+            let start: i8 = self.parse_number(1)?;
+            let start = if negative { -start } else { start };
+
+            let end: Option<i8> = {
+                if self.eat("-") {
+                    Some(self.parse_number(1)?)
+                } else {
+                    None
+                }
+            };
+
+            if !(1..=5).contains(&start.abs()) || end.is_some_and(|end| !(1..=5).contains(&end)) {
+                return Err(self.error("a weekday index between 1 and 5"));
+            }
+
+            nth.push((start, end));
+            self.skip_ws();
+
+            if self.eat("]") {
+                return Ok(nth);
+            }
+
+            if !self.eat(",") {
+                return Err(self.error("`,` or `]`"));
+            }
+        }
+    }
+
+    fn parse_weekdays(&mut self, rule: &mut Rule) -> Result<(), ParseError> {
+        self.skip_ws();
+
+        if !self.at_weekday_or_holiday() {
+            return Ok(());
+        }
+
+        loop {
+            let word = self.peek_word();
+
+            if let Some(kind) = holiday_kind(word) {
+                self.pos += word.len();
+                let offset = self.parse_day_offset();
+                rule.holidays.push(Holiday { kind, offset });
+            } else {
+                let day = weekday_index(word).ok_or_else(|| self.error("a weekday"))?;
+                self.pos += word.len();
+
+                if self.eat("[") {
+                    let nth = self.parse_nth()?;
+                    let offset = self.parse_day_offset();
+                    rule.weekdays.push(WeekdayRange::Nth { day, nth, offset });
+                } else {
+                    let save = self.pos;
+
+                    let end = {
+                        if self.eat_dash() && weekday_index(self.peek_word()).is_some() {
+                            let word = self.peek_word();
+                            self.pos += word.len();
+                            weekday_index(word).unwrap_or(day)
+                        } else {
+                            self.pos = save;
+                            day
+                        }
+                    };
+
+                    rule.weekdays.push(WeekdayRange::Span { start: day, end });
+                }
+            }
+
+            if !self.eat_list_separator(Self::at_weekday_or_holiday) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_clock(&mut self, max_hour: u16) -> Result<u16, ParseError> {
+        let hours: u16 = self.parse_number(2)?;
+
+        if !self.eat(":") {
+            return Err(self.error("`:`"));
+        }
+
+        if self.peek_digits().len() != 2 {
+            return Err(self.error("minutes"));
+        }
+
+        let minutes: u16 = self.parse_number(2)?;
+
+        if hours > max_hour || minutes >= 60 || hours * 60 + minutes > max_hour * 60 {
+            return Err(self.error("a valid time"));
+        }
+
+        Ok(hours * 60 + minutes)
+    }
+
+    fn parse_time_point(&mut self, max_hour: u16) -> Result<TimePoint, ParseError> {
+        self.skip_ws();
+
+        if self.eat("(") {
+            self.skip_ws();
+            let word = self.peek_word();
+            let event = event_name(word).ok_or_else(|| self.error("an event"))?;
+            self.pos += word.len();
+            self.skip_ws();
+
+            let sign = {
+                if self.eat("+") {
+                    1
+                } else if self.eat("-") {
+                    -1
+                } else {
+                    return Err(self.error("`+` or `-`"));
+                }
+            };
+
+            self.skip_ws();
+            let offset = sign * i16::try_from(self.parse_clock(24)?).expect("invalid offset");
+            self.skip_ws();
+
+            if !self.eat(")") {
+                return Err(self.error("`)`"));
+            }
+
+            return Ok(TimePoint::Event { event, offset });
+        }
+
+        let word = self.peek_word();
+
+        if let Some(event) = event_name(word) {
+            self.pos += word.len();
+            return Ok(TimePoint::Event { event, offset: 0 });
+        }
+
+        Ok(TimePoint::Clock(self.parse_clock(max_hour)?))
+    }
+
+    fn parse_times(&mut self, times: &mut Vec<TimeSpan>) -> Result<(), ParseError> {
+        self.skip_ws();
+
+        if !self.at_time() {
+            return Ok(());
+        }
+
+        loop {
+            let mut span = TimeSpan {
+                start: self.parse_time_point(24)?,
+                end: None,
+                open_end: false,
+                period: None,
+            };
+
+            if self.eat_dash() {
+                span.end = Some(self.parse_time_point(48)?);
+            }
+
+            if self.eat("+") {
+                span.open_end = true;
+            } else if span.end.is_some() && self.eat("/") {
+                span.period = Some({
+                    if self.at_clock() {
+                        self.parse_clock(24)?
+                    } else {
+                        self.parse_number(4)?
+                    }
+                });
+            }
+
+            times.push(span);
+
+            if !self.eat_list_separator(Self::at_time) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_modifier(&mut self, rule: &mut Rule) -> Result<(), ParseError> {
+        self.skip_ws();
+        let word = self.peek_word();
+
+        let modifier = match word.to_ascii_lowercase().as_str() {
+            "open" => Some(Modifier::Open),
+            "closed" | "off" => Some(Modifier::Closed),
+            "unknown" => Some(Modifier::Unknown),
+            _ => None,
+        };
+
+        if modifier.is_some() {
+            rule.modifier = modifier;
+            self.pos += word.len();
+            self.skip_ws();
+        }
+
+        if self.eat("\"") {
+            let len = self
+                .rest()
+                .find('"')
+                .ok_or_else(|| self.error("the end of a comment"))?;
+
+            rule.comment = Some(self.rest()[..len].to_string());
+            self.pos += len + 1;
+        }
+
+        Ok(())
+    }
+}
+
+fn weekday_index(word: &str) -> Option<u8> {
+    match word.to_ascii_lowercase().as_str() {
+        "mo" | "mon" | "monday" => Some(0),
+        "tu" | "tue" | "tues" | "tuesday" => Some(1),
+        "we" | "wed" | "wednesday" => Some(2),
+        "th" | "thu" | "thur" | "thurs" | "thursday" => Some(3),
+        "fr" | "fri" | "friday" => Some(4),
+        "sa" | "sat" | "saturday" => Some(5),
+        "su" | "sun" | "sunday" => Some(6),
+        _ => None,
+    }
+}
+
+fn month_index(word: &str) -> Option<u8> {
+    if word.len() < 3 {
+        return None;
+    }
+
+    let word = word.to_ascii_lowercase();
+
+    MONTHS_FULL_NAMES
+        .iter()
+        .position(|name| name.starts_with(&word))
+        .map(|month| month as u8)
+}
+
+fn holiday_kind(word: &str) -> Option<&'static str> {
+    ["PH", "SH"]
+        .into_iter()
+        .find(|kind| kind.eq_ignore_ascii_case(word))
+}
+
+fn event_name(word: &str) -> Option<&'static str> {
+    EVENTS
+        .into_iter()
+        .find(|event| event.eq_ignore_ascii_case(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_normalized(raw: &str, expected: &str) {
+        assert_eq!(
+            normalize(raw).as_deref(),
+            Ok(expected),
+            "normalizing {raw:?}"
+        );
+    }
+
+    #[test]
+    fn merge_repeated_weekday_rules() {
+        assert_normalized(
+            "Mo 09:00-12:00; Tu 09:00-12:00; We 09:00-12:00; Fr 09:00-12:00",
+            "Mo-We,Fr 09:00-12:00",
+        );
+        assert_normalized("Sa 08:00-12:00; Su 08:00-12:00", "Sa,Su 08:00-12:00");
+        assert_normalized("Mo-Fr 10:00-12:00, 09:00-11:00", "Mo-Fr 09:00-12:00");
+    }
+
+    #[test]
+    fn keep_rules_overridden_in_between() {
+        // Merging the first and last rules would change which one wins on
+        // mondays.
+        assert_normalized(
+            "Mo 10:00-12:00; Tu 09:00-12:00; Mo 09:00-12:00",
+            "Mo 10:00-12:00; Mo,Tu 09:00-12:00",
+        );
+        assert_normalized(
+            "Mo-Fr 08:00-12:00; We off; Th 08:00-12:00",
+            "Mo-Fr 08:00-12:00; We off",
+        );
+    }
+
+    #[test]
+    fn ranges_crossing_midnight() {
+        assert_normalized("Fr-Sa 22:00-02:00", "Fr,Sa 22:00-02:00");
+        assert_normalized("Mo 22:00-26:00", "Mo 22:00-26:00");
+        assert_normalized("Mo-Su 18:00-00:00", "Mo-Su 18:00-24:00");
+        assert_normalized("Monday-Sunday 00:00-24:00", "24/7");
+    }
+
+    #[test]
+    fn public_holidays() {
+        assert_normalized("PH closed", "PH off");
+        assert_normalized("Mo-Fr 09:00-18:00; PH off", "Mo-Fr 09:00-18:00; PH off");
+        assert_normalized(
+            "Mo-Fr 09:00-18:00; PH 10:00-12:00",
+            "Mo-Fr 09:00-18:00; PH 10:00-12:00",
+        );
+    }
+
+    #[test]
+    fn date_ranges_across_year_boundary() {
+        assert_normalized("Dec 24-Jan 02 off", "Dec 24-Jan 02 off");
+        assert_normalized("Dec-Feb Mo-Fr 09:00-12:00", "Dec-Feb Mo-Fr 09:00-12:00");
+        assert_normalized("week 52-02 Sa 10:00-12:00", "week 52-02 Sa 10:00-12:00");
+    }
+
+    #[test]
+    fn invalid_values() {
+        for raw in [
+            "",
+            "Mo-Fr 25:00-18:00",
+            "Mo-Fr 09:00-",
+            "Mo-Fr 9h-18h",
+            "Jan 32 off",
+            "Mo-Fr 09:00-12:00 \"unterminated",
+        ] {
+            assert!(normalize(raw).is_err(), "{raw:?} should be invalid");
+        }
+    }
+}
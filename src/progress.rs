@@ -0,0 +1,297 @@
+//! Periodic reports of the progress of an import. The first stage of the
+//! pipeline is compared to the expected total to estimate the remaining time,
+//! while the throughput of each stage tells where the bottleneck is.
+
+use std::fmt::Write;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::metrics::Counter;
+
+struct Stage {
+    name: &'static str,
+    unit: &'static str,
+    count: Box<dyn Fn() -> u64 + Send + Sync>,
+}
+
+/// Stages of a pipeline for which progress is reported.
+pub struct Progress {
+    total: Option<u64>,
+    stages: Vec<Stage>,
+}
+
+impl Progress {
+    /// Track progress towards an expected total, which is expressed in the
+    /// unit of the first stage.
+    pub fn new(total: Option<u64>) -> Self {
+        Self {
+            total,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Add a stage of the pipeline, its count must never decrease.
+    pub fn stage(
+        mut self,
+        name: &'static str,
+        unit: &'static str,
+        count: impl Fn() -> u64 + Send + Sync + 'static,
+    ) -> Self {
+        self.stages.push(Stage {
+            name,
+            unit,
+            count: Box::new(count),
+        });
+
+        self
+    }
+
+    /// Report progress every `interval` until the returned handle is
+    /// finished.
+    pub fn start(self, interval: Duration) -> ProgressHandle {
+        let progress = Arc::new(self);
+        let start = Instant::now();
+
+        let task = tokio::spawn({
+            let progress = progress.clone();
+
+            async move {
+                let mut last_report = start;
+                let mut last_counts = progress.counts();
+
+                loop {
+                    tokio::time::sleep(interval).await;
+                    let counts = progress.counts();
+                    progress.report(start, last_report, &counts, &last_counts);
+                    last_report = Instant::now();
+                    last_counts = counts;
+                }
+            }
+        });
+
+        ProgressHandle {
+            progress,
+            start,
+            task,
+        }
+    }
+
+    fn counts(&self) -> Vec<u64> {
+        self.stages.iter().map(|stage| (stage.count)()).collect()
+    }
+
+    fn report(&self, start: Instant, last_report: Instant, counts: &[u64], last_counts: &[u64]) {
+        let (elapsed, interval) = (start.elapsed(), last_report.elapsed());
+        let msg = self.format_report(elapsed, interval, counts, last_counts);
+        info!("progress: {msg}");
+    }
+
+    /// Describe progress after `elapsed` since start, throughputs are
+    /// computed over the `interval` since the previous report.
+    fn format_report(
+        &self,
+        elapsed: Duration,
+        interval: Duration,
+        counts: &[u64],
+        last_counts: &[u64],
+    ) -> String {
+        let mut msg = String::new();
+
+        if let (Some(total), Some(stage), Some(&done)) =
+            (self.total, self.stages.first(), counts.first())
+        {
+            let percent = 100. * done as f64 / total.max(1) as f64;
+            write!(msg, "{percent:.1}% ({done}/{total} {})", stage.unit).unwrap();
+
+            if let Some(eta) = estimate_eta(elapsed, done, total) {
+                write!(msg, ", ETA {}", format_duration(eta)).unwrap();
+            }
+        }
+
+        for ((stage, count), last_count) in self.stages.iter().zip(counts).zip(last_counts) {
+            let (name, unit) = (stage.name, stage.unit);
+            let rate = throughput(count.saturating_sub(*last_count), interval);
+
+            if !msg.is_empty() {
+                msg.push_str(" | ");
+            }
+
+            write!(msg, "{name}: {count} {unit} ({rate:.0} {unit}/s)").unwrap();
+        }
+
+        msg
+    }
+
+    /// Describe the average throughput of each stage over the whole import.
+    fn format_summary(&self, elapsed: Duration, counts: &[u64]) -> String {
+        let mut msg = format!("finished in {}", format_duration(elapsed));
+
+        for (stage, count) in self.stages.iter().zip(counts) {
+            let (name, unit) = (stage.name, stage.unit);
+            let rate = throughput(*count, elapsed);
+            write!(msg, " | {name}: {count} {unit} ({rate:.0} {unit}/s)").unwrap();
+        }
+
+        msg
+    }
+}
+
+/// Estimate the remaining time assuming that the throughput observed since
+/// start stays the same, nothing can be estimated until something is done.
+fn estimate_eta(elapsed: Duration, done: u64, total: u64) -> Option<Duration> {
+    if done == 0 {
+        return None;
+    }
+
+    let remaining = total.saturating_sub(done) as f64 / done as f64;
+    Some(elapsed.mul_f64(remaining))
+}
+
+/// Number of items processed per second.
+fn throughput(count: u64, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        return 0.;
+    }
+
+    count as f64 / duration.as_secs_f64()
+}
+
+/// Handle on the task reporting progress.
+pub struct ProgressHandle {
+    progress: Arc<Progress>,
+    start: Instant,
+    task: JoinHandle<()>,
+}
+
+impl ProgressHandle {
+    /// Stop reporting progress and log the average throughput of each stage.
+    pub fn finish(self) {
+        self.task.abort();
+        let counts = self.progress.counts();
+        info!(
+            "{}",
+            self.progress.format_summary(self.start.elapsed(), &counts)
+        );
+    }
+}
+
+/// Format a duration as hours, minutes and seconds, eg. `1h02m03s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    match (hours, mins) {
+        (0, 0) => format!("{secs}s"),
+        (0, _) => format!("{mins}m{secs:02}s"),
+        _ => format!("{hours}h{mins:02}m{secs:02}s"),
+    }
+}
+
+/// Reader which adds the number of bytes it reads to a counter, this can be
+/// used to track progress through a compressed file.
+pub struct CountingReader<R> {
+    inner: R,
+    counter: &'static Counter,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, counter: &'static Counter) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.counter.add((buf.filled().len() - before) as u64);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(total: Option<u64>) -> Progress {
+        Progress::new(total)
+            .stage("fetch", "rows", || 0)
+            .stage("locate", "POIs", || 0)
+    }
+
+    #[test]
+    fn eta_assumes_a_constant_throughput() {
+        let elapsed = Duration::from_secs(10);
+        assert_eq!(estimate_eta(elapsed, 0, 100), None);
+        assert_eq!(
+            estimate_eta(elapsed, 25, 100),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(estimate_eta(elapsed, 100, 100), Some(Duration::ZERO));
+
+        // The total may be underestimated
+        assert_eq!(estimate_eta(elapsed, 120, 100), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn throughput_is_per_second() {
+        assert_eq!(throughput(300, Duration::from_secs(2)), 150.);
+        assert_eq!(throughput(1, Duration::from_millis(100)), 10.);
+        assert_eq!(throughput(300, Duration::ZERO), 0.);
+    }
+
+    #[test]
+    fn durations_are_formatted_with_their_largest_unit() {
+        assert_eq!(format_duration(Duration::from_millis(59_900)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(61)), "1m01s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+        assert_eq!(format_duration(Duration::from_secs(90_000)), "25h00m00s");
+    }
+
+    #[test]
+    fn report_compares_first_stage_to_total() {
+        let msg = progress(Some(100)).format_report(
+            Duration::from_secs(10),
+            Duration::from_secs(2),
+            &[25, 20],
+            &[15, 10],
+        );
+
+        assert_eq!(
+            msg,
+            "25.0% (25/100 rows), ETA 30s | fetch: 25 rows (5 rows/s) | locate: 20 POIs (5 POIs/s)"
+        );
+    }
+
+    #[test]
+    fn report_without_total_only_has_throughputs() {
+        let msg = progress(None).format_report(
+            Duration::from_secs(10),
+            Duration::from_secs(2),
+            &[0, 0],
+            &[0, 0],
+        );
+
+        assert_eq!(msg, "fetch: 0 rows (0 rows/s) | locate: 0 POIs (0 POIs/s)");
+    }
+
+    #[test]
+    fn summary_has_average_throughputs() {
+        let msg = progress(Some(100)).format_summary(Duration::from_secs(50), &[100, 80]);
+
+        assert_eq!(
+            msg,
+            "finished in 50s | fetch: 100 rows (2 rows/s) | locate: 80 POIs (2 POIs/s)"
+        );
+    }
+}
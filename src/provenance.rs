@@ -0,0 +1,285 @@
+//! Provenance of indexed documents: which source and record they were built
+//! from, by which run of which version of fafnir and how their address was
+//! found. It is added as properties of documents, right before they are sent
+//! to elasticsearch.
+
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::stream::{Stream, StreamExt};
+use places::poi::Poi;
+
+use crate::settings::ProvenanceSettings;
+use crate::utils::format_timestamp;
+
+/// Prefix of the properties holding provenance.
+pub const PROVENANCE_PREFIX: &str = "fafnir:";
+
+/// Provenance properties, without their prefix.
+pub const PROVENANCE_FIELDS: &[&str] = &[
+    "source",
+    "source_id",
+    "run_id",
+    "version",
+    "input",
+    "imported_at",
+    "address_method",
+];
+
+/// How the address of a POI was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressMethod {
+    /// Read from the source: tags of the POI or of the polygon it is located
+    /// in, or the address given by TripAdvisor.
+    Tag,
+    /// Searched for around the POI.
+    Reverse,
+    /// Kept from a previous import as inputs of the reverse didn't change.
+    Reused,
+}
+
+impl AddressMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tag => "tag",
+            Self::Reverse => "reverse",
+            Self::Reused => "reused",
+        }
+    }
+}
+
+/// A document produced by an import.
+pub trait Record {
+    /// Identifier of the record the document was built from, in its source.
+    fn source_id(&self) -> String;
+
+    /// How the address of the document was found, if it has one.
+    fn address_method(&self) -> Option<AddressMethod>;
+
+    fn poi_mut(&mut self) -> &mut Poi;
+}
+
+/// Provenance shared by all documents of a run.
+#[derive(Clone, Debug)]
+pub struct Provenance {
+    source: &'static str,
+    run_id: String,
+    input: Option<String>,
+    imported_at: String,
+    exclude: Vec<String>,
+}
+
+impl Provenance {
+    /// Provenance of documents built from `source`, or `None` if it is
+    /// disabled. The input is a file or a snapshot of a database.
+    pub fn new(
+        settings: &ProvenanceSettings,
+        source: &'static str,
+        input: Option<String>,
+    ) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before UNIX epoch");
+
+        // Runs started within the same second are told apart by their PID
+        let run_id = (settings.run_id.clone())
+            .unwrap_or_else(|| format!("{}-{}", now.as_secs(), process::id()));
+
+        Some(Self {
+            source,
+            run_id,
+            input,
+            imported_at: format_timestamp(now.as_millis() as u64),
+            exclude: settings.exclude.clone(),
+        })
+    }
+
+    /// Add provenance properties to a document. Properties that are unknown
+    /// for this document are left untouched.
+    pub fn stamp(&self, poi: &mut Poi, source_id: String, address_method: Option<AddressMethod>) {
+        let address_method = address_method.map(|method| method.as_str().to_string());
+
+        let values = [
+            ("source", Some(self.source.to_string())),
+            ("source_id", Some(source_id)),
+            ("run_id", Some(self.run_id.clone())),
+            ("version", Some(env!("CARGO_PKG_VERSION").to_string())),
+            ("input", self.input.clone()),
+            ("imported_at", Some(self.imported_at.clone())),
+            ("address_method", address_method),
+        ];
+
+        for (field, value) in values {
+            if self.exclude.iter().any(|excluded| excluded == field) {
+                continue;
+            }
+
+            if let Some(value) = value {
+                poi.properties
+                    .insert(format!("{PROVENANCE_PREFIX}{field}"), value);
+            }
+        }
+    }
+}
+
+/// Add provenance properties to all documents of the output stream of an
+/// import, this is a no-op if provenance is disabled.
+pub fn with_provenance<T: Record>(
+    records: impl Stream<Item = T>,
+    provenance: Option<Provenance>,
+) -> impl Stream<Item = T> {
+    records.map(move |mut record| {
+        if let Some(provenance) = &provenance {
+            let source_id = record.source_id();
+            let address_method = record.address_method();
+            provenance.stamp(record.poi_mut(), source_id, address_method);
+        }
+
+        record
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    struct Doc {
+        id: &'static str,
+        address_method: Option<AddressMethod>,
+        poi: Poi,
+    }
+
+    impl Record for Doc {
+        fn source_id(&self) -> String {
+            self.id.to_string()
+        }
+
+        fn address_method(&self) -> Option<AddressMethod> {
+            self.address_method
+        }
+
+        fn poi_mut(&mut self) -> &mut Poi {
+            &mut self.poi
+        }
+    }
+
+    fn settings(run_id: Option<&str>, exclude: &[&str]) -> ProvenanceSettings {
+        ProvenanceSettings {
+            enabled: true,
+            run_id: run_id.map(str::to_string),
+            snapshot: None,
+            exclude: exclude.iter().map(|field| field.to_string()).collect(),
+        }
+    }
+
+    fn stamped(provenance: &Provenance, poi: &mut Poi) {
+        provenance.stamp(poi, "osm:node:1".to_string(), Some(AddressMethod::Reverse));
+    }
+
+    #[test]
+    fn all_fields_are_stamped() {
+        let provenance = Provenance::new(
+            &settings(Some("run-1"), &[]),
+            "osm",
+            Some("snapshot-1".to_string()),
+        )
+        .unwrap();
+
+        let mut poi = Poi::default();
+        stamped(&provenance, &mut poi);
+        let props = &poi.properties;
+        assert_eq!(props.len(), PROVENANCE_FIELDS.len());
+        assert_eq!(props["fafnir:source"], "osm");
+        assert_eq!(props["fafnir:source_id"], "osm:node:1");
+        assert_eq!(props["fafnir:run_id"], "run-1");
+        assert_eq!(props["fafnir:version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(props["fafnir:input"], "snapshot-1");
+        assert_eq!(props["fafnir:address_method"], "reverse");
+        assert!(props["fafnir:imported_at"].ends_with(" UTC"));
+    }
+
+    #[test]
+    fn excluded_fields_are_not_stamped() {
+        let provenance = Provenance::new(
+            &settings(Some("run-1"), &["version", "imported_at"]),
+            "osm",
+            None,
+        )
+        .unwrap();
+
+        let mut poi = Poi::default();
+        stamped(&provenance, &mut poi);
+        assert!(!poi.properties.contains_key("fafnir:version"));
+        assert!(!poi.properties.contains_key("fafnir:imported_at"));
+        assert_eq!(poi.properties["fafnir:run_id"], "run-1");
+    }
+
+    #[test]
+    fn missing_values_keep_existing_properties() {
+        let provenance = Provenance::new(&settings(None, &[]), "osm", None).unwrap();
+        let mut poi = Poi::default();
+
+        for (key, value) in [
+            ("fafnir:input", "previous"),
+            ("fafnir:address_method", "tag"),
+        ] {
+            poi.properties.insert(key.to_string(), value.to_string());
+        }
+
+        provenance.stamp(&mut poi, "osm:node:1".to_string(), None);
+        assert_eq!(poi.properties["fafnir:input"], "previous");
+        assert_eq!(poi.properties["fafnir:address_method"], "tag");
+        assert_eq!(poi.properties["fafnir:source_id"], "osm:node:1");
+    }
+
+    #[test]
+    fn run_id_is_generated_if_not_set() {
+        let provenance = Provenance::new(&settings(None, &[]), "osm", None).unwrap();
+        let mut poi = Poi::default();
+        stamped(&provenance, &mut poi);
+
+        let run_id = &poi.properties["fafnir:run_id"];
+        let (secs, pid) = run_id.split_once('-').unwrap();
+        assert!(secs.parse::<u64>().is_ok());
+        assert_eq!(pid, process::id().to_string());
+    }
+
+    #[tokio::test]
+    async fn records_of_a_stream_are_stamped() {
+        let docs = || {
+            let doc = |id, address_method| Doc {
+                id,
+                address_method,
+                poi: Poi::default(),
+            };
+
+            stream::iter([doc("1", Some(AddressMethod::Tag)), doc("2", None)])
+        };
+
+        let provenance = Provenance::new(&settings(Some("run-1"), &[]), "tripadvisor", None);
+        let stamped: Vec<_> = with_provenance(docs(), provenance).collect().await;
+        assert_eq!(stamped[0].poi.properties["fafnir:source_id"], "1");
+        assert_eq!(stamped[0].poi.properties["fafnir:address_method"], "tag");
+        assert_eq!(stamped[1].poi.properties["fafnir:source_id"], "2");
+        assert!(!stamped[1]
+            .poi
+            .properties
+            .contains_key("fafnir:address_method"));
+
+        // Documents are left untouched if provenance is disabled
+        let disabled = ProvenanceSettings {
+            enabled: false,
+            ..settings(None, &[])
+        };
+
+        let provenance = Provenance::new(&disabled, "tripadvisor", None);
+        assert!(provenance.is_none());
+        let untouched: Vec<_> = with_provenance(docs(), provenance).collect().await;
+        assert!(untouched.iter().all(|doc| doc.poi.properties.is_empty()));
+    }
+}
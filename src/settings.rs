@@ -1,19 +1,268 @@
 //! Shared settings structs.
 
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use mimir::adapters::secondary::elasticsearch::ElasticsearchStorageConfig;
 use serde::Deserialize;
+use url::Url;
+
+use crate::addr_index::Distance;
 
 #[derive(Debug, Deserialize)]
 pub struct FafnirSettings {
+    /// Only import POIs inside of this box, with format [lon1, lat1, lon2, lat2].
     pub bounding_box: Option<[f64; 4]>,
+    #[serde(default)]
+    pub area: AreaSettings,
     pub langs: Vec<String>,
     pub skip_reverse: bool,
+    /// Search addresses while previous documents are fetched.
+    #[serde(default)]
+    pub speculative_reverse: bool,
+    /// Keep addresses of unchanged POIs even if addresses have been updated.
+    pub skip_reverse_on_addr_update: bool,
+    /// Keep the whole document of unchanged POIs.
+    pub skip_unchanged_pois: bool,
+    pub inherit_address_from: Vec<String>,
     #[serde(default = "num_cpus::get")]
     pub concurrent_blocks: usize,
+    pub scan_partitions: usize,
     pub max_query_batch_size: usize,
+    pub query_cache_size: usize,
     pub log_indexed_count_interval: usize,
+    pub reverse: ReverseSettings,
+    pub address_index: AddressIndexSettings,
+    pub throttle: ThrottleSettings,
+    #[serde(default)]
+    pub routes: Vec<RouteSettings>,
+}
+
+/// Area the import is restricted to, it is the union of all polygons defined
+/// by these fields.
+#[derive(Debug, Default, Deserialize)]
+pub struct AreaSettings {
+    /// Path to a GeoJSON file containing polygons or multipolygons.
+    #[serde(default)]
+    pub geojson: Option<PathBuf>,
+    /// Ids of admins, as indexed by mimir.
+    #[serde(default)]
+    pub admins: Vec<String>,
+    /// Country codes.
+    #[serde(default)]
+    pub countries: Vec<String>,
+}
+
+impl AreaSettings {
+    /// Check if the import is not restricted.
+    pub fn is_empty(&self) -> bool {
+        self.geojson.is_none() && self.admins.is_empty() && self.countries.is_empty()
+    }
+}
+
+/// Rule sending POIs to one of the additional containers. A POI matches the
+/// rule if it matches all criteria that are not empty.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RouteSettings {
+    /// Name of the container, as defined in the `containers` section.
+    pub container: String,
+    #[serde(default)]
+    pub classes: Vec<String>,
+    #[serde(default)]
+    pub subclasses: Vec<String>,
+    /// Tags that the POI must have, a value of "*" matches any value.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    #[serde(default)]
+    pub countries: Vec<String>,
+    /// Only match POIs that are searchable, or not.
+    #[serde(default)]
+    pub searchable: Option<bool>,
+}
+
+/// Max distances used to search for the address of a POI when it can't be
+/// read from its tags.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReverseDistances {
+    /// Max distance to the closest address.
+    pub addr_distance: Distance,
+    /// Max distance to the closest street, which is used if no address was
+    /// found.
+    pub street_distance: Distance,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReverseSettings {
+    pub default: ReverseDistances,
+    /// Distances overriding the default for some classes of POIs.
+    #[serde(default)]
+    pub classes: HashMap<String, ReverseDistances>,
+}
+
+impl ReverseSettings {
+    /// Iterate over all distances, including overrides.
+    pub fn iter(&self) -> impl Iterator<Item = &ReverseDistances> {
+        std::iter::once(&self.default).chain(self.classes.values())
+    }
+
+    /// Get max distances used for POIs of input class.
+    pub fn distances(&self, poi_class: Option<&str>) -> &ReverseDistances {
+        poi_class
+            .and_then(|class| self.classes.get(class))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddressIndexSettings {
+    /// Perform reverse queries on addresses loaded in memory.
+    pub enabled: bool,
+    /// Max number of addresses loaded in memory at once.
+    pub max_addresses: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThrottleSettings {
+    /// Adapt the size of batches and the number of batches sent concurrently
+    /// to the response time and errors of elasticsearch.
+    pub adaptive: bool,
+    /// Response time in milliseconds above which batches are reduced.
+    pub target_latency_ms: u64,
+    /// Batches won't be reduced below this number of queries.
+    pub min_batch_size: usize,
+    /// Max number of queries sent to elasticsearch per second, 0 means there
+    /// is no limit.
+    pub max_queries_per_second: u32,
+}
+
+/// Sources of admins other than elasticsearch.
+#[derive(Debug, Default, Deserialize)]
+pub struct AdminsSettings {
+    /// Local snapshot of admins, it is used instead of fetching admins from
+    /// elasticsearch if the admin index didn't change since it was written.
+    #[serde(default)]
+    pub snapshot: Option<PathBuf>,
+    /// Build admins from a cosmogony file instead of elasticsearch.
+    #[serde(default)]
+    pub cosmogony: Option<PathBuf>,
+    /// Languages of the names and labels of admins built from cosmogony.
+    #[serde(default)]
+    pub langs: Vec<String>,
+}
+
+/// Exposition of the metrics of an import.
+#[derive(Debug, Deserialize)]
+pub struct MetricsSettings {
+    /// Address of a local HTTP endpoint serving metrics.
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+    /// Path of a file metrics are written to, for node-exporter's textfile
+    /// collector.
+    #[serde(default)]
+    pub textfile: Option<PathBuf>,
+    /// Interval between two updates of the textfile.
+    pub textfile_interval_secs: u64,
+}
+
+/// Periodic reports of the progress of an import.
+#[derive(Debug, Deserialize)]
+pub struct ProgressSettings {
+    /// Count rows to import from postgres before fetching them, so that the
+    /// remaining time can be estimated.
+    pub count_rows: bool,
+    /// Interval between two reports of progress.
+    pub interval_secs: u64,
+}
+
+/// Provenance properties added to indexed documents, with the `fafnir:`
+/// prefix.
+#[derive(Debug, Deserialize)]
+pub struct ProvenanceSettings {
+    pub enabled: bool,
+    /// Identifier of the run, a unique one is generated if it is not set.
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// Identifier of the snapshot of the postgres database POIs are imported
+    /// from, defaults to the name of the database.
+    #[serde(default)]
+    pub snapshot: Option<String>,
+    /// Provenance properties that are not added, without their prefix.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetentionSettings {
+    /// Number of unpublished indexes kept for each container when old
+    /// indexes are pruned, these are the indexes available for a rollback.
+    pub keep_previous: usize,
+}
+
+/// Connection to elasticsearch, on top of the configuration used by mimir.
+#[derive(Debug, Deserialize)]
+pub struct ElasticsearchSettings {
+    #[serde(flatten)]
+    pub storage: ElasticsearchStorageConfig,
+    /// Other nodes of the cluster, requests are balanced between `url` and
+    /// these nodes.
+    #[serde(default)]
+    pub nodes: Vec<Url>,
+    #[serde(default)]
+    pub auth: Option<ElasticsearchAuth>,
+    /// Path to the PEM certificate of the authority used to validate the
+    /// certificates of the nodes.
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ElasticsearchAuth {
+    Basic { username: String, password: String },
+    ApiKey { id: String, api_key: String },
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PostgresSettings {
     pub url: String,
+    /// Max number of connections opened to postgres.
+    pub pool_size: usize,
+    pub tls: PostgresTlsSettings,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostgresTlsSettings {
+    /// Whether TLS is used and how the server is authenticated, it overrides
+    /// the `sslmode` of the connection string.
+    pub sslmode: PostgresSslMode,
+    /// Path to the PEM certificate of the authority used to validate the
+    /// certificate of the server.
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+    /// Path to the PEM certificate used to authenticate the client.
+    #[serde(default)]
+    pub client_certificate: Option<PathBuf>,
+    /// Path to the PEM private key (PKCS #8) of the client certificate.
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+}
+
+/// TLS modes of connections to postgres, they have the same meaning as in
+/// libpq.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostgresSslMode {
+    /// Never use TLS.
+    Disable,
+    /// Use TLS if the server supports it, without validating its certificate.
+    Prefer,
+    /// Always use TLS, without validating the certificate of the server.
+    Require,
+    /// Always use TLS and check that the certificate of the server is signed
+    /// by a trusted authority.
+    VerifyCa,
+    /// Same as `VerifyCa`, and also check that the certificate matches the
+    /// hostname of the server.
+    VerifyFull,
 }
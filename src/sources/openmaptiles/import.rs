@@ -0,0 +1,251 @@
+//! Import POIs from a postgres database into mimir's elasticsearch.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::try_join_all;
+use futures::stream::StreamExt;
+use futures::{try_join, FutureExt};
+use mimir::adapters::secondary::elasticsearch::ElasticsearchStorage;
+use mimir::domain::model::configuration::ContainerConfig;
+use mimir::domain::ports::primary::generate_index::GenerateIndex;
+use serde::Deserialize;
+use tokio::sync::mpsc::channel;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::info;
+use tracing::info_span;
+use tracing_futures::Instrument;
+
+use super::pois::Reuse;
+use super::routing::{route, DEFAULT_NOSEARCH_CONTAINER, DEFAULT_SEARCH_CONTAINER};
+use super::{count_pois, fetch_and_locate_pois, PoiLocator};
+use crate::admins::load_admins;
+use crate::area::Area;
+use crate::cli::exit_with_problems;
+use crate::indexes::container_alias;
+use crate::metrics::{self, METRICS};
+use crate::mimir::{address_updated_after_pois, admin_geofinder_from};
+use crate::progress::Progress;
+use crate::provenance::{with_provenance, Provenance};
+use crate::settings::{
+    AdminsSettings, ElasticsearchSettings, FafnirSettings, MetricsSettings, PostgresSettings,
+    ProgressSettings, ProvenanceSettings,
+};
+use crate::utils::{start_es_client, start_postgres_pool};
+use crate::validation::{join_key, validate_fields, Problem, Validate};
+
+// Size of the buffers of POIs that have to be indexed.
+const CHANNEL_SIZE: usize = 10_000;
+
+/// Settings of an import from postgres.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportSettings {
+    pub fafnir: FafnirSettings,
+    pub postgres: PostgresSettings,
+    pub elasticsearch: ElasticsearchSettings,
+    #[serde(default)]
+    pub admins: AdminsSettings,
+    pub metrics: MetricsSettings,
+    pub progress: ProgressSettings,
+    pub provenance: ProvenanceSettings,
+    pub container_search: ContainerConfig,
+    pub container_nosearch: ContainerConfig,
+    /// Additional containers, POIs are sent to them through `fafnir.routes`.
+    #[serde(default)]
+    pub containers: BTreeMap<String, ContainerConfig>,
+}
+
+impl Validate for ImportSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        validate_fields(
+            key,
+            &[
+                ("fafnir", &self.fafnir),
+                ("postgres", &self.postgres),
+                ("elasticsearch", &self.elasticsearch),
+                ("admins", &self.admins),
+                ("metrics", &self.metrics),
+                ("progress", &self.progress),
+                ("provenance", &self.provenance),
+                ("container-search", &self.container_search),
+                ("container-nosearch", &self.container_nosearch),
+            ],
+            problems,
+        );
+
+        let containers_key = join_key(key, "containers");
+        self.containers.validate(&containers_key, problems);
+
+        for name in [DEFAULT_SEARCH_CONTAINER, DEFAULT_NOSEARCH_CONTAINER] {
+            if self.containers.contains_key(name) {
+                problems.push(Problem::new(
+                    join_key(&containers_key, name),
+                    format!("`{name}` is the name of a default container"),
+                ));
+            }
+        }
+
+        for (i, route) in self.fafnir.routes.iter().enumerate() {
+            let is_known = [DEFAULT_SEARCH_CONTAINER, DEFAULT_NOSEARCH_CONTAINER]
+                .contains(&route.container.as_str())
+                || self.containers.contains_key(&route.container);
+
+            if !is_known {
+                problems.push(Problem::new(
+                    join_key(key, format!("fafnir.routes[{i}].container")),
+                    format!("unknown container `{}`", route.container),
+                ));
+            }
+        }
+    }
+}
+
+/// Fetch POIs from postgres, search for their admins and addresses and index
+/// them into the containers they are routed to.
+pub async fn import_pois(settings: ImportSettings) {
+    metrics::start(&settings.metrics);
+
+    // Elasticsearch client shared by all requests, including indexation
+    let es = start_es_client(&settings.elasticsearch);
+
+    let mimir_es = ElasticsearchStorage {
+        client: es.clone(),
+        config: settings.elasticsearch.storage,
+    };
+
+    // If addresses have not changed since last update of POIs, it is not
+    // necessary to perform a reverse again for POIs that didn't change.
+    let addr_updated = address_updated_after_pois(&es).await;
+    let try_skip_reverse = settings.fafnir.skip_reverse
+        && (settings.fafnir.skip_reverse_on_addr_update || !addr_updated);
+
+    let reuse = match (try_skip_reverse, settings.fafnir.skip_unchanged_pois) {
+        (false, _) => Reuse::Nothing,
+        (true, false) => Reuse::Address,
+        (true, true) => Reuse::Document,
+    };
+
+    if try_skip_reverse {
+        info!("reverse on unchanged POIs won't be performed (reuse: {reuse:?})");
+    }
+
+    // Fetch admins
+    let admins = load_admins(&es, &mimir_es, &settings.admins).await;
+    let area = Area::from_settings(&settings.fafnir.area, "fafnir.area", &admins)
+        .unwrap_or_else(|problems| exit_with_problems(&problems));
+    let admins_geofinder = admin_geofinder_from(admins);
+
+    // Spawn tasks that will build indexes. These tasks will provide a single
+    // stream to mimirsbrunn which is built from data sent into async channels.
+    let containers = [
+        (DEFAULT_SEARCH_CONTAINER, &settings.container_search),
+        (DEFAULT_NOSEARCH_CONTAINER, &settings.container_nosearch),
+    ]
+    .into_iter()
+    .chain((settings.containers.iter()).map(|(name, container)| (name.as_str(), container)));
+
+    let mut poi_channels = HashMap::new();
+    let mut poi_indexes = HashMap::new();
+    let mut index_tasks = Vec::new();
+
+    for (name, container) in containers {
+        let (send, recv) = channel(CHANNEL_SIZE);
+        poi_indexes.insert(name.to_string(), container_alias(container));
+
+        let task = mimir_es
+            .generate_index(container, ReceiverStream::new(recv))
+            .map(move |res| res.map(|index| (name, index)).map_err(Into::into));
+
+        if poi_channels.insert(name, send).is_some() {
+            panic!("container `{name}` is defined twice");
+        }
+
+        index_tasks.push(task);
+    }
+
+    let poi_channels = Arc::new(poi_channels);
+
+    // Build POIs and send them to indexing tasks
+    let mut total_nb_pois: usize = 0;
+    let mut count_invalid_values: HashMap<&str, u64> = HashMap::new();
+
+    let pg_pool = start_postgres_pool(&settings.postgres);
+
+    let total_rows = {
+        if settings.progress.count_rows {
+            let total = count_pois(&pg_pool, area.as_ref(), &settings.fafnir).await;
+            info!("{total} rows will be fetched from postgres");
+            Some(total)
+        } else {
+            None
+        }
+    };
+
+    let progress = Progress::new(total_rows)
+        .stage("fetch", "rows", || METRICS.rows_read.get())
+        .stage("locate", "POIs", || METRICS.pois_located.get())
+        .stage("index", "docs", || METRICS.container_documents.sum())
+        .start(Duration::from_secs(settings.progress.interval_secs));
+
+    let locator = PoiLocator::new(es, admins_geofinder, poi_indexes, reuse, &settings.fafnir);
+
+    // The snapshot defaults to the name of the database POIs are read from
+    let snapshot = (settings.provenance.snapshot.clone()).or_else(|| {
+        let pg_config: tokio_postgres::Config = settings.postgres.url.parse().ok()?;
+        pg_config.get_dbname().map(str::to_string)
+    });
+
+    let provenance = Provenance::new(&settings.provenance, "osm", snapshot);
+
+    let fetch_pois_task = {
+        let pois = fetch_and_locate_pois(&pg_pool, locator, area.as_ref(), &settings.fafnir).await;
+
+        with_provenance(pois, provenance)
+            .instrument(info_span!("fetch POIs"))
+            .inspect(|p| {
+                for key in &p.invalid_values {
+                    METRICS.errors.get(&format!("invalid_{key}")).inc();
+                    *count_invalid_values.entry(*key).or_insert(0) += 1;
+                }
+
+                // Log advancement
+                total_nb_pois += 1;
+
+                if total_nb_pois % settings.fafnir.log_indexed_count_interval == 0 {
+                    info!("Number of indexed POIs: {total_nb_pois}")
+                }
+            })
+            .for_each(|p| {
+                let poi_channels = poi_channels.clone();
+                let container = route(&settings.fafnir.routes, &p);
+
+                async move {
+                    let channel = &poi_channels[container];
+
+                    channel.send(p.poi).await.unwrap_or_else(|_| {
+                        panic!("failed to send POI into channel of {container}")
+                    });
+
+                    METRICS.container_documents.get(container).inc();
+                    (METRICS.channel_fill.get(container))
+                        .set((CHANNEL_SIZE - channel.capacity()) as i64);
+                }
+            })
+            .map(Ok::<_, Box<dyn std::error::Error>>)
+    };
+
+    // Wait for the indexing tasks to complete
+    let (indexes, _) =
+        try_join!(try_join_all(index_tasks), fetch_pois_task).expect("failed to index POIs");
+
+    for (name, index) in indexes {
+        info!("Created index {index:?} for container {name}");
+    }
+
+    progress.finish();
+    info!("Total number of pois: {total_nb_pois}");
+    info!("Invalid values: {count_invalid_values:?}");
+    metrics::finish(&settings.metrics).await;
+}
@@ -1,103 +1,299 @@
 //! Utilities to fetch list of POIs from a postgres databased indexed with the openmaptiles schema.
 //! See https://github.com/Qwant/openmaptiles/
+pub mod import;
 pub mod pois;
 pub mod postgres;
+pub mod relocate;
+pub mod routing;
 
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
+use deadpool_postgres::Pool;
 use elasticsearch::Elasticsearch;
 use futures::stream::{Stream, StreamExt};
-use futures::{future, stream};
+use futures::{future, stream, FutureExt};
+use tokio::task::JoinHandle;
 
-use crate::lazy_es::LazyEs;
-use crate::settings::FafnirSettings;
+use crate::addr_index::{plan_tiles, AddrIndex, Tile};
+use crate::area::Area;
+use crate::lazy_es::{EsCache, EsThrottle, LazyEs};
+use crate::metrics::METRICS;
+use crate::settings::{FafnirSettings, ReverseSettings, RouteSettings};
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
-use pois::IndexedPoi;
-use postgres::fetch_all_pois_query;
-
-/// Iter over all POIs from postgres.
-// Clippy most probably gives a false positive here:
-// https://github.com/rust-lang/rust-clippy/issues/7271
-#[allow(clippy::needless_lifetimes)]
-pub async fn fetch_pois<'a>(
-    pg: &tokio_postgres::Client,
-    bbox: Option<[f64; 4]>,
+use pois::{IndexedPoi, Reuse};
+use postgres::{fetch_all_pois_query, PoisQuery};
+
+/// Iter over all POIs from postgres, the scans built from the query are run
+/// concurrently over connections of the pool.
+pub fn fetch_pois<'a>(
+    pg: &Pool,
+    query: PoisQuery,
     langs: &'a [String],
 ) -> impl Stream<Item = IndexedPoi> + 'a {
-    let query = fetch_all_pois_query(bbox);
-
-    let stmt = pg
-        .prepare(&query.build())
-        .await
-        .expect("failed to prepare query");
-
-    pg.query_raw::<_, i32, _>(&stmt, [])
-        .await
-        .expect("could not query postgres")
-        .map(|row| {
-            row.unwrap_or_else(|err| panic!("error while fetching row from postgres: {err}"))
-        })
-        .filter_map(move |row| {
-            let poi = IndexedPoi::from_row(row, langs);
-            future::ready(poi)
+    let scans = query.build_scans().into_iter().map(|scan| {
+        let pg = pg.clone();
+
+        async move {
+            let client = pg.get().await.expect("could not connect to postgres");
+
+            let stmt = client
+                .prepare(&scan)
+                .await
+                .expect("failed to prepare query");
+
+            let rows = client
+                .query_raw::<_, i32, _>(&stmt, [])
+                .await
+                .expect("could not query postgres");
+
+            // The connection is held until all rows of the scan are read
+            rows.map(move |row| {
+                let _client = &client;
+                METRICS.rows_read.inc();
+                row.unwrap_or_else(|err| panic!("error while fetching row from postgres: {err}"))
+            })
+        }
+        .flatten_stream()
+        .boxed()
+    });
+
+    stream::select_all(scans).filter_map(move |row| {
+        let poi = IndexedPoi::from_row(row, langs);
+        future::ready(poi)
+    })
+}
+
+/// Context shared by the tasks that search for admins and addresses of POIs.
+#[derive(Clone)]
+pub struct PoiLocator {
+    admin_geofinder: Arc<AdminGeoFinder>,
+    es: Arc<Elasticsearch>,
+    reverse_settings: Arc<ReverseSettings>,
+    es_cache: Option<Arc<EsCache>>,
+    es_throttle: Option<Arc<EsThrottle>>,
+    langs: Vec<String>,
+    routes: Arc<[RouteSettings]>,
+    /// Name of the index of each container, previous documents of POIs are
+    /// read from there.
+    poi_indexes: Arc<HashMap<String, String>>,
+    reuse: Reuse,
+    speculative_reverse: bool,
+    max_query_batch_size: usize,
+    concurrent_blocks: usize,
+}
+
+impl PoiLocator {
+    pub fn new(
+        es: Elasticsearch,
+        admin_geofinder: AdminGeoFinder,
+        poi_indexes: HashMap<String, String>,
+        reuse: Reuse,
+        settings: &FafnirSettings,
+    ) -> Self {
+        let es_cache = (settings.query_cache_size > 0)
+            .then(|| Arc::new(EsCache::new(settings.query_cache_size)));
+
+        let es_throttle = {
+            let throttle = &settings.throttle;
+
+            (throttle.adaptive || throttle.max_queries_per_second > 0).then(|| {
+                let mut es_throttle =
+                    EsThrottle::new(settings.max_query_batch_size, settings.concurrent_blocks)
+                        .max_queries_per_second(throttle.max_queries_per_second);
+
+                if throttle.adaptive {
+                    es_throttle = es_throttle.adaptive(
+                        Duration::from_millis(throttle.target_latency_ms),
+                        throttle.min_batch_size,
+                    );
+                }
+
+                Arc::new(es_throttle)
+            })
+        };
+
+        Self {
+            admin_geofinder: Arc::new(admin_geofinder),
+            es: Arc::new(es),
+            reverse_settings: Arc::new(settings.reverse.clone()),
+            es_cache,
+            es_throttle,
+            langs: settings.langs.clone(),
+            routes: settings.routes.clone().into(),
+            poi_indexes: Arc::new(poi_indexes),
+            reuse,
+            speculative_reverse: settings.speculative_reverse,
+            max_query_batch_size: settings.max_query_batch_size,
+            concurrent_blocks: settings.concurrent_blocks,
+        }
+    }
+
+    /// Search for admin/address of a chunk of POIs in a new task.
+    fn spawn(
+        &self,
+        pois: Vec<IndexedPoi>,
+        addr_index: Option<Arc<AddrIndex>>,
+    ) -> JoinHandle<Vec<IndexedPoi>> {
+        let locator = self.clone();
+
+        tokio::spawn(async move {
+            let pois: Vec<_> = pois
+                .iter()
+                .map(|indexed_poi| {
+                    indexed_poi.locate_poi(
+                        &locator.admin_geofinder,
+                        &locator.langs,
+                        &locator.routes,
+                        &locator.poi_indexes,
+                        locator.reuse,
+                        locator.speculative_reverse,
+                        &locator.reverse_settings,
+                        addr_index.as_deref(),
+                    )
+                })
+                .collect();
+
+            // Run ES queries until all POIs are fully built
+            let pois: Vec<_> = LazyEs::batch_make_progress_until_value(
+                locator.es.as_ref(),
+                pois,
+                locator.max_query_batch_size,
+                locator.es_cache.as_deref(),
+                locator.es_throttle.as_deref(),
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+
+            METRICS.pois_located.add(pois.len() as u64);
+            METRICS.last_progress.set_to_now();
+            pois
         })
+    }
+
+    /// Search for admin/address of all POIs from a stream, chunks of POIs are
+    /// processed concurrently. POIs that are not on any admin are dropped.
+    pub fn locate<'a>(
+        &self,
+        pois: impl Stream<Item = IndexedPoi> + 'a,
+        addr_index: Option<Arc<AddrIndex>>,
+    ) -> impl Stream<Item = IndexedPoi> + 'a {
+        let locator = self.clone();
+
+        // Keeping chunks big enough compared to the batch size will ensure that most of the
+        // requests will have exactly `max_query_batch_size` elements to be sent to ES.
+        let chunks_size = 10 * self.max_query_batch_size;
+
+        pois.chunks(chunks_size)
+            .map(move |pois| locator.spawn(pois, addr_index.clone()))
+            .buffer_unordered(self.concurrent_blocks)
+            .map(|res| res.expect("task panicked"))
+            .flat_map(stream::iter)
+    }
+}
+
+/// Query selecting all POIs to import from postgres.
+fn pois_query(
+    bbox: Option<[f64; 4]>,
+    area_geojson: Option<String>,
+    settings: &FafnirSettings,
+) -> PoisQuery {
+    let mut query = fetch_all_pois_query(bbox, &settings.inherit_address_from)
+        .partitions(settings.scan_partitions);
+
+    if let Some(area_geojson) = area_geojson {
+        query = query.area(area_geojson);
+    }
+
+    query
+}
+
+/// Query selecting all POIs to import from postgres, restricted to the
+/// bounding box from settings or to an area.
+pub fn import_query(area: Option<&Area>, settings: &FafnirSettings) -> PoisQuery {
+    let bbox = settings.bounding_box.or_else(|| area.map(Area::bbox));
+    pois_query(bbox, area.map(Area::to_geojson), settings)
+}
+
+/// Count POIs that will be fetched from postgres, tables are counted
+/// concurrently over connections of the pool.
+pub async fn count_pois(pg: &Pool, area: Option<&Area>, settings: &FafnirSettings) -> u64 {
+    let query = import_query(area, settings);
+
+    let counts = query.build_counts().into_iter().map(|count| {
+        let pg = pg.clone();
+
+        async move {
+            let client = pg.get().await.expect("could not connect to postgres");
+
+            let row = client
+                .query_one(count.as_str(), &[])
+                .await
+                .expect("could not count POIs");
+
+            row.get::<_, i64>(0) as u64
+        }
+    });
+
+    future::join_all(counts).await.into_iter().sum()
 }
 
 /// Iter over all POIs from postgres and search for its admin/address.
+///
+/// If the in-memory address index is enabled, POIs are processed tile by
+/// tile, after the addresses around each tile have been loaded.
 pub async fn fetch_and_locate_pois<'a>(
-    pg: &tokio_postgres::Client,
-    es: Elasticsearch,
-    admin_geofinder: AdminGeoFinder,
-    poi_index_name: &'a str,
-    poi_index_nosearch_name: &'a str,
-    try_skip_reverse: bool,
+    pg: &'a Pool,
+    locator: PoiLocator,
+    area: Option<&Area>,
     settings: &'a FafnirSettings,
 ) -> impl Stream<Item = IndexedPoi> + 'a {
-    let admin_geofinder = Arc::new(admin_geofinder);
-    let es = Arc::new(es);
-
-    // Keeping chunks big enough compared to the batch size will ensure that most of the requests
-    // will have exactly `max_query_batch_size` elements to be sent to ES.
-    let chunks_size = 10 * settings.max_query_batch_size;
-
-    fetch_pois(pg, settings.bounding_box, &settings.langs)
-        .await
-        .chunks(chunks_size)
-        .map(move |pois| {
-            let admin_geofinder = admin_geofinder.clone();
-            let es = es.clone();
-            let poi_index_name = poi_index_name.to_string();
-            let poi_index_nosearch_name = poi_index_nosearch_name.to_string();
-            let langs = settings.langs.clone();
-            let max_query_batch_size = settings.max_query_batch_size;
-
-            tokio::spawn(async move {
-                // Build POIs from postgres
-                let pois: Vec<_> = pois
-                    .iter()
-                    .map(|indexed_poi| {
-                        indexed_poi.locate_poi(
-                            &admin_geofinder,
-                            &langs,
-                            &poi_index_name,
-                            &poi_index_nosearch_name,
-                            try_skip_reverse,
-                        )
-                    })
-                    .collect();
-
-                // Run ES queries until all POIs are fully built
-                let pois: Vec<_> =
-                    LazyEs::batch_make_progress_until_value(&es, pois, max_query_batch_size)
-                        .await
-                        .into_iter()
-                        .flatten()
-                        .collect();
-
-                stream::iter(pois)
-            })
+    // The bounding box of the area is used to plan tiles and partitions
+    let bbox = settings.bounding_box.or_else(|| area.map(Area::bbox));
+    let area_geojson = area.map(Area::to_geojson);
+
+    let tiles: Vec<Option<(Tile, f64)>> = {
+        if settings.address_index.enabled {
+            // Addresses must be loaded up to the max reverse distance around each tile
+            let margin = (settings.reverse.iter())
+                .map(|distances| distances.addr_distance.meters())
+                .fold(0., f64::max);
+
+            let max_addresses = settings.address_index.max_addresses;
+
+            plan_tiles(&locator.es, Tile::from_bbox(bbox), margin, max_addresses)
+                .await
+                .into_iter()
+                .map(|tile| Some((tile, margin)))
+                .collect()
+        } else {
+            vec![None]
+        }
+    };
+
+    stream::iter(tiles)
+        .then(move |tile| {
+            let locator = locator.clone();
+            let area_geojson = area_geojson.clone();
+
+            async move {
+                let mut query = pois_query(bbox, area_geojson, settings);
+
+                let addr_index = match tile {
+                    Some((tile, margin)) => {
+                        query = query.tile(tile.bbox());
+                        let addr_index =
+                            AddrIndex::load(&locator.es, &tile.with_margin(margin)).await;
+                        Some(Arc::new(addr_index))
+                    }
+                    None => None,
+                };
+
+                locator.locate(fetch_pois(pg, query, &settings.langs), addr_index)
+            }
         })
-        .buffer_unordered(settings.concurrent_blocks)
-        .map(|res| res.expect("task panicked"))
         .flatten()
 }
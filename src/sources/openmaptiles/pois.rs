@@ -1,7 +1,19 @@
-use crate::addresses::find_address;
-use crate::addresses::iter_admins;
+use crate::addr_index::AddrIndex;
+use crate::addresses::{
+    addr_tags, alternate_addr_names, extract_unit_and_floor, find_address,
+    is_addr_derived_from_tags, iter_admins,
+};
+use crate::contact::normalize_contact_properties;
+use crate::fingerprint::{
+    address_fingerprint, document_fingerprint, stored_fingerprint, ADDRESS_FINGERPRINT_KEY,
+    DOCUMENT_FINGERPRINT_KEY,
+};
 use crate::langs::COUNTRIES_LANGS;
 use crate::lazy_es::LazyEs;
+use crate::opening_hours;
+use crate::provenance::{AddressMethod, Record, PROVENANCE_PREFIX};
+use crate::settings::{ReverseSettings, RouteSettings};
+use crate::sources::openmaptiles::routing::route_with_countries;
 use itertools::Itertools;
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
 use mimirsbrunn::labels::{format_international_poi_label, format_poi_label};
@@ -12,6 +24,7 @@ use places::{
     poi::{Poi, PoiType},
     Address, Property,
 };
+use serde_json::json;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use tracing::{debug, warn};
 
@@ -58,10 +71,28 @@ static NON_SEARCHABLE_ITEMS: Lazy<HashSet<(String, String)>> = Lazy::new(|| {
     .collect()
 });
 
+/// Parts of the document of a POI from a previous import that can be reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reuse {
+    /// Always build POIs from scratch.
+    Nothing,
+    /// Keep the address of a POI if inputs of the reverse didn't change.
+    Address,
+    /// Keep the whole document of a POI if none of its inputs changed, or
+    /// else its address if inputs of the reverse didn't change.
+    Document,
+}
+
 #[derive(Clone)]
 pub struct IndexedPoi {
     pub poi: Poi,
     pub is_searchable: bool,
+    /// Keys of the tags that were discarded because of an invalid value.
+    pub invalid_values: Vec<&'static str>,
+    /// Address tags inherited from the polygon the POI is located in.
+    pub inherited_address: BTreeMap<String, String>,
+    /// How the address of the POI was found, once it has been located.
+    pub address_method: Option<AddressMethod>,
 }
 
 impl IndexedPoi {
@@ -78,6 +109,11 @@ impl IndexedPoi {
 
         let weight = row.get::<_, Option<f64>>("weight").unwrap_or(0.);
 
+        let inherited_address = row
+            .get::<_, Option<HashMap<_, _>>>("inherited_addr")
+            .map(properties_from_tags)
+            .unwrap_or_default();
+
         let lat = row
             .try_get("lat")
             .map_err(|e| warn!("impossible to get lat for {id} because {e}"))
@@ -99,7 +135,15 @@ impl IndexedPoi {
 
         let poi_type_id = format!("class_{class}:subclass_{subclass}");
         let poi_type_text = build_poi_type_text(&class, &subclass, &tags);
-        let row_properties = properties_from_tags(tags);
+        let mut row_properties = properties_from_tags(tags);
+        let mut invalid_values = Vec::new();
+
+        if !normalize_opening_hours(&id, &mut row_properties) {
+            invalid_values.push("opening_hours");
+        }
+
+        extract_unit_and_floor(&mut row_properties);
+
         let names = build_names(langs, &row_properties);
         let properties = build_poi_properties(&row, row_properties);
 
@@ -126,116 +170,315 @@ impl IndexedPoi {
             ..Default::default()
         };
 
-        Some(IndexedPoi { poi, is_searchable })
+        Some(IndexedPoi {
+            poi,
+            is_searchable,
+            invalid_values,
+            inherited_address,
+            address_method: None,
+        })
+    }
+
+    /// Rebuild a POI read from an existing index as it was before its admins
+    /// and address were searched, so that it can be located again.
+    pub fn from_document(mut poi: Poi, is_searchable: bool) -> IndexedPoi {
+        // Address tags inherited from a polygon are not stored in documents,
+        // they are recovered from the address if it was read from tags. They
+        // are ignored if the POI has its own address tags.
+        let inherited_address = match &poi.address {
+            Some(addr) if is_addr_derived_from_tags(addr) => addr_tags(addr),
+            _ => BTreeMap::new(),
+        };
+
+        poi.administrative_regions = Vec::new();
+        poi.address = None;
+        poi.label = "".into();
+        poi.labels = I18nProperties::default();
+        poi.zip_codes = Vec::new();
+        poi.country_codes = Vec::new();
+
+        for key in [ADDRESS_FINGERPRINT_KEY, DOCUMENT_FINGERPRINT_KEY] {
+            poi.properties.remove(key);
+        }
+
+        // The address will be searched again
+        (poi.properties).remove(&format!("{PROVENANCE_PREFIX}address_method"));
+
+        // The class is the first extra label, next ones are built from admins
+        // and address.
+        poi.full_label_extra.truncate(1);
+
+        IndexedPoi {
+            poi,
+            is_searchable,
+            invalid_values: Vec::new(),
+            inherited_address,
+            address_method: None,
+        }
     }
 
     // TODO: shoudn't we move self?
+    #[allow(clippy::too_many_arguments)]
     pub fn locate_poi<'a>(
         &'a self,
         geofinder: &'a AdminGeoFinder,
         langs: &'a [String],
-        poi_index: &'a str,
-        poi_index_nosearch: &'a str,
-        try_skip_reverse: bool,
+        routes: &'a [RouteSettings],
+        poi_indexes: &'a HashMap<String, String>,
+        reuse: Reuse,
+        speculative_reverse: bool,
+        reverse_settings: &'a ReverseSettings,
+        addr_index: Option<&'a AddrIndex>,
     ) -> LazyEs<'a, Option<IndexedPoi>> {
-        let index = if self.is_searchable {
-            poi_index
-        } else {
-            poi_index_nosearch
+        let poi_class = self.poi.properties.get("poi_class").map(String::as_str);
+        let distances = reverse_settings.distances(poi_class);
+        let admins = geofinder.get(&self.poi.coord);
+
+        let address_fingerprint =
+            address_fingerprint(&self.poi, &self.inherited_address, &admins, distances);
+
+        let document_fingerprint = document_fingerprint(&self.poi, &address_fingerprint, langs);
+
+        let previous = match reuse {
+            Reuse::Nothing => LazyEs::Value(None),
+            Reuse::Address | Reuse::Document => {
+                // The previous document is read from the index of the
+                // container the POI is routed to.
+                let country_codes = find_country_codes(iter_admins(&admins));
+                let container = route_with_countries(routes, self, &country_codes);
+                get_indexed_poi(&poi_indexes[container], &self.poi.id)
+            }
         };
 
-        find_address(&self.poi, geofinder, index, try_skip_reverse).map(move |poi_address| {
-            let mut res = self.clone();
+        // The address can be searched while the previous document is fetched,
+        // in the same batch of queries, at the cost of sending a reverse even
+        // for POIs which inputs didn't change.
+        let speculative = match reuse {
+            Reuse::Address | Reuse::Document if speculative_reverse => find_address(
+                &self.poi,
+                &self.inherited_address,
+                geofinder,
+                distances,
+                addr_index,
+                None,
+            )
+            .map(Some),
+            _ => LazyEs::Value(None),
+        };
 
-            // if we have an address, we take the address's admin as the poi's admin
-            // else we lookup the admin by the poi's coordinates
-            let (admins, country_codes) = poi_address
-                .as_ref()
-                .map(|a| match a {
-                    Address::Street(ref s) => {
-                        (s.administrative_regions.clone(), s.country_codes.clone())
+        previous
+            .join(speculative)
+            .then(move |(previous, speculative)| {
+                let fingerprint_matches = |key, fingerprint: &str| {
+                    previous
+                        .as_ref()
+                        .and_then(|poi| stored_fingerprint(poi, key))
+                        == Some(fingerprint)
+                };
+
+                let same_document =
+                    fingerprint_matches(DOCUMENT_FINGERPRINT_KEY, &document_fingerprint);
+                let same_address =
+                    fingerprint_matches(ADDRESS_FINGERPRINT_KEY, &address_fingerprint);
+
+                match previous {
+                    Some(poi) if reuse == Reuse::Document && same_document => {
+                        // Addresses read from tags are not considered as reused
+                        let address_method = poi.address.as_ref().map(|addr| {
+                            if is_addr_derived_from_tags(addr) {
+                                AddressMethod::Tag
+                            } else {
+                                AddressMethod::Reused
+                            }
+                        });
+
+                        LazyEs::Value(Some(IndexedPoi {
+                            poi,
+                            is_searchable: self.is_searchable,
+                            invalid_values: self.invalid_values.clone(),
+                            inherited_address: self.inherited_address.clone(),
+                            address_method,
+                        }))
                     }
-                    Address::Addr(ref s) => (
-                        s.street.administrative_regions.clone(),
-                        s.country_codes.clone(),
-                    ),
-                })
-                .unwrap_or_else(|| {
-                    let admins = geofinder.get(&res.poi.coord);
-                    let country_codes = find_country_codes(iter_admins(&admins));
-                    (admins, country_codes)
-                });
-
-            if admins.is_empty() {
-                debug!("The poi {} is not on any admins", &res.poi.id);
-                return None;
-            }
+                    previous => {
+                        let previous_address =
+                            same_address.then(|| previous.and_then(|poi| poi.address));
+
+                        // No reverse is sent if the previous address is reused
+                        let found = match (speculative, previous_address) {
+                            (Some(found), None) => LazyEs::Value(found),
+                            (_, previous_address) => find_address(
+                                &self.poi,
+                                &self.inherited_address,
+                                geofinder,
+                                distances,
+                                addr_index,
+                                previous_address,
+                            ),
+                        };
+
+                        found.map(move |found| {
+                            self.build_located(
+                                geofinder,
+                                langs,
+                                found,
+                                [
+                                    (ADDRESS_FINGERPRINT_KEY, address_fingerprint),
+                                    (DOCUMENT_FINGERPRINT_KEY, document_fingerprint),
+                                ],
+                            )
+                        })
+                    }
+                }
+            })
+    }
 
-            let zip_codes = match poi_address {
-                Some(Address::Street(ref s)) => s.zip_codes.clone(),
-                Some(Address::Addr(ref a)) => a.zip_codes.clone(),
-                None => vec![],
-            };
+    /// Set admins and address of the POI and build its labels, fingerprints
+    /// of the inputs are stored in its properties.
+    fn build_located(
+        &self,
+        geofinder: &AdminGeoFinder,
+        langs: &[String],
+        found: Option<(Address, AddressMethod)>,
+        fingerprints: [(&'static str, String); 2],
+    ) -> Option<IndexedPoi> {
+        let mut res = self.clone();
+        let (poi_address, address_method) = found.unzip();
+
+        // if we have an address, we take the address's admin as the poi's admin
+        // else we lookup the admin by the poi's coordinates
+        let (admins, country_codes) = poi_address
+            .as_ref()
+            .map(|a| match a {
+                Address::Street(ref s) => {
+                    (s.administrative_regions.clone(), s.country_codes.clone())
+                }
+                Address::Addr(ref s) => (
+                    s.street.administrative_regions.clone(),
+                    s.country_codes.clone(),
+                ),
+            })
+            .unwrap_or_else(|| {
+                let admins = geofinder.get(&res.poi.coord);
+                let country_codes = find_country_codes(iter_admins(&admins));
+                (admins, country_codes)
+            });
+
+        if admins.is_empty() {
+            debug!("The poi {} is not on any admins", &res.poi.id);
+            return None;
+        }
 
-            res.poi.administrative_regions = admins;
-            res.poi.address = poi_address;
-
-            res.poi.label = format_poi_label(
-                &res.poi.name,
-                iter_admins(&res.poi.administrative_regions),
-                &country_codes,
-            );
-
-            res.poi.labels = format_international_poi_label(
-                &res.poi.names,
-                &res.poi.name,
-                &res.poi.label,
-                iter_admins(&res.poi.administrative_regions),
-                &country_codes,
-                langs,
-            );
-
-            for country_code in country_codes.iter() {
-                if let Some(&country_langs) =
-                    COUNTRIES_LANGS.get(country_code.to_uppercase().as_str())
-                {
-                    let has_lang = |props: &I18nProperties, lang: &str| {
-                        props.0.iter().any(|prop| prop.key == lang)
-                    };
-
-                    for lang in country_langs {
-                        if langs.contains(&lang.to_string()) && !has_lang(&res.poi.labels, lang) {
-                            res.poi.labels.0.push(Property {
-                                key: lang.to_string(),
-                                value: res.poi.label.clone(),
-                            });
-                        }
+        let zip_codes = match poi_address {
+            Some(Address::Street(ref s)) => s.zip_codes.clone(),
+            Some(Address::Addr(ref a)) => a.zip_codes.clone(),
+            None => vec![],
+        };
+
+        res.poi.administrative_regions = admins;
+        res.poi.address = poi_address;
+        res.address_method = address_method;
+
+        res.poi.label = format_poi_label(
+            &res.poi.name,
+            iter_admins(&res.poi.administrative_regions),
+            &country_codes,
+        );
+
+        res.poi.labels = format_international_poi_label(
+            &res.poi.names,
+            &res.poi.name,
+            &res.poi.label,
+            iter_admins(&res.poi.administrative_regions),
+            &country_codes,
+            langs,
+        );
+
+        for country_code in country_codes.iter() {
+            if let Some(&country_langs) = COUNTRIES_LANGS.get(country_code.to_uppercase().as_str())
+            {
+                let has_lang = |props: &I18nProperties, lang: &str| {
+                    props.0.iter().any(|prop| prop.key == lang)
+                };
+
+                for lang in country_langs {
+                    if langs.contains(&lang.to_string()) && !has_lang(&res.poi.labels, lang) {
+                        res.poi.labels.0.push(Property {
+                            key: lang.to_string(),
+                            value: res.poi.label.clone(),
+                        });
                     }
+                }
 
-                    for lang in country_langs {
-                        if langs.contains(&lang.to_string()) && !has_lang(&res.poi.names, lang) {
-                            res.poi.names.0.push(Property {
-                                key: lang.to_string(),
-                                value: res.poi.name.clone(),
-                            })
-                        }
+                for lang in country_langs {
+                    if langs.contains(&lang.to_string()) && !has_lang(&res.poi.names, lang) {
+                        res.poi.names.0.push(Property {
+                            key: lang.to_string(),
+                            value: res.poi.name.clone(),
+                        })
                     }
                 }
             }
-            res.poi.zip_codes = zip_codes;
-
-            let full_label_admins: Vec<String> = res
-                .poi
-                .administrative_regions
-                .iter()
-                .filter(|admin| admin.level == 8 || admin.level == 6)
-                .map(|admin| admin.name.clone())
-                .collect();
-
-            res.poi.full_label_extra.extend(full_label_admins);
-            res.poi.country_codes = country_codes;
-            Some(res)
-        })
+        }
+        res.poi.zip_codes = zip_codes;
+
+        let full_label_admins: Vec<String> = res
+            .poi
+            .administrative_regions
+            .iter()
+            .filter(|admin| admin.level == 8 || admin.level == 6)
+            .map(|admin| admin.name.clone())
+            .collect();
+
+        res.poi.full_label_extra.extend(full_label_admins);
+
+        let invalid_contacts =
+            normalize_contact_properties(&mut res.poi.properties, &country_codes);
+
+        res.invalid_values.extend(invalid_contacts);
+        res.poi.country_codes = country_codes;
+
+        // Make alternate house numbers searchable
+        let alternate_names = alternate_addr_names(&res.poi);
+        res.poi.full_label_extra.extend(alternate_names);
+
+        for (key, fingerprint) in fingerprints {
+            res.poi.properties.insert(key.to_string(), fingerprint);
+        }
+
+        Some(res)
+    }
+}
+
+impl Record for IndexedPoi {
+    fn source_id(&self) -> String {
+        self.poi.id.clone()
+    }
+
+    fn address_method(&self) -> Option<AddressMethod> {
+        self.address_method
+    }
+
+    fn poi_mut(&mut self) -> &mut Poi {
+        &mut self.poi
+    }
+}
+
+/// Get the document of a POI from an index, if it exists.
+fn get_indexed_poi<'a>(poi_index: &str, id: &str) -> LazyEs<'a, Option<Poi>> {
+    LazyEs::NeedEsQuery {
+        header: json!({ "index": poi_index }),
+        query: json!({ "query": { "terms": { "_id": [id] } } }),
+        cache_key: None,
+        progress: Box::new(|hits| {
+            LazyEs::Value({
+                assert!(hits.len() <= 1);
+
+                hits.into_iter().next().map(|hit| {
+                    serde_json::from_str(hit.source.get()).expect("got invalid POI from ES")
+                })
+            })
+        }),
     }
 }
 
@@ -245,6 +488,25 @@ fn properties_from_tags(tags: HashMap<String, Option<String>>) -> BTreeMap<Strin
         .collect()
 }
 
+/// Replace the `opening_hours` tag with its canonical form, the tag is removed
+/// if its value is invalid, in which case `false` is returned.
+fn normalize_opening_hours(id: &str, properties: &mut BTreeMap<String, String>) -> bool {
+    if let Some(raw) = properties.get("opening_hours") {
+        match opening_hours::normalize(raw) {
+            Ok(normalized) => {
+                properties.insert("opening_hours".to_string(), normalized);
+            }
+            Err(err) => {
+                debug!("invalid opening_hours for {id} ({raw:?}): {err}");
+                properties.remove("opening_hours");
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
 fn build_poi_type_text(
     class: &str,
     subclass: &str,
@@ -309,3 +571,373 @@ fn build_names(langs: &[String], properties: &BTreeMap<String, String>) -> I18nP
 
     I18nProperties(properties)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::addr_index::Distance;
+    use crate::es_memory::MemoryEs;
+    use crate::mimir::admin_geofinder_from;
+    use crate::settings::ReverseDistances;
+    use crate::sources::openmaptiles::routing::DEFAULT_SEARCH_CONTAINER;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+    use places::addr::Addr;
+    use places::admin::Admin;
+    use places::street::Street;
+    use std::sync::Arc;
+
+    fn city() -> Arc<Admin> {
+        let boundary = Polygon::new(
+            LineString::from(vec![(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.)]),
+            vec![],
+        );
+
+        Arc::new(Admin {
+            id: "admin:bobtown".to_string(),
+            level: 8,
+            name: "Bobtown".to_string(),
+            label: "Bobtown".to_string(),
+            zone_type: Some(cosmogony::ZoneType::City),
+            boundary: Some(MultiPolygon(vec![boundary])),
+            ..Default::default()
+        })
+    }
+
+    fn street(id: &str, name: &str, lon: f64) -> Street {
+        Street {
+            id: id.to_string(),
+            name: name.to_string(),
+            label: format!("{name} (Bobtown)"),
+            coord: Coord::new(lon, 5.),
+            administrative_regions: vec![city()],
+            zip_codes: vec!["12345".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn addr(id: &str, house_number: &str, lon: f64) -> Addr {
+        let street = street(&format!("street:{id}"), "Rue du test", lon);
+
+        Addr {
+            id: id.to_string(),
+            house_number: house_number.to_string(),
+            name: format!("{house_number} Rue du test"),
+            label: format!("{house_number} Rue du test (Bobtown)"),
+            coord: street.coord,
+            approx_coord: None,
+            weight: 0.,
+            zip_codes: street.zip_codes.clone(),
+            street,
+            distance: None,
+            country_codes: Vec::new(),
+            context: None,
+        }
+    }
+
+    fn poi(tags: &[(&str, &str)]) -> IndexedPoi {
+        IndexedPoi {
+            poi: Poi {
+                id: "osm:node:1".to_string(),
+                name: "Bob's shop".to_string(),
+                coord: Coord::new(5., 5.),
+                properties: (tags.iter())
+                    .map(|(key, val)| (key.to_string(), val.to_string()))
+                    .collect(),
+                full_label_extra: vec!["shop".to_string()],
+                ..Default::default()
+            },
+            is_searchable: true,
+            invalid_values: Vec::new(),
+            inherited_address: BTreeMap::new(),
+            address_method: None,
+        }
+    }
+
+    /// Search for the admins and address of a POI, addresses are read from
+    /// an in-memory backend.
+    async fn locate(es: &MemoryEs, poi: &IndexedPoi) -> IndexedPoi {
+        locate_with(es, poi, Reuse::Nothing).await
+    }
+
+    /// Same as `locate`, previous documents are read from `munin_poi`.
+    async fn locate_with(es: &MemoryEs, poi: &IndexedPoi, reuse: Reuse) -> IndexedPoi {
+        locate_speculative(es, poi, reuse, false).await
+    }
+
+    /// Same as `locate_with`, with control over `speculative_reverse`.
+    async fn locate_speculative(
+        es: &MemoryEs,
+        poi: &IndexedPoi,
+        reuse: Reuse,
+        speculative_reverse: bool,
+    ) -> IndexedPoi {
+        let poi_indexes = [(
+            DEFAULT_SEARCH_CONTAINER.to_string(),
+            "munin_poi".to_string(),
+        )]
+        .into();
+        let geofinder = admin_geofinder_from(vec![city().as_ref().clone()]);
+        let langs = ["fr".to_string()];
+
+        let reverse_settings = ReverseSettings {
+            default: ReverseDistances {
+                addr_distance: Distance::parse("50m").unwrap(),
+                street_distance: Distance::parse("1km").unwrap(),
+            },
+            classes: HashMap::new(),
+        };
+
+        let located = poi.locate_poi(
+            &geofinder,
+            &langs,
+            &[],
+            &poi_indexes,
+            reuse,
+            speculative_reverse,
+            &reverse_settings,
+            None,
+        );
+
+        LazyEs::batch_make_progress_until_value(es, vec![located], 10, None, None)
+            .await
+            .remove(0)
+            .expect("POI is not on any admin")
+    }
+
+    fn address_id(poi: &IndexedPoi) -> Option<&str> {
+        match poi.poi.address.as_ref()? {
+            Address::Addr(addr) => Some(&addr.id),
+            Address::Street(street) => Some(&street.id),
+        }
+    }
+
+    fn address_label(poi: &IndexedPoi) -> Option<String> {
+        match poi.poi.address.as_ref()? {
+            Address::Addr(addr) => Some(addr.label.clone()),
+            Address::Street(street) => Some(street.label.clone()),
+        }
+    }
+
+    fn fixtures(places: &[(&str, serde_json::Value)]) -> MemoryEs {
+        let mut es = MemoryEs::new();
+
+        for (id, place) in places {
+            let index = if id.starts_with("street:") {
+                "munin_street"
+            } else {
+                "munin_addr"
+            };
+
+            es.insert(index, *id, place.clone());
+        }
+
+        es
+    }
+
+    #[tokio::test]
+    async fn address_is_built_from_tags() {
+        let es = fixtures(&[("addr:1", json!(addr("addr:1", "1", 5.)))]);
+        let poi = poi(&[("addr:housenumber", "5"), ("addr:street", "Rue Bob")]);
+        let located = locate(&es, &poi).await;
+
+        let Some(Address::Addr(addr)) = &located.poi.address else {
+            panic!("expected an address, got {:?}", located.poi.address);
+        };
+
+        assert_eq!(addr.house_number, "5");
+        assert_eq!(addr.street.name, "Rue Bob");
+        assert_eq!(located.address_method, Some(AddressMethod::Tag));
+        assert_eq!(located.poi.administrative_regions[0].id, "admin:bobtown");
+    }
+
+    #[tokio::test]
+    async fn nearest_address_is_used_without_tags() {
+        let es = fixtures(&[
+            ("addr:far", json!(addr("addr:far", "3", 5.0003))),
+            ("addr:near", json!(addr("addr:near", "1", 5.0001))),
+            ("street:1", json!(street("street:1", "Rue du test", 5.))),
+        ]);
+
+        let located = locate(&es, &poi(&[])).await;
+        assert_eq!(address_id(&located), Some("addr:near"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reverse));
+        assert_eq!(located.poi.zip_codes, ["12345"]);
+    }
+
+    #[tokio::test]
+    async fn street_is_used_without_address_in_range() {
+        let es = fixtures(&[
+            ("addr:far", json!(addr("addr:far", "3", 5.01))),
+            ("street:1", json!(street("street:1", "Rue du test", 5.002))),
+        ]);
+
+        let located = locate(&es, &poi(&[])).await;
+        assert_eq!(address_id(&located), Some("street:1"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reverse));
+    }
+
+    #[tokio::test]
+    async fn poi_is_kept_without_address() {
+        let es = fixtures(&[("addr:far", json!(addr("addr:far", "3", 5.01)))]);
+        let located = locate(&es, &poi(&[])).await;
+        assert_eq!(address_id(&located), None);
+        assert_eq!(located.address_method, None);
+        assert_eq!(located.poi.administrative_regions[0].id, "admin:bobtown");
+    }
+
+    /// Index a POI as it was located by a previous import, then add an
+    /// address closer to it.
+    async fn previous_import(poi: &IndexedPoi) -> MemoryEs {
+        let mut es = fixtures(&[("addr:far", json!(addr("addr:far", "3", 5.0003)))]);
+        let mut previous = locate(&es, poi).await;
+        assert_eq!(address_id(&previous), Some("addr:far"));
+
+        // Labels are not part of the inputs, this tells if the document was
+        // kept as is.
+        previous.poi.label = "previous label".to_string();
+        es.insert("munin_poi", &poi.poi.id, json!(previous.poi));
+
+        let near = addr("addr:near", "1", 5.0001);
+        es.insert("munin_addr", "addr:near", json!(near));
+        es
+    }
+
+    #[tokio::test]
+    async fn nothing_is_reused_by_default() {
+        let shop = poi(&[]);
+        let es = previous_import(&shop).await;
+        let located = locate_with(&es, &shop, Reuse::Nothing).await;
+        assert_eq!(address_id(&located), Some("addr:near"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reverse));
+    }
+
+    #[tokio::test]
+    async fn address_is_reused_if_its_inputs_did_not_change() {
+        let shop = poi(&[]);
+        let es = previous_import(&shop).await;
+        let located = locate_with(&es, &shop, Reuse::Address).await;
+        assert_eq!(address_id(&located), Some("addr:far"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reused));
+        assert_ne!(located.poi.label, "previous label");
+
+        // The address is searched again for POIs that moved
+        let mut moved = shop.clone();
+        moved.poi.coord = Coord::new(5.00005, 5.);
+        let located = locate_with(&es, &moved, Reuse::Address).await;
+        assert_eq!(address_id(&located), Some("addr:near"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reverse));
+    }
+
+    #[tokio::test]
+    async fn document_is_reused_if_its_inputs_did_not_change() {
+        let shop = poi(&[]);
+        let es = previous_import(&shop).await;
+        let located = locate_with(&es, &shop, Reuse::Document).await;
+        assert_eq!(located.poi.label, "previous label");
+        assert_eq!(address_id(&located), Some("addr:far"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reused));
+
+        // Only the address is reused for POIs that changed
+        let mut renamed = shop.clone();
+        renamed.poi.name = "Bob's bakery".to_string();
+        let located = locate_with(&es, &renamed, Reuse::Document).await;
+        assert_ne!(located.poi.label, "previous label");
+        assert_eq!(located.poi.name, "Bob's bakery");
+        assert_eq!(address_id(&located), Some("addr:far"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reused));
+    }
+
+    /// Names of the indexes searched since the last call, reverses may
+    /// target a list of indexes.
+    fn searched_indexes(es: &MemoryEs) -> Vec<String> {
+        (es.take_searches().into_iter())
+            .flat_map(|(header, _)| match &header["index"] {
+                serde_json::Value::Array(indices) => indices.clone(),
+                index => vec![index.clone()],
+            })
+            .filter_map(|index| index.as_str().map(str::to_string))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn no_reverse_is_sent_if_inputs_did_not_change() {
+        let shop = poi(&[]);
+        let es = previous_import(&shop).await;
+
+        for reuse in [Reuse::Address, Reuse::Document] {
+            es.take_searches();
+            let located = locate_with(&es, &shop, reuse).await;
+            assert_eq!(located.address_method, Some(AddressMethod::Reused));
+            assert_eq!(searched_indexes(&es), ["munin_poi"]);
+        }
+
+        // The address is searched after the previous document is read
+        let mut moved = shop.clone();
+        moved.poi.coord = Coord::new(5.00005, 5.);
+        locate_with(&es, &moved, Reuse::Address).await;
+        let searched = searched_indexes(&es);
+        assert_eq!(searched[0], "munin_poi");
+        assert!(searched[1..].iter().any(|index| index == "munin_addr"));
+    }
+
+    #[tokio::test]
+    async fn speculative_reverse_is_sent_with_previous_document() {
+        let shop = poi(&[]);
+        let es = previous_import(&shop).await;
+        es.take_searches();
+
+        let located = locate_speculative(&es, &shop, Reuse::Address, true).await;
+        assert_eq!(address_id(&located), Some("addr:far"));
+        assert_eq!(located.address_method, Some(AddressMethod::Reused));
+
+        let searched = searched_indexes(&es);
+        assert!(searched.iter().any(|index| index == "munin_poi"));
+        assert!(searched.iter().any(|index| index == "munin_addr"));
+    }
+
+    #[tokio::test]
+    async fn inherited_address_is_recovered_from_documents() {
+        let mut poi = poi(&[]);
+
+        poi.inherited_address = [
+            ("addr:housenumber", "8"),
+            ("addr:street", "Rue du parc"),
+            ("addr:postcode", "75016"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let located = locate(&MemoryEs::new(), &poi).await;
+        assert_eq!(located.address_method, Some(AddressMethod::Tag));
+        let label = address_label(&located);
+
+        let document = IndexedPoi::from_document(located.poi, true);
+        assert_eq!(document.inherited_address, poi.inherited_address);
+        assert!(document.poi.address.is_none());
+
+        // The address is built again from the recovered tags
+        let relocated = locate(&MemoryEs::new(), &document).await;
+        assert_eq!(address_label(&relocated), label);
+        assert_eq!(relocated.poi.zip_codes, ["75016"]);
+    }
+
+    fn opening_hours(raw: &str) -> BTreeMap<String, String> {
+        [("opening_hours".to_string(), raw.to_string())].into()
+    }
+
+    #[test]
+    fn invalid_opening_hours_are_removed() {
+        let mut properties = opening_hours("Mo-Fr 9h-18h");
+        assert!(!normalize_opening_hours("osm:node:1", &mut properties));
+        assert!(!properties.contains_key("opening_hours"));
+    }
+
+    #[test]
+    fn valid_opening_hours_are_normalized() {
+        let mut properties = opening_hours("Mo 09:00-12:00; Tu 09:00-12:00");
+        assert!(normalize_opening_hours("osm:node:1", &mut properties));
+        assert_eq!(properties["opening_hours"], "Mo,Tu 09:00-12:00");
+        assert!(normalize_opening_hours("osm:node:2", &mut BTreeMap::new()));
+    }
+}
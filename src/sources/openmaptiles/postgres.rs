@@ -1,35 +1,53 @@
 //! Helpers to query the list of searchable POIs from a postgres database previously populated with
 //! imposm.
 
-pub fn fetch_all_pois_query(bbox: Option<[f64; 4]>) -> PoisQuery {
+/// Tags of a polygon that can be inherited by the POIs it contains.
+const INHERITED_ADDRESS_TAGS: &[&str] = &[
+    "addr:housenumber",
+    "addr:street",
+    "addr:place",
+    "addr:postcode",
+    "addr:city",
+];
+
+/// Tags that make a polygon, or a POI, hold its own address.
+const ADDRESS_TAGS: &[&str] = &["addr:street", "addr:place"];
+
+pub fn fetch_all_pois_query(bbox: Option<[f64; 4]>, address_tables: &[String]) -> PoisQuery {
+    let inherit = |table: TableQuery| table.inherit_address_from(address_tables);
+
     let mut query = PoisQuery::new()
-        .with_table(TableQuery::new("all_pois(14)").id_column("global_id"))
-        .with_table(
+        .with_table(inherit(
+            TableQuery::new("all_pois(14)")
+                .id_column("global_id")
+                .partitioned(),
+        ))
+        .with_table(inherit(
             TableQuery::new("osm_aerodrome_label_point")
                 .override_class("'aerodrome'")
                 .override_subclass("'airport'"),
-        )
-        .with_table(
+        ))
+        .with_table(inherit(
             TableQuery::new("osm_city_point")
                 .override_class("'locality'")
                 .override_subclass("'hamlet'")
                 .filter("name <> '' AND place='hamlet'"),
-        )
-        .with_table(
+        ))
+        .with_table(inherit(
             TableQuery::new("osm_water_lakeline")
                 .override_class("'water'")
                 .override_subclass("'lake'"),
-        )
-        .with_table(
+        ))
+        .with_table(inherit(
             TableQuery::new("osm_water_point")
                 .override_class("'water'")
                 .override_subclass("'water'"),
-        )
-        .with_table(
+        ))
+        .with_table(inherit(
             TableQuery::new("osm_marine_point")
                 .override_class("'water'")
                 .override_subclass("place"),
-        );
+        ));
 
     if let Some(bbox) = bbox {
         query = query.bbox(bbox);
@@ -41,6 +59,9 @@ pub fn fetch_all_pois_query(bbox: Option<[f64; 4]>) -> PoisQuery {
 #[derive(Default)]
 pub struct PoisQuery {
     bbox: Option<[f64; 4]>,
+    area: Option<String>,
+    tile: Option<[f64; 4]>,
+    partitions: usize,
     tables: Vec<TableQuery>,
 }
 
@@ -54,13 +75,97 @@ impl PoisQuery {
         self
     }
 
+    /// Only select POIs which are located in an area, given as a GeoJSON
+    /// geometry.
+    pub fn area(mut self, geojson: String) -> Self {
+        self.area = Some(geojson);
+        self
+    }
+
+    /// Only select POIs which are located in a tile, unlike the bounding box,
+    /// maximal bounds are exclusive and the location of a POI is a single
+    /// point so that a POI can't be part of two contiguous tiles.
+    pub fn tile(mut self, tile: [f64; 4]) -> Self {
+        self.tile = Some(tile);
+        self
+    }
+
+    /// Split partitioned tables into this number of scans, see
+    /// `TableQuery::partitioned`.
+    pub fn partitions(mut self, partitions: usize) -> Self {
+        self.partitions = partitions;
+        self
+    }
+
     pub fn with_table(mut self, table_query: TableQuery) -> Self {
         self.tables.push(table_query);
         self
     }
 
+    /// Build a single query selecting POIs from all tables.
     pub fn build(&self) -> String {
-        let mut result = format!(
+        let tables: Vec<_> = self.tables.iter().map(TableQuery::build).collect();
+        self.build_select(&tables.join(" UNION ALL "), Vec::new())
+    }
+
+    /// Build a list of independent queries which can be run concurrently and
+    /// together select POIs from all tables. There is one query per table,
+    /// except for partitioned tables which are split into bands of longitude.
+    pub fn build_scans(&self) -> Vec<String> {
+        let mut scans = Vec::new();
+
+        for table in &self.tables {
+            let table_query = table.build();
+
+            if !table.partitioned || self.partitions <= 1 {
+                scans.push(self.build_select(&table_query, Vec::new()));
+                continue;
+            }
+
+            let [min_lon, _, max_lon, _] =
+                self.tile.or(self.bbox).unwrap_or([-180., -90., 180., 90.]);
+
+            let band_width = (max_lon - min_lon) / self.partitions as f64;
+
+            for part in 0..self.partitions {
+                // Bounds of extreme bands are left open so that no POI is
+                // missed because of rounding errors.
+                let mut conditions = Vec::new();
+                let band_min = min_lon + part as f64 * band_width;
+                let band_max = min_lon + (part + 1) as f64 * band_width;
+
+                if part > 0 {
+                    conditions.push(format!("lon >= {band_min}"));
+                }
+
+                if part + 1 < self.partitions {
+                    conditions.push(format!("lon < {band_max}"));
+                }
+
+                scans.push(self.build_select(&table_query, conditions));
+            }
+        }
+
+        scans
+    }
+
+    /// Build one query per table counting the POIs it selects. Addresses are
+    /// not inherited in these queries, which makes them cheaper than scans.
+    pub fn build_counts(&self) -> Vec<String> {
+        self.tables
+            .iter()
+            .map(|table| {
+                format!(
+                    "SELECT COUNT(*) FROM ({}) AS unionall {}",
+                    table.build_source(false),
+                    self.build_where(Vec::new()),
+                )
+            })
+            .collect()
+    }
+
+    fn build_select(&self, tables: &str, conditions: Vec<String>) -> String {
+        let result = format!(
             "
                 SELECT
                     id,
@@ -71,45 +176,68 @@ impl PoisQuery {
                     tags,
                     subclass,
                     mapping_key,
+                    inherited_addr,
                     poi_display_weight(name, subclass, mapping_key, tags)::float as weight
                 FROM
-                    ({}) AS unionall
-            ",
-            self.tables
-                .iter()
-                .map(TableQuery::build)
-                .collect::<Vec<_>>()
-                .join(" UNION ALL ")
+                    ({tables}) AS unionall
+            "
         );
 
-        if let Some([lat1, lon1, lat2, lon2]) = self.bbox {
-            result += format!(
-                "WHERE ST_MakeEnvelope({lat1}, {lon1}, {lat2}, {lon2}, 4326)
+        result + &self.build_where(conditions)
+    }
+
+    fn build_where(&self, mut conditions: Vec<String>) -> String {
+        if let Some([lon1, lat1, lon2, lat2]) = self.bbox {
+            conditions.push(format!(
+                "ST_MakeEnvelope({lon1}, {lat1}, {lon2}, {lat2}, 4326)
                     && st_transform(geometry, 4326)"
-            )
-            .as_str();
+            ));
         }
 
-        result
+        if let Some(area) = &self.area {
+            conditions.push(format!(
+                "ST_Intersects(
+                    ST_SetSRID(ST_GeomFromGeoJSON('{}'), 4326),
+                    ST_SetSRID(ST_MakePoint(lon, lat), 4326)
+                )",
+                area.replace('\'', "''"),
+            ));
+        }
+
+        if let Some([min_lon, min_lat, max_lon, max_lat]) = self.tile {
+            conditions.push(format!(
+                "lon >= {min_lon} AND lon < {max_lon} AND lat >= {min_lat} AND lat < {max_lat}"
+            ));
+        }
+
+        if conditions.is_empty() {
+            return String::new();
+        }
+
+        format!("WHERE {}", conditions.join(" AND "))
     }
 }
 
 pub struct TableQuery {
     table: String,
+    partitioned: bool,
     id_column: String,
     filter: Option<String>,
     override_class: Option<String>,
     override_subclass: Option<String>,
+    inherit_address_from: Vec<String>,
 }
 
 impl TableQuery {
     pub fn new<S: Into<String>>(table: S) -> Self {
         Self {
             table: table.into(),
+            partitioned: false,
             id_column: "global_id_from_imposm(osm_id)".to_string(),
             filter: None,
             override_class: None,
             override_subclass: None,
+            inherit_address_from: Vec::new(),
         }
     }
 
@@ -133,7 +261,72 @@ impl TableQuery {
         self
     }
 
+    /// Mark this table as large enough to be scanned in several parts
+    /// concurrently.
+    pub fn partitioned(mut self) -> Self {
+        self.partitioned = true;
+        self
+    }
+
+    /// Select address tags of the smallest polygon from input tables that
+    /// contains each POI without an address of its own, as column
+    /// `inherited_addr`. Tables must have `geometry` and `tags` columns.
+    pub fn inherit_address_from(mut self, tables: &[String]) -> Self {
+        self.inherit_address_from = tables.to_vec();
+        self
+    }
+
+    fn build_inherited_addr(&self) -> String {
+        if self.inherit_address_from.is_empty() {
+            return "NULL::hstore AS inherited_addr".to_string();
+        }
+
+        let sql_array = |items: &[&str]| {
+            let items: Vec<_> = items.iter().map(|item| format!("'{item}'")).collect();
+            format!("ARRAY[{}]", items.join(", "))
+        };
+
+        let parents = self
+            .inherit_address_from
+            .iter()
+            .map(|table| format!("SELECT geometry, tags FROM {table}"))
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ");
+
+        format!(
+            "
+                CASE
+                    WHEN source.tags ?| {address_tags} THEN NULL
+                    ELSE (
+                        SELECT slice(parent.tags, {inherited_tags})
+                        FROM ({parents}) AS parent
+                        WHERE
+                            parent.geometry && source.geometry
+                            AND parent.tags ?| {address_tags}
+                            AND ST_Contains(parent.geometry, ST_PointOnSurface(source.geometry))
+                        ORDER BY ST_Area(parent.geometry)
+                        LIMIT 1
+                    )
+                END AS inherited_addr
+            ",
+            address_tags = sql_array(ADDRESS_TAGS),
+            inherited_tags = sql_array(INHERITED_ADDRESS_TAGS),
+        )
+    }
+
     pub fn build(&self) -> String {
+        self.build_source(true)
+    }
+
+    fn build_source(&self, with_inherited_addr: bool) -> String {
+        let inherited_addr = {
+            if with_inherited_addr {
+                self.build_inherited_addr()
+            } else {
+                "NULL::hstore AS inherited_addr".to_string()
+            }
+        };
+
         let mut result = format!(
             "
                 SELECT
@@ -145,8 +338,9 @@ impl TableQuery {
                     tags,
                     {class},
                     {mapping_key},
-                    {subclass}
-                FROM {table}
+                    {subclass},
+                    {inherited_addr}
+                FROM {table} AS source
             ",
             table = self.table,
             id_column = self.id_column,
@@ -0,0 +1,187 @@
+//! Search again for admins and addresses of POIs that are already indexed,
+//! without reading them from postgres. This is useful after admins or
+//! addresses have been updated.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use futures::stream::StreamExt;
+use futures::{try_join, FutureExt};
+use mimir::adapters::secondary::elasticsearch::ElasticsearchStorage;
+use mimir::domain::model::configuration::ContainerConfig;
+use mimir::domain::ports::primary::generate_index::GenerateIndex;
+use mimir::domain::ports::secondary::storage::Storage;
+use places::poi::Poi;
+use serde::Deserialize;
+use tokio::sync::mpsc::channel;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, info_span};
+use tracing_futures::Instrument;
+
+use super::pois::{IndexedPoi, Reuse};
+use super::routing::{
+    container_is_searchable, DEFAULT_NOSEARCH_CONTAINER, DEFAULT_SEARCH_CONTAINER,
+};
+use super::PoiLocator;
+use crate::admins::load_admins;
+use crate::indexes::container_alias;
+use crate::metrics::{self, METRICS};
+use crate::mimir::admin_geofinder_from;
+use crate::progress::Progress;
+use crate::provenance::{with_provenance, Provenance};
+use crate::settings::{
+    AdminsSettings, ElasticsearchSettings, FafnirSettings, MetricsSettings, ProgressSettings,
+    ProvenanceSettings,
+};
+use crate::utils::{get_index_docs_count, start_es_client};
+use crate::validation::{validate_fields, Problem, Validate};
+
+// Size of the buffer of POIs that have to be indexed.
+const CHANNEL_SIZE: usize = 10_000;
+
+/// Settings of a relocation of indexed POIs.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RelocateSettings {
+    pub fafnir: FafnirSettings,
+    pub elasticsearch: ElasticsearchSettings,
+    #[serde(default)]
+    pub admins: AdminsSettings,
+    pub metrics: MetricsSettings,
+    pub progress: ProgressSettings,
+    pub provenance: ProvenanceSettings,
+    pub container_search: ContainerConfig,
+    pub container_nosearch: ContainerConfig,
+    #[serde(default)]
+    pub containers: BTreeMap<String, ContainerConfig>,
+}
+
+impl Validate for RelocateSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        validate_fields(
+            key,
+            &[
+                ("fafnir", &self.fafnir),
+                ("elasticsearch", &self.elasticsearch),
+                ("admins", &self.admins),
+                ("metrics", &self.metrics),
+                ("progress", &self.progress),
+                ("provenance", &self.provenance),
+                ("container-search", &self.container_search),
+                ("container-nosearch", &self.container_nosearch),
+                ("containers", &self.containers),
+            ],
+            problems,
+        );
+    }
+}
+
+/// Read POIs from the indexes of all containers, search again for their
+/// admins and addresses and index them into new indexes.
+pub async fn relocate_pois(settings: RelocateSettings) {
+    metrics::start(&settings.metrics);
+
+    let es = start_es_client(&settings.elasticsearch);
+
+    let mimir_es = ElasticsearchStorage {
+        client: es.clone(),
+        config: settings.elasticsearch.storage,
+    };
+
+    // Admins are loaded again, they may be the reason for a relocation
+    let admins = load_admins(&es, &mimir_es, &settings.admins).await;
+
+    let containers: Vec<_> = [
+        (DEFAULT_SEARCH_CONTAINER, &settings.container_search),
+        (DEFAULT_NOSEARCH_CONTAINER, &settings.container_nosearch),
+    ]
+    .into_iter()
+    .chain((settings.containers.iter()).map(|(name, container)| (name.as_str(), container)))
+    .collect();
+
+    // The number of documents currently indexed is used to estimate progress
+    let mut total_docs = 0;
+
+    for (_, container) in &containers {
+        total_docs += get_index_docs_count(&es, container_alias(container))
+            .await
+            .unwrap_or(0);
+    }
+
+    let progress = Progress::new(Some(total_docs))
+        .stage("locate", "POIs", || METRICS.pois_located.get())
+        .stage("index", "docs", || METRICS.container_documents.sum())
+        .start(Duration::from_secs(settings.progress.interval_secs));
+
+    let poi_indexes = (containers.iter())
+        .map(|(name, container)| (name.to_string(), container_alias(container)))
+        .collect();
+
+    // Addresses may have changed, thus a reverse is always performed again
+    let locator = PoiLocator::new(
+        es,
+        admin_geofinder_from(admins),
+        poi_indexes,
+        Reuse::Nothing,
+        &settings.fafnir,
+    );
+
+    // Documents keep the input they were imported from
+    let provenance = Provenance::new(&settings.provenance, "osm", None);
+
+    for (name, container) in containers {
+        let alias = container_alias(container);
+        let (send, recv) = channel(CHANNEL_SIZE);
+
+        // POIs of a container fed by routes that don't tell if they are
+        // searchable are assumed to be searchable if they have a name.
+        let is_searchable = container_is_searchable(&settings.fafnir.routes, name);
+
+        let index_task = mimir_es
+            .generate_index(container, ReceiverStream::new(recv))
+            .map(|res| res.map_err(Into::into));
+
+        let documents = mimir_es
+            .list_documents(alias.clone())
+            .await
+            .unwrap_or_else(|err| panic!("could not list documents of `{alias}`: {err}"))
+            .map(|poi: Result<Poi, _>| {
+                let poi = poi.unwrap_or_else(|err| panic!("got invalid POI from ES: {err}"));
+                let is_searchable = is_searchable.unwrap_or(!poi.name.is_empty());
+                IndexedPoi::from_document(poi, is_searchable)
+            });
+
+        let mut total_nb_pois: usize = 0;
+
+        let relocate_task = with_provenance(locator.locate(documents, None), provenance.clone())
+            .instrument(info_span!("relocate POIs", container = name))
+            .inspect(|_| {
+                total_nb_pois += 1;
+
+                if total_nb_pois % settings.fafnir.log_indexed_count_interval == 0 {
+                    info!("Number of relocated POIs in {name}: {total_nb_pois}")
+                }
+            })
+            .for_each(move |p| {
+                let send = send.clone();
+
+                async move {
+                    send.send(p.poi)
+                        .await
+                        .unwrap_or_else(|_| panic!("failed to send POI into channel of {name}"));
+
+                    METRICS.container_documents.get(name).inc();
+                    (METRICS.channel_fill.get(name)).set((CHANNEL_SIZE - send.capacity()) as i64);
+                }
+            })
+            .map(Ok::<_, Box<dyn std::error::Error>>);
+
+        let (index, _) =
+            try_join!(index_task, relocate_task).expect("failed to index relocated POIs");
+
+        info!("Created index {index:?} for container {name} with {total_nb_pois} POIs");
+    }
+
+    progress.finish();
+    metrics::finish(&settings.metrics).await;
+}
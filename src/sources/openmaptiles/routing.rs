@@ -0,0 +1,226 @@
+//! Choose the container each POI is indexed into.
+
+use crate::settings::RouteSettings;
+
+use super::pois::IndexedPoi;
+
+/// Name of the container used for searchable POIs that don't match any route.
+pub const DEFAULT_SEARCH_CONTAINER: &str = "search";
+
+/// Name of the container used for other POIs that don't match any route.
+pub const DEFAULT_NOSEARCH_CONTAINER: &str = "nosearch";
+
+/// Get the name of the container a POI must be sent to, which is the
+/// container of the first matching route. POIs that don't match any route are
+/// sent to a default container depending on whether they are searchable.
+pub fn route<'a>(routes: &'a [RouteSettings], poi: &IndexedPoi) -> &'a str {
+    route_with_countries(routes, poi, &poi.poi.country_codes)
+}
+
+/// Same as `route`, with the country codes of the POI given apart, which
+/// allows to route POIs that are not located yet.
+pub fn route_with_countries<'a>(
+    routes: &'a [RouteSettings],
+    poi: &IndexedPoi,
+    country_codes: &[String],
+) -> &'a str {
+    routes
+        .iter()
+        .find(|route| matches_route(route, poi, country_codes))
+        .map(|route| route.container.as_str())
+        .unwrap_or(if poi.is_searchable {
+            DEFAULT_SEARCH_CONTAINER
+        } else {
+            DEFAULT_NOSEARCH_CONTAINER
+        })
+}
+
+/// Check if all POIs routed to a container are searchable, or if none of them
+/// are. Returns `None` if it can't be told from routes.
+pub fn container_is_searchable(routes: &[RouteSettings], container: &str) -> Option<bool> {
+    match container {
+        DEFAULT_SEARCH_CONTAINER => Some(true),
+        DEFAULT_NOSEARCH_CONTAINER => Some(false),
+        _ => {
+            let mut values = (routes.iter())
+                .filter(|route| route.container == container)
+                .map(|route| route.searchable);
+
+            let first = values.next()??;
+            values.all(|value| value == Some(first)).then_some(first)
+        }
+    }
+}
+
+fn matches_route(route: &RouteSettings, poi: &IndexedPoi, country_codes: &[String]) -> bool {
+    let properties = &poi.poi.properties;
+
+    let matches_property = |key: &str, allowed: &[String]| {
+        allowed.is_empty() || (properties.get(key)).is_some_and(|value| allowed.contains(value))
+    };
+
+    let matches_tags = route.tags.iter().all(|(key, expected)| {
+        properties
+            .get(key)
+            .is_some_and(|value| expected == "*" || value == expected)
+    });
+
+    let matches_country = route.countries.is_empty()
+        || (route.countries.iter())
+            .any(|expected| (country_codes.iter()).any(|code| code.eq_ignore_ascii_case(expected)));
+
+    route.searchable.unwrap_or(poi.is_searchable) == poi.is_searchable
+        && matches_property("poi_class", &route.classes)
+        && matches_property("poi_subclass", &route.subclasses)
+        && matches_tags
+        && matches_country
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use places::poi::Poi;
+
+    fn poi(tags: &[(&str, &str)], country_code: &str, is_searchable: bool) -> IndexedPoi {
+        let poi = Poi {
+            properties: (tags.iter())
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            country_codes: vec![country_code.to_string()],
+            ..Default::default()
+        };
+
+        IndexedPoi {
+            poi,
+            is_searchable,
+            invalid_values: Vec::new(),
+            inherited_address: Default::default(),
+            address_method: None,
+        }
+    }
+
+    fn route_to(container: &str) -> RouteSettings {
+        RouteSettings {
+            container: container.to_string(),
+            classes: Vec::new(),
+            subclasses: Vec::new(),
+            tags: Default::default(),
+            countries: Vec::new(),
+            searchable: None,
+        }
+    }
+
+    #[test]
+    fn unmatched_pois_are_routed_by_searchability() {
+        let shop = [("poi_class", "shop")];
+        assert_eq!(
+            route(&[], &poi(&shop, "fr", true)),
+            DEFAULT_SEARCH_CONTAINER
+        );
+        assert_eq!(
+            route(&[], &poi(&shop, "fr", false)),
+            DEFAULT_NOSEARCH_CONTAINER
+        );
+    }
+
+    #[test]
+    fn first_matching_route_is_used() {
+        let routes = [
+            RouteSettings {
+                classes: vec!["shop".to_string()],
+                subclasses: vec!["bakery".to_string()],
+                ..route_to("bakeries")
+            },
+            RouteSettings {
+                classes: vec!["shop".to_string()],
+                ..route_to("shops")
+            },
+        ];
+
+        let bakery = poi(
+            &[("poi_class", "shop"), ("poi_subclass", "bakery")],
+            "fr",
+            true,
+        );
+        let florist = poi(
+            &[("poi_class", "shop"), ("poi_subclass", "florist")],
+            "fr",
+            true,
+        );
+        let school = poi(&[("poi_class", "school")], "fr", true);
+
+        assert_eq!(route(&routes, &bakery), "bakeries");
+        assert_eq!(route(&routes, &florist), "shops");
+        assert_eq!(route(&routes, &school), DEFAULT_SEARCH_CONTAINER);
+    }
+
+    #[test]
+    fn routes_match_tags() {
+        let route = RouteSettings {
+            tags: [("wheelchair", "yes"), ("website", "*")]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ..route_to("accessible")
+        };
+
+        let matches = |tags| matches_route(&route, &poi(tags, "fr", true), &[]);
+        assert!(matches(&[
+            ("wheelchair", "yes"),
+            ("website", "https://bob.fr")
+        ]));
+        assert!(!matches(&[
+            ("wheelchair", "no"),
+            ("website", "https://bob.fr")
+        ]));
+        assert!(!matches(&[("wheelchair", "yes")]));
+    }
+
+    #[test]
+    fn routes_match_countries_and_searchability() {
+        let route = RouteSettings {
+            countries: vec!["FR".to_string()],
+            searchable: Some(false),
+            ..route_to("hidden_fr")
+        };
+
+        let (fr, be) = (["fr".to_string()], ["be".to_string()]);
+        let hidden = poi(&[], "fr", false);
+        let searchable = poi(&[], "fr", true);
+
+        assert!(matches_route(&route, &hidden, &fr));
+        assert!(!matches_route(&route, &hidden, &be));
+        assert!(!matches_route(&route, &searchable, &fr));
+
+        // Country codes given apart are used instead of the ones of the POI
+        let routes = std::slice::from_ref(&route);
+        assert_eq!(route_with_countries(routes, &hidden, &fr), "hidden_fr");
+        assert_eq!(
+            route_with_countries(routes, &hidden, &be),
+            DEFAULT_NOSEARCH_CONTAINER
+        );
+        assert_eq!(route(routes, &hidden), "hidden_fr");
+    }
+
+    #[test]
+    fn searchability_of_containers() {
+        let routes = [
+            RouteSettings {
+                searchable: Some(false),
+                ..route_to("hidden")
+            },
+            route_to("mixed"),
+            RouteSettings {
+                searchable: Some(true),
+                ..route_to("mixed")
+            },
+        ];
+
+        let searchable = |container| container_is_searchable(&routes, container);
+        assert_eq!(searchable(DEFAULT_SEARCH_CONTAINER), Some(true));
+        assert_eq!(searchable(DEFAULT_NOSEARCH_CONTAINER), Some(false));
+        assert_eq!(searchable("hidden"), Some(false));
+        assert_eq!(searchable("mixed"), None);
+        assert_eq!(searchable("unknown"), None);
+    }
+}
@@ -0,0 +1,280 @@
+//! Import POIs, photos and reviews from TripAdvisor's dumps into mimir's
+//! elasticsearch.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_compression::tokio::bufread::GzipDecoder;
+use futures::future;
+use futures::stream::StreamExt;
+use mimir::adapters::secondary::elasticsearch::ElasticsearchStorage;
+use mimir::domain::model::configuration::ContainerConfig;
+use mimir::domain::model::update::UpdateOperation;
+use mimir::domain::ports::primary::generate_index::GenerateIndex;
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, BufReader};
+use tracing::info;
+
+use super::{build_id, read_photos, read_pois, read_reviews, TripAdvisorWeightSettings};
+use crate::admins::load_admins;
+use crate::area::Area;
+use crate::cli::exit_with_problems;
+use crate::metrics::{self, METRICS};
+use crate::mimir::admin_geofinder_from;
+use crate::progress::{CountingReader, Progress};
+use crate::provenance::{with_provenance, Provenance};
+use crate::settings::{
+    AdminsSettings, AreaSettings, ElasticsearchSettings, MetricsSettings, ProgressSettings,
+    ProvenanceSettings,
+};
+use crate::utils::start_es_client;
+use crate::validation::{validate_fields, Problem, Validate};
+
+/// Buffer size use for IO over JSON files
+const JSON_BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct TripAdvisorSettings {
+    /// Path to the gzipped list of properties.
+    pub properties: PathBuf,
+    /// Path to the gzipped list of photos.
+    pub photos: PathBuf,
+    /// Path to the gzipped list of reviews.
+    pub reviews: PathBuf,
+    pub weight: TripAdvisorWeightSettings,
+    #[serde(default)]
+    pub area: AreaSettings,
+}
+
+/// Settings of an import from TripAdvisor's dumps.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportSettings {
+    pub tripadvisor: TripAdvisorSettings,
+    pub elasticsearch: ElasticsearchSettings,
+    #[serde(default)]
+    pub admins: AdminsSettings,
+    pub metrics: MetricsSettings,
+    pub progress: ProgressSettings,
+    pub provenance: ProvenanceSettings,
+    pub container_tripadvisor: ContainerConfig,
+}
+
+impl Validate for ImportSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        validate_fields(
+            key,
+            &[
+                ("tripadvisor", &self.tripadvisor),
+                ("elasticsearch", &self.elasticsearch),
+                ("admins", &self.admins),
+                ("metrics", &self.metrics),
+                ("progress", &self.progress),
+                ("provenance", &self.provenance),
+                ("container-tripadvisor", &self.container_tripadvisor),
+            ],
+            problems,
+        );
+    }
+}
+
+async fn read_gzip_file(path: &Path) -> impl AsyncBufRead {
+    let file = File::open(path)
+        .await
+        .unwrap_or_else(|err| panic!("could not open `{}`: {err}", path.display()));
+
+    // Progress is tracked from the compressed bytes read
+    let file = CountingReader::new(file, &METRICS.bytes_read);
+    let raw = BufReader::with_capacity(JSON_BUFFER_SIZE, file);
+    BufReader::new(GzipDecoder::new(raw))
+}
+
+/// Index POIs from TripAdvisor, then add their photos and reviews before the
+/// index is published.
+pub async fn import_tripadvisor(settings: ImportSettings) {
+    metrics::start(&settings.metrics);
+
+    // Connect to mimir ES
+
+    let mimir_es = ElasticsearchStorage {
+        client: start_es_client(&settings.elasticsearch),
+        config: settings.elasticsearch.storage,
+    };
+
+    let admins = load_admins(&mimir_es.client, &mimir_es, &settings.admins).await;
+    let area = Area::from_settings(&settings.tripadvisor.area, "tripadvisor.area", &admins)
+        .unwrap_or_else(|problems| exit_with_problems(&problems));
+    let admin_geofinder = admin_geofinder_from(admins);
+
+    // The size of compressed input files is used to estimate progress
+    let mut total_size = 0;
+
+    for path in [
+        &settings.tripadvisor.properties,
+        &settings.tripadvisor.photos,
+        &settings.tripadvisor.reviews,
+    ] {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .unwrap_or_else(|err| panic!("could not open `{}`: {err}", path.display()));
+
+        total_size += metadata.len();
+    }
+
+    let progress = Progress::new(Some(total_size))
+        .stage("read", "bytes", || METRICS.bytes_read.get())
+        .stage("index", "POIs", || METRICS.container_documents.sum())
+        .start(Duration::from_secs(settings.progress.interval_secs));
+
+    // Init Index
+    let index_generator = mimir_es
+        .init_container(&settings.container_tripadvisor)
+        .await
+        .expect("could not create index");
+
+    // Insert POIs
+    let mut indexed_documents = HashSet::new();
+
+    let provenance = Provenance::new(
+        &settings.provenance,
+        "tripadvisor",
+        Some(settings.tripadvisor.properties.display().to_string()),
+    );
+
+    let index_generator = {
+        let raw_json = read_gzip_file(&settings.tripadvisor.properties).await;
+        let mut count_ok: u64 = 0;
+        let mut count_errors: HashMap<_, u64> = HashMap::new();
+        let mut count_invalid_values: HashMap<_, u64> = HashMap::new();
+        let mut count_outside_area: u64 = 0;
+
+        let pois = read_pois(raw_json, admin_geofinder, settings.tripadvisor.weight)
+            .filter_map(|poi| {
+                future::ready(
+                    poi.map_err(|err| *count_errors.entry(err).or_insert(0) += 1)
+                        .ok(),
+                )
+            })
+            .filter(|(_, poi, _)| {
+                let inside = match &area {
+                    Some(area) => area.contains(poi.coord.lon(), poi.coord.lat()),
+                    None => true,
+                };
+
+                if !inside {
+                    count_outside_area += 1;
+                }
+
+                future::ready(inside)
+            });
+
+        let pois = with_provenance(pois, provenance).map(|(ta_id, poi, invalid_values)| {
+            for key in invalid_values {
+                METRICS.errors.get(&format!("invalid_{key}")).inc();
+                *count_invalid_values.entry(key).or_insert(0) += 1;
+            }
+
+            indexed_documents.insert(ta_id);
+            METRICS.container_documents.get("tripadvisor").inc();
+            count_ok += 1;
+            poi
+        });
+
+        let index_generator = index_generator
+            .insert_documents(pois)
+            .await
+            .expect("could not insert POIs into index");
+
+        info!("Parsed {count_ok} POIs");
+        info!("Skipped POIs: {count_errors:?}");
+        info!("POIs outside of imported area: {count_outside_area}");
+        info!("Invalid values: {count_invalid_values:?}");
+        index_generator
+    };
+
+    // Insert Photos
+    let index_generator = {
+        let raw_xml = read_gzip_file(&settings.tripadvisor.photos).await;
+        let mut count_ok: u64 = 0;
+        let mut count_errors: HashMap<_, u64> = HashMap::new();
+
+        let photos = read_photos(raw_xml)
+            .filter_map(|photos| {
+                future::ready(
+                    photos
+                        .map_err(|err| *count_errors.entry(err).or_insert(0) += 1)
+                        .ok(),
+                )
+            })
+            .filter(|(ta_id, _)| future::ready(indexed_documents.contains(ta_id)))
+            .map(|(ta_id, url)| {
+                let op = UpdateOperation::Set {
+                    ident: "properties.image".to_string(),
+                    value: url,
+                };
+
+                count_ok += 1;
+                (build_id(ta_id), vec![op])
+            });
+
+        let index_generator = index_generator
+            .update_documents(photos)
+            .await
+            .expect("could not update documents from index");
+
+        info!("Parsed {count_ok} Photos");
+        info!("Skipped Photos: {count_errors:?}");
+        index_generator
+    };
+
+    // Insert Reviews
+    let index_generator = {
+        let raw_xml = read_gzip_file(&settings.tripadvisor.reviews).await;
+        let mut count_ok: u64 = 0;
+        let mut count_errors: HashMap<_, u64> = HashMap::new();
+
+        let reviews = read_reviews(raw_xml)
+            .filter_map(|reviews| {
+                future::ready(
+                    reviews
+                        .map_err(|err| *count_errors.entry(err).or_insert(0) += 1)
+                        .ok(),
+                )
+            })
+            .filter(|(ta_id, _)| future::ready(indexed_documents.contains(ta_id)))
+            .map(|(ta_id, reviews)| {
+                count_ok += 1;
+
+                let update_operations = reviews
+                    .into_iter()
+                    .enumerate()
+                    .map(|(review_id, review)| UpdateOperation::Set {
+                        ident: format!("properties.ta:reviews:{review_id}"),
+                        value: review,
+                    })
+                    .collect();
+
+                (build_id(ta_id), update_operations)
+            });
+
+        let index_generator = index_generator
+            .update_documents(reviews)
+            .await
+            .expect("could not update documents from index");
+
+        info!("Parsed {count_ok} reviews");
+        info!("Skipped Reviews: {count_errors:?}");
+        index_generator
+    };
+
+    // Publish index
+    index_generator
+        .publish()
+        .await
+        .expect("could not publish index");
+
+    progress.finish();
+    metrics::finish(&settings.metrics).await;
+}
@@ -1,3 +1,4 @@
+pub mod import;
 pub mod parse;
 pub mod photos;
 pub mod pois;
@@ -12,6 +13,8 @@ use serde::Deserialize;
 use std::sync::Arc;
 use tokio::io::AsyncBufRead;
 
+use crate::provenance::{AddressMethod, Record};
+
 /// Number of tokio's blocking thread that can be spawned to parse XML. Keeping
 /// a rather low constant value is fine as the input will be provided by a GZip
 /// decoder, which only runs on a single thread.
@@ -32,6 +35,21 @@ pub fn build_id(ta_id: u32) -> String {
     format!("ta:poi:{ta_id}")
 }
 
+impl Record for (u32, Poi, Vec<&'static str>) {
+    fn source_id(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// Addresses of TripAdvisor's POIs are read from the dump.
+    fn address_method(&self) -> Option<AddressMethod> {
+        self.1.address.as_ref().map(|_| AddressMethod::Tag)
+    }
+
+    fn poi_mut(&mut self) -> &mut Poi {
+        &mut self.1
+    }
+}
+
 fn parse_properties<P, R>(
     input: impl AsyncBufRead + Unpin,
     convert: impl Fn(P) -> R + Sync + Send + 'static,
@@ -71,7 +89,7 @@ pub fn read_pois(
     input: impl AsyncBufRead + Unpin,
     geofinder: AdminGeoFinder,
     weight_settings: TripAdvisorWeightSettings,
-) -> impl Stream<Item = Result<(u32, Poi), pois::convert::BuildError>> {
+) -> impl Stream<Item = Result<(u32, Poi, Vec<&'static str>), pois::convert::BuildError>> {
     parse_properties(input, move |property| {
         pois::convert::build_poi(property, &geofinder, weight_settings)
     })
@@ -9,10 +9,13 @@ use places::i18n_properties::I18nProperties;
 use places::poi::{Poi, PoiType};
 use places::street::Street;
 use places::Address;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use tracing::debug;
 
 use super::models::Property;
+use crate::contact::normalize_contact_properties;
 use crate::langs::COUNTRIES_LANGS;
+use crate::opening_hours;
 use crate::sources::tripadvisor::pois::models::Phone;
 use crate::sources::tripadvisor::{build_id, TripAdvisorWeightSettings};
 
@@ -64,11 +67,13 @@ pub enum BuildError {
     EmptyAdmins,
 }
 
+/// Build a mimir POI from a TripAdvisor property, together with the keys of
+/// properties that were discarded because of an invalid value.
 pub fn build_poi(
     property: Property,
     geofinder: &AdminGeoFinder,
     weight_settings: TripAdvisorWeightSettings,
-) -> Result<(u32, Poi), BuildError> {
+) -> Result<(u32, Poi, Vec<&'static str>), BuildError> {
     let coord = Coord::new(
         property
             .longitude
@@ -168,25 +173,37 @@ pub fn build_poi(
         weight_settings.boost + rating_weight * review_count_weight * (1. - weight_settings.boost);
 
     // Build opening_hours
-    let opening_hours = property
+    let raw_opening_hours = property
         .hours
         .inner
         .into_iter()
         .filter_map(|day| {
             day.time
+                .filter(|times| !times.is_empty())
                 .map(|times| {
                     times
                         .iter()
                         .map(|time| format!("{}-{}", time.open_time, time.close_time))
                         .join(",")
                 })
-                .map(|opening_times| {
-                    format!("{} {opening_times}", day.day_name.get(0..2).unwrap_or(""))
-                })
+                .map(|opening_times| format!("{} {opening_times}", day.day_name))
         })
         .join("; ");
 
-    let properties = [
+    let mut invalid_values = Vec::new();
+
+    let opening_hours = Some(raw_opening_hours)
+        .filter(|x| !x.is_empty())
+        .and_then(|raw| {
+            opening_hours::normalize(&raw)
+                .map_err(|err| {
+                    debug!("invalid opening_hours for {id} ({raw:?}): {err}");
+                    invalid_values.push("opening_hours");
+                })
+                .ok()
+        });
+
+    let mut properties: BTreeMap<_, _> = [
         ("name", Some(name.clone())),
         ("website", property.url),
         (
@@ -199,10 +216,7 @@ pub fn build_poi(
                 })
                 .number,
         ),
-        (
-            "opening_hours",
-            Some(opening_hours).filter(|x| !x.is_empty()),
-        ),
+        ("opening_hours", opening_hours),
         ("poi_class", Some(category)),
         ("poi_subclass", Some(sub_category)),
         ("ta:url", property.ta_url),
@@ -222,6 +236,11 @@ pub fn build_poi(
     )
     .collect();
 
+    invalid_values.extend(normalize_contact_properties(
+        &mut properties,
+        &country_codes,
+    ));
+
     Ok((
         property.id,
         Poi {
@@ -243,6 +262,7 @@ pub fn build_poi(
             context: None,
             full_label_extra,
         },
+        invalid_values,
     ))
 }
 
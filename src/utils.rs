@@ -1,6 +1,25 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use deadpool_postgres::{Manager, Pool};
+use elasticsearch::auth::Credentials;
 use elasticsearch::cat::CatIndicesParts;
+use elasticsearch::cert::{Certificate, CertificateValidation};
+use elasticsearch::http::transport::{
+    Connection, ConnectionPool, SingleNodeConnectionPool, TransportBuilder,
+};
+use elasticsearch::indices::IndicesGetAliasParts;
 use elasticsearch::Elasticsearch;
+use postgres_native_tls::MakeTlsConnector;
+use tokio_postgres::config::SslMode;
 use tracing::warn;
+use url::Url;
+
+use crate::settings::{
+    ElasticsearchAuth, ElasticsearchSettings, PostgresSettings, PostgresSslMode,
+    PostgresTlsSettings,
+};
 
 pub async fn start_postgres_session(
     config: &str,
@@ -18,21 +37,206 @@ pub async fn start_postgres_session(
     Ok(client)
 }
 
+/// Build a pool of connections to postgres, connections are only opened
+/// when they are needed.
+pub fn start_postgres_pool(settings: &PostgresSettings) -> Pool {
+    let mut config: tokio_postgres::Config = settings.url.parse().expect("invalid postgres url");
+    config.ssl_mode(postgres_ssl_mode(settings.tls.sslmode));
+
+    let manager = {
+        if settings.tls.sslmode == PostgresSslMode::Disable {
+            Manager::new(config, tokio_postgres::NoTls)
+        } else {
+            Manager::new(config, build_postgres_tls(&settings.tls))
+        }
+    };
+
+    Pool::builder(manager)
+        .max_size(settings.pool_size)
+        .build()
+        .expect("failed to build postgres pool")
+}
+
+/// Whether connections to postgres must use TLS, the certificate of the
+/// server is validated by the TLS connector.
+fn postgres_ssl_mode(sslmode: PostgresSslMode) -> SslMode {
+    match sslmode {
+        PostgresSslMode::Disable => SslMode::Disable,
+        PostgresSslMode::Prefer => SslMode::Prefer,
+        PostgresSslMode::Require | PostgresSslMode::VerifyCa | PostgresSslMode::VerifyFull => {
+            SslMode::Require
+        }
+    }
+}
+
+fn build_postgres_tls(settings: &PostgresTlsSettings) -> MakeTlsConnector {
+    let read = |path: &Path| {
+        std::fs::read(path)
+            .unwrap_or_else(|err| panic!("could not read `{}`: {err}", path.display()))
+    };
+
+    let mut builder = native_tls::TlsConnector::builder();
+
+    match settings.sslmode {
+        PostgresSslMode::Disable | PostgresSslMode::Prefer | PostgresSslMode::Require => {
+            builder.danger_accept_invalid_certs(true);
+        }
+        PostgresSslMode::VerifyCa => {
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        PostgresSslMode::VerifyFull => {}
+    }
+
+    if let Some(path) = &settings.ca_certificate {
+        let certificate =
+            native_tls::Certificate::from_pem(&read(path)).expect("invalid postgres CA");
+
+        builder.add_root_certificate(certificate);
+    }
+
+    match (&settings.client_certificate, &settings.client_key) {
+        (Some(certificate), Some(key)) => {
+            let identity = native_tls::Identity::from_pkcs8(&read(certificate), &read(key))
+                .expect("invalid postgres client certificate");
+
+            builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => panic!("postgres client certificate and key must be provided together"),
+    }
+
+    let connector = builder
+        .build()
+        .expect("failed to build postgres TLS connector");
+
+    MakeTlsConnector::new(connector)
+}
+
+/// Pool of connections to the nodes of a cluster, which are used in turn.
+#[derive(Clone, Debug)]
+struct RoundRobinPool {
+    connections: Vec<Connection>,
+    next: Arc<AtomicUsize>,
+}
+
+impl RoundRobinPool {
+    fn new(urls: impl IntoIterator<Item = Url>) -> Self {
+        let connections: Vec<_> = urls.into_iter().map(Connection::new).collect();
+        assert!(!connections.is_empty(), "no elasticsearch node");
+
+        Self {
+            connections,
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl ConnectionPool for RoundRobinPool {
+    fn next(&self) -> &Connection {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.connections[index % self.connections.len()]
+    }
+}
+
+fn es_credentials(auth: &ElasticsearchAuth) -> Credentials {
+    match auth {
+        ElasticsearchAuth::Basic { username, password } => {
+            Credentials::Basic(username.clone(), password.clone())
+        }
+        ElasticsearchAuth::ApiKey { id, api_key } => {
+            Credentials::ApiKey(id.clone(), api_key.clone())
+        }
+    }
+}
+
+/// Validate certificates of the nodes with the authority stored at `path`.
+fn es_cert_validation(path: &Path) -> CertificateValidation {
+    let raw = std::fs::read(path)
+        .unwrap_or_else(|err| panic!("could not read CA certificate `{}`: {err}", path.display()));
+
+    let certificate = Certificate::from_pem(&raw).expect("invalid CA certificate");
+    CertificateValidation::Full(certificate)
+}
+
+/// Build a client to elasticsearch, it is meant to be shared by all requests
+/// performed during an import.
+pub fn start_es_client(settings: &ElasticsearchSettings) -> Elasticsearch {
+    let mut builder = {
+        if settings.nodes.is_empty() {
+            TransportBuilder::new(SingleNodeConnectionPool::new(settings.storage.url.clone()))
+        } else {
+            let urls = std::iter::once(&settings.storage.url)
+                .chain(&settings.nodes)
+                .cloned();
+
+            TransportBuilder::new(RoundRobinPool::new(urls))
+        }
+    };
+
+    if let Some(auth) = &settings.auth {
+        builder = builder.auth(es_credentials(auth));
+    }
+
+    if let Some(path) = &settings.ca_certificate {
+        builder = builder.cert_validation(es_cert_validation(path));
+    }
+
+    let transport = builder
+        .build()
+        .expect("failed to initialize Elasticsearch transport");
+
+    Elasticsearch::new(transport)
+}
+
+/// Get the name of the index an alias points to, if it points to a single
+/// index.
+pub async fn get_alias_index(es: &Elasticsearch, alias: impl AsRef<str>) -> Option<String> {
+    let res = es
+        .indices()
+        .get_alias(IndicesGetAliasParts::Name(&[alias.as_ref()]))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status_code())
+        .map_err(|err| warn!("failed to query ES for alias: {err:?}"))
+        .ok()?;
+
+    let raw: serde_json::Value = res
+        .json()
+        .await
+        .map_err(|err| warn!("failed to load ES response for alias: {err:?}"))
+        .ok()?;
+
+    match raw.as_object() {
+        Some(indexes) if indexes.len() == 1 => indexes.keys().next().cloned(),
+        _ => None,
+    }
+}
+
 /// Get creation date of an index as a timestamp.
 pub async fn get_index_creation_date(es: &Elasticsearch, index: impl AsRef<str>) -> Option<u64> {
+    get_index_stat(es, index.as_ref(), "creation.date").await
+}
+
+/// Get the number of documents stored in an index.
+pub async fn get_index_docs_count(es: &Elasticsearch, index: impl AsRef<str>) -> Option<u64> {
+    get_index_stat(es, index.as_ref(), "docs.count").await
+}
+
+/// Read a numeric column of the cat indices API for a single index.
+async fn get_index_stat(es: &Elasticsearch, index: &str, column: &str) -> Option<u64> {
     let res = es
         .cat()
-        .indices(CatIndicesParts::Index(&[index.as_ref()]))
-        .h(&["creation.date"])
+        .indices(CatIndicesParts::Index(&[index]))
+        .h(&[column])
         .send()
         .await
-        .map_err(|err| warn!("failed to query ES for creation date: {err:?}"))
+        .map_err(|err| warn!("failed to query ES for {column}: {err:?}"))
         .ok()?;
 
     let raw = res
         .text()
         .await
-        .map_err(|err| warn!("failed to load ES response for creation date: {err:?}"))
+        .map_err(|err| warn!("failed to load ES response for {column}: {err:?}"))
         .ok()?;
 
     if raw.is_empty() {
@@ -41,6 +245,138 @@ pub async fn get_index_creation_date(es: &Elasticsearch, index: impl AsRef<str>)
 
     raw.trim()
         .parse()
-        .map_err(|err| warn!("invalid index creation timestamp: {err:?}"))
+        .map_err(|err| warn!("invalid value for {column}: {err:?}"))
         .ok()
 }
+
+/// Format a timestamp in milliseconds as a UTC date.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let secs = timestamp / 1000;
+    let days = (secs / 86_400) as i64;
+    let (hour, min, sec) = (secs % 86_400 / 3600, secs % 3600 / 60, secs % 60);
+
+    // Convert days since epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{min:02}:{sec:02} UTC")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(raw: &str) -> Url {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn postgres_tls_is_required_unless_disabled_or_preferred() {
+        let modes = [
+            (PostgresSslMode::Disable, SslMode::Disable),
+            (PostgresSslMode::Prefer, SslMode::Prefer),
+            (PostgresSslMode::Require, SslMode::Require),
+            (PostgresSslMode::VerifyCa, SslMode::Require),
+            (PostgresSslMode::VerifyFull, SslMode::Require),
+        ];
+
+        for (sslmode, expected) in modes {
+            assert_eq!(postgres_ssl_mode(sslmode), expected);
+        }
+    }
+
+    #[test]
+    fn nodes_are_used_in_turn() {
+        let pool = RoundRobinPool::new([url("http://node1:9200"), url("http://node2:9200")]);
+        let other = pool.clone();
+
+        let hosts: Vec<_> = (0..4)
+            .map(|i| {
+                if i % 2 == 0 {
+                    pool.next()
+                } else {
+                    other.next()
+                }
+            })
+            .map(|connection| format!("{connection:?}"))
+            .collect();
+
+        assert!(hosts[0].contains("node1") && hosts[2].contains("node1"));
+        assert!(hosts[1].contains("node2") && hosts[3].contains("node2"));
+    }
+
+    #[test]
+    fn auth_settings_are_converted_to_credentials() {
+        let basic = ElasticsearchAuth::Basic {
+            username: "bob".to_string(),
+            password: "secret".to_string(),
+        };
+
+        assert!(matches!(
+            es_credentials(&basic),
+            Credentials::Basic(username, password) if username == "bob" && password == "secret"
+        ));
+
+        let api_key = ElasticsearchAuth::ApiKey {
+            id: "fafnir".to_string(),
+            api_key: "key".to_string(),
+        };
+
+        assert!(matches!(
+            es_credentials(&api_key),
+            Credentials::ApiKey(id, api_key) if id == "fafnir" && api_key == "key"
+        ));
+    }
+
+    #[test]
+    fn ca_certificate_is_used_to_validate_nodes() {
+        let validation = es_cert_validation(Path::new("tests/data/ca.pem"));
+        assert!(matches!(validation, CertificateValidation::Full(_)));
+    }
+
+    #[test]
+    #[should_panic(expected = "could not read CA certificate")]
+    fn missing_ca_certificate() {
+        es_cert_validation(Path::new("tests/data/missing.pem"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid CA certificate")]
+    fn invalid_ca_certificate() {
+        es_cert_validation(Path::new("Cargo.toml"));
+    }
+
+    #[test]
+    fn timestamps_are_formatted_as_utc_dates() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(
+            format_timestamp(1_655_210_096_123),
+            "2022-06-14 12:34:56 UTC"
+        );
+        assert_eq!(
+            format_timestamp(1_735_689_600_000),
+            "2025-01-01 00:00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn leap_days_are_formatted() {
+        assert_eq!(format_timestamp(951_782_400_000), "2000-02-29 00:00:00 UTC");
+        assert_eq!(
+            format_timestamp(1_709_251_199_999),
+            "2024-02-29 23:59:59 UTC"
+        );
+        assert_eq!(
+            format_timestamp(4_102_444_800_000),
+            "2100-01-01 00:00:00 UTC"
+        );
+    }
+}
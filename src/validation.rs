@@ -0,0 +1,627 @@
+//! Semantic validation of settings. It is performed once settings are loaded
+//! and before any connection is made, so that all problems are reported at
+//! once instead of failing deep into an import.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use mimir::domain::model::configuration::ContainerConfig;
+
+use crate::langs::is_known_lang;
+use crate::provenance::PROVENANCE_FIELDS;
+use crate::settings::{
+    AdminsSettings, AreaSettings, ElasticsearchSettings, FafnirSettings, MetricsSettings,
+    PostgresSettings, PostgresSslMode, PostgresTlsSettings, ProgressSettings, ProvenanceSettings,
+    RetentionSettings,
+};
+use crate::sources::tripadvisor::import::TripAdvisorSettings;
+
+// Characters that elasticsearch forbids in index names
+const INVALID_INDEX_CHARS: &[char] = &['\\', '/', '*', '?', '"', '<', '>', '|', ' ', ',', '#', ':'];
+
+/// A problem found in settings, with the path of the offending key. The key
+/// is empty for problems of the configuration as a whole.
+#[derive(Debug)]
+pub struct Problem {
+    pub key: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "`{}`: {}", self.key, self.message)
+        }
+    }
+}
+
+/// Settings that can be checked before they are used.
+pub trait Validate {
+    /// Push problems found in these settings, which are located at `key` in
+    /// the configuration.
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>);
+}
+
+/// Collect all problems of settings loaded from the root of the
+/// configuration.
+pub fn validate_settings(settings: &impl Validate) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    settings.validate("", &mut problems);
+    problems
+}
+
+/// Validate fields of settings located at `key`, given with their name.
+pub fn validate_fields(key: &str, fields: &[(&str, &dyn Validate)], problems: &mut Vec<Problem>) {
+    for (field, settings) in fields {
+        settings.validate(&join_key(key, field), problems);
+    }
+}
+
+/// Path of a field of settings located at `key`.
+pub fn join_key(key: &str, field: impl fmt::Display) -> String {
+    if key.is_empty() {
+        field.to_string()
+    } else {
+        format!("{key}.{field}")
+    }
+}
+
+/// Push a problem if a numeric setting is zero.
+pub fn check_positive(key: &str, field: &str, value: u64, problems: &mut Vec<Problem>) {
+    if value == 0 {
+        problems.push(Problem::new(join_key(key, field), "must be greater than 0"));
+    }
+}
+
+/// Push a problem if a path doesn't lead to an existing file.
+pub fn check_file(key: String, path: &Path, problems: &mut Vec<Problem>) {
+    if !path.is_file() {
+        let message = format!("`{}` is not an existing file", path.display());
+        problems.push(Problem::new(key, message));
+    }
+}
+
+/// Push a problem for each language code that is not known.
+pub fn check_langs(key: &str, langs: &[String], problems: &mut Vec<Problem>) {
+    for (i, lang) in langs.iter().enumerate() {
+        if !is_known_lang(lang) {
+            problems.push(Problem::new(
+                join_key(key, format!("langs[{i}]")),
+                format!("unknown language code `{lang}`"),
+            ));
+        }
+    }
+}
+
+impl<T: Validate> Validate for BTreeMap<String, T> {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        for (name, value) in self {
+            value.validate(&join_key(key, name), problems);
+        }
+    }
+}
+
+impl Validate for FafnirSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        if let Some([lon1, lat1, lon2, lat2]) = self.bounding_box {
+            let bbox_key = join_key(key, "bounding_box");
+
+            if ![lon1, lon2].iter().all(|lon| (-180. ..=180.).contains(lon)) {
+                problems.push(Problem::new(&bbox_key, "longitudes must be in [-180, 180]"));
+            }
+
+            if ![lat1, lat2].iter().all(|lat| (-90. ..=90.).contains(lat)) {
+                problems.push(Problem::new(&bbox_key, "latitudes must be in [-90, 90]"));
+            }
+
+            if lon1 >= lon2 || lat1 >= lat2 {
+                problems.push(Problem::new(
+                    &bbox_key,
+                    "expected format [lon1, lat1, lon2, lat2] with lon1 < lon2 and lat1 < lat2",
+                ));
+            }
+        }
+
+        self.area.validate(&join_key(key, "area"), problems);
+
+        check_langs(key, &self.langs, problems);
+
+        if self.skip_unchanged_pois && !self.skip_reverse {
+            problems.push(Problem::new(
+                join_key(key, "skip_unchanged_pois"),
+                format!("requires `{}` to be enabled", join_key(key, "skip_reverse")),
+            ));
+        }
+
+        // Table names are inserted as is in SQL queries
+        for (i, table) in self.inherit_address_from.iter().enumerate() {
+            let is_identifier = table.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && (table.chars()).all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+
+            if !is_identifier {
+                problems.push(Problem::new(
+                    join_key(key, format!("inherit_address_from[{i}]")),
+                    format!("`{table}` is not a valid table name"),
+                ));
+            }
+        }
+
+        for (field, value) in [
+            ("concurrent_blocks", self.concurrent_blocks),
+            ("scan_partitions", self.scan_partitions),
+            ("max_query_batch_size", self.max_query_batch_size),
+            (
+                "log_indexed_count_interval",
+                self.log_indexed_count_interval,
+            ),
+        ] {
+            check_positive(key, field, value as u64, problems);
+        }
+
+        if self.address_index.enabled {
+            let address_index_key = join_key(key, "address_index");
+            let max_addresses = self.address_index.max_addresses as u64;
+            check_positive(&address_index_key, "max_addresses", max_addresses, problems);
+        }
+
+        if self.throttle.adaptive {
+            let throttle_key = join_key(key, "throttle");
+            let min_batch_size = self.throttle.min_batch_size;
+            check_positive(
+                &throttle_key,
+                "min_batch_size",
+                min_batch_size as u64,
+                problems,
+            );
+
+            if min_batch_size > self.max_query_batch_size {
+                problems.push(Problem::new(
+                    join_key(&throttle_key, "min_batch_size"),
+                    format!(
+                        "must not be greater than `{}`",
+                        join_key(key, "max_query_batch_size"),
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+impl Validate for AreaSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        if let Some(path) = &self.geojson {
+            check_file(join_key(key, "geojson"), path, problems);
+        }
+
+        for (i, country) in self.countries.iter().enumerate() {
+            if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+                problems.push(Problem::new(
+                    join_key(key, format!("countries[{i}]")),
+                    format!("`{country}` is not a two-letter country code"),
+                ));
+            }
+        }
+    }
+}
+
+impl Validate for TripAdvisorSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        for (field, path) in [
+            ("properties", &self.properties),
+            ("photos", &self.photos),
+            ("reviews", &self.reviews),
+        ] {
+            check_file(join_key(key, field), path, problems);
+        }
+
+        let weight_key = join_key(key, "weight");
+        let high_review_count = self.weight.high_review_count;
+
+        if !high_review_count.is_finite() || high_review_count <= 0. {
+            problems.push(Problem::new(
+                join_key(&weight_key, "high_review_count"),
+                "must be greater than 0",
+            ));
+        }
+
+        if !(0. ..=1.).contains(&self.weight.boost) {
+            problems.push(Problem::new(
+                join_key(&weight_key, "boost"),
+                "must be in [0, 1]",
+            ));
+        }
+
+        self.area.validate(&join_key(key, "area"), problems);
+    }
+}
+
+impl Validate for ContainerConfig {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        for (field, value) in [("name", &self.name), ("dataset", &self.dataset)] {
+            let is_valid = !value.is_empty()
+                && !value.starts_with(['_', '-', '+'])
+                && !value.contains(INVALID_INDEX_CHARS)
+                && !value.chars().any(|c| c.is_uppercase());
+
+            if !is_valid {
+                problems.push(Problem::new(
+                    join_key(key, field),
+                    format!("`{value}` can't be part of an index name"),
+                ));
+            }
+        }
+    }
+}
+
+impl Validate for AdminsSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        if let Some(path) = &self.cosmogony {
+            check_file(join_key(key, "cosmogony"), path, problems);
+        }
+
+        check_langs(key, &self.langs, problems);
+    }
+}
+
+impl Validate for PostgresSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        if let Err(err) = self.url.parse::<tokio_postgres::Config>() {
+            problems.push(Problem::new(
+                join_key(key, "url"),
+                format!("invalid url: {err}"),
+            ));
+        }
+
+        check_positive(key, "pool_size", self.pool_size as u64, problems);
+        let tls_key = join_key(key, "tls");
+
+        self.tls.validate(&tls_key, problems);
+    }
+}
+
+impl Validate for RetentionSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        // At least one previous index is needed to rollback
+        check_positive(key, "keep_previous", self.keep_previous as u64, problems);
+    }
+}
+
+impl Validate for PostgresTlsSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        for (field, path) in [
+            ("ca_certificate", &self.ca_certificate),
+            ("client_certificate", &self.client_certificate),
+            ("client_key", &self.client_key),
+        ] {
+            if let Some(path) = path {
+                if self.sslmode == PostgresSslMode::Disable {
+                    problems.push(Problem::new(
+                        join_key(key, field),
+                        "TLS is disabled, set `sslmode` to use certificates",
+                    ));
+                }
+
+                check_file(join_key(key, field), path, problems);
+            }
+        }
+
+        if self.client_certificate.is_some() != self.client_key.is_some() {
+            problems.push(Problem::new(
+                join_key(key, "client_key"),
+                "client certificate and key must be provided together",
+            ));
+        }
+    }
+}
+
+impl Validate for ElasticsearchSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        if let Some(path) = &self.ca_certificate {
+            check_file(join_key(key, "ca_certificate"), path, problems);
+        }
+    }
+}
+
+impl Validate for MetricsSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        if self.textfile.is_some() {
+            check_positive(
+                key,
+                "textfile_interval_secs",
+                self.textfile_interval_secs,
+                problems,
+            );
+        }
+    }
+}
+
+impl Validate for ProgressSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        check_positive(key, "interval_secs", self.interval_secs, problems);
+    }
+}
+
+impl Validate for ProvenanceSettings {
+    fn validate(&self, key: &str, problems: &mut Vec<Problem>) {
+        for (field, value) in [("run_id", &self.run_id), ("snapshot", &self.snapshot)] {
+            if value.as_deref() == Some("") {
+                problems.push(Problem::new(join_key(key, field), "must not be empty"));
+            }
+        }
+
+        for (i, field) in self.exclude.iter().enumerate() {
+            if !PROVENANCE_FIELDS.contains(&field.as_str()) {
+                problems.push(Problem::new(
+                    join_key(key, format!("exclude[{i}]")),
+                    format!("unknown provenance property `{field}`"),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem_keys(settings: &impl Validate, key: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        settings.validate(key, &mut problems);
+        problems.into_iter().map(|problem| problem.key).collect()
+    }
+
+    fn fafnir_settings(overrides: serde_json::Value) -> FafnirSettings {
+        let mut settings = serde_json::json!({
+            "langs": ["fr", "en"],
+            "skip_reverse": true,
+            "skip_reverse_on_addr_update": false,
+            "skip_unchanged_pois": false,
+            "inherit_address_from": [],
+            "concurrent_blocks": 4,
+            "scan_partitions": 8,
+            "max_query_batch_size": 5000,
+            "query_cache_size": 10000,
+            "log_indexed_count_interval": 100000,
+            "reverse": {
+                "default": { "addr_distance": "50m", "street_distance": "1km" },
+            },
+            "address_index": { "enabled": true, "max_addresses": 1000 },
+            "throttle": {
+                "adaptive": true,
+                "target_latency_ms": 1000,
+                "min_batch_size": 100,
+                "max_queries_per_second": 0,
+            },
+        });
+
+        for (key, value) in overrides.as_object().expect("overrides must be an object") {
+            settings[key] = value.clone();
+        }
+
+        serde_json::from_value(settings).expect("invalid fafnir settings")
+    }
+
+    #[test]
+    fn keys_are_joined_with_dots() {
+        assert_eq!(join_key("", "fafnir"), "fafnir");
+        assert_eq!(join_key("fafnir", "langs[0]"), "fafnir.langs[0]");
+        assert_eq!(join_key("fafnir.throttle", 3), "fafnir.throttle.3");
+    }
+
+    #[test]
+    fn problems_are_displayed_with_their_key() {
+        let problem = Problem::new("fafnir.langs[0]", "unknown language code `xx`");
+        assert_eq!(
+            problem.to_string(),
+            "`fafnir.langs[0]`: unknown language code `xx`"
+        );
+
+        let problem = Problem::new("", "could not read configuration");
+        assert_eq!(problem.to_string(), "could not read configuration");
+    }
+
+    #[test]
+    fn default_fafnir_settings_are_valid() {
+        let settings = fafnir_settings(serde_json::json!({}));
+        assert!(problem_keys(&settings, "fafnir").is_empty());
+    }
+
+    #[test]
+    fn bounding_box_must_be_ordered_coordinates() {
+        let settings = fafnir_settings(serde_json::json!({ "bounding_box": [2., 48., 3., 49.] }));
+        assert!(problem_keys(&settings, "fafnir").is_empty());
+
+        let settings = fafnir_settings(serde_json::json!({ "bounding_box": [3., 48., 2., 49.] }));
+        assert_eq!(problem_keys(&settings, "fafnir"), ["fafnir.bounding_box"]);
+
+        // Coordinates out of range and in the wrong order
+        let settings =
+            fafnir_settings(serde_json::json!({ "bounding_box": [200., 100., 3., 49.] }));
+        assert_eq!(
+            problem_keys(&settings, "fafnir"),
+            ["fafnir.bounding_box"; 3]
+        );
+    }
+
+    #[test]
+    fn fafnir_settings_are_checked() {
+        let settings = fafnir_settings(serde_json::json!({
+            "langs": ["fr", "klingon"],
+            "skip_reverse": false,
+            "skip_unchanged_pois": true,
+            "inherit_address_from": ["osm_poi_polygon", "osm; DROP TABLE osm"],
+            "scan_partitions": 0,
+        }));
+
+        assert_eq!(
+            problem_keys(&settings, "fafnir"),
+            [
+                "fafnir.langs[1]",
+                "fafnir.skip_unchanged_pois",
+                "fafnir.inherit_address_from[1]",
+                "fafnir.scan_partitions",
+            ]
+        );
+    }
+
+    #[test]
+    fn throttle_min_batch_size_must_fit_in_batches() {
+        let throttle = serde_json::json!({
+            "adaptive": true,
+            "target_latency_ms": 1000,
+            "min_batch_size": 10000,
+            "max_queries_per_second": 0,
+        });
+
+        let settings = fafnir_settings(serde_json::json!({ "throttle": throttle.clone() }));
+        assert_eq!(
+            problem_keys(&settings, "fafnir"),
+            ["fafnir.throttle.min_batch_size"]
+        );
+
+        // The min size of batches is only used by the adaptive throttle
+        let mut throttle = throttle;
+        throttle["adaptive"] = false.into();
+        let settings = fafnir_settings(serde_json::json!({ "throttle": throttle }));
+        assert!(problem_keys(&settings, "fafnir").is_empty());
+    }
+
+    #[test]
+    fn area_is_made_of_existing_files_and_country_codes() {
+        let area = AreaSettings {
+            geojson: Some("missing.geojson".into()),
+            admins: vec!["admin:osm:relation:8649".to_string()],
+            countries: vec!["fr".to_string(), "fra".to_string(), "b1".to_string()],
+        };
+
+        assert_eq!(
+            problem_keys(&area, "fafnir.area"),
+            [
+                "fafnir.area.geojson",
+                "fafnir.area.countries[1]",
+                "fafnir.area.countries[2]",
+            ]
+        );
+    }
+
+    #[test]
+    fn tripadvisor_weights_are_checked() {
+        let tripadvisor: TripAdvisorSettings = serde_json::from_value(serde_json::json!({
+            "properties": "Cargo.toml",
+            "photos": "Cargo.toml",
+            "reviews": "missing.xml.gz",
+            "weight": { "high_review_count": 0., "boost": 1.5 },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            problem_keys(&tripadvisor, "tripadvisor"),
+            [
+                "tripadvisor.reviews",
+                "tripadvisor.weight.high_review_count",
+                "tripadvisor.weight.boost",
+            ]
+        );
+    }
+
+    #[test]
+    fn metrics_textfile_requires_an_interval() {
+        let metrics = MetricsSettings {
+            listen: None,
+            textfile: None,
+            textfile_interval_secs: 0,
+        };
+
+        assert!(problem_keys(&metrics, "metrics").is_empty());
+
+        let metrics = MetricsSettings {
+            textfile: Some("fafnir.prom".into()),
+            ..metrics
+        };
+
+        assert_eq!(
+            problem_keys(&metrics, "metrics"),
+            ["metrics.textfile_interval_secs"]
+        );
+    }
+
+    #[test]
+    fn provenance_fields_are_checked() {
+        let provenance = ProvenanceSettings {
+            enabled: true,
+            run_id: Some(String::new()),
+            snapshot: Some("osm-2022-06".to_string()),
+            exclude: vec![PROVENANCE_FIELDS[0].to_string(), "color".to_string()],
+        };
+
+        assert_eq!(
+            problem_keys(&provenance, "provenance"),
+            ["provenance.run_id", "provenance.exclude[1]"]
+        );
+    }
+
+    #[test]
+    fn previous_indexes_must_be_kept() {
+        let retention = RetentionSettings { keep_previous: 0 };
+        assert_eq!(
+            problem_keys(&retention, "retention"),
+            ["retention.keep_previous"]
+        );
+
+        let retention = RetentionSettings { keep_previous: 2 };
+        assert!(problem_keys(&retention, "retention").is_empty());
+    }
+
+    #[test]
+    fn admins_langs_must_be_known() {
+        let admins = AdminsSettings {
+            langs: vec!["fr".to_string(), "xx".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(problem_keys(&admins, "admins"), ["admins.langs[1]"]);
+    }
+
+    #[test]
+    fn postgres_tls_certificates_require_tls() {
+        let tls = PostgresTlsSettings {
+            sslmode: PostgresSslMode::Disable,
+            ca_certificate: Some("Cargo.toml".into()),
+            client_certificate: None,
+            client_key: None,
+        };
+
+        assert_eq!(problem_keys(&tls, "tls"), ["tls.ca_certificate"]);
+
+        let tls = PostgresTlsSettings {
+            sslmode: PostgresSslMode::VerifyFull,
+            ..tls
+        };
+
+        assert!(problem_keys(&tls, "tls").is_empty());
+    }
+
+    #[test]
+    fn postgres_client_certificate_requires_a_key() {
+        let tls = PostgresTlsSettings {
+            sslmode: PostgresSslMode::Require,
+            ca_certificate: None,
+            client_certificate: Some("Cargo.toml".into()),
+            client_key: None,
+        };
+
+        assert_eq!(problem_keys(&tls, "tls"), ["tls.client_key"]);
+    }
+}
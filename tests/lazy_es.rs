@@ -0,0 +1,105 @@
+use fafnir::es_memory::MemoryEs;
+use fafnir::lazy_es::LazyEs;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize)]
+struct Doc {
+    name: String,
+}
+
+fn search<'p>(index: &str, query: serde_json::Value) -> LazyEs<'p, Vec<String>> {
+    LazyEs::NeedEsQuery {
+        header: json!({ "index": [index], "ignore_unavailable": true }),
+        query,
+        cache_key: None,
+        progress: Box::new(|hits| {
+            LazyEs::Value(
+                hits.into_iter()
+                    .map(|hit| {
+                        let doc: Doc = serde_json::from_str(hit.source.get()).unwrap();
+                        doc.name
+                    })
+                    .collect(),
+            )
+        }),
+    }
+}
+
+fn fixtures() -> MemoryEs {
+    let mut es = MemoryEs::new();
+
+    for (id, name, lon) in [("a1", "1 rue du test", 5.0), ("a2", "2 rue du test", 5.002)] {
+        es.insert(
+            "munin_addr",
+            id,
+            json!({ "name": name, "coord": { "lon": lon, "lat": 5.0 } }),
+        );
+    }
+
+    es
+}
+
+#[tokio::test]
+async fn memory_backend_queries() {
+    let es = fixtures();
+
+    let by_id = search(
+        "munin_addr",
+        json!({ "query": { "terms": { "_id": ["a2"] } } }),
+    );
+
+    let reverse = search(
+        "munin_addr",
+        json!({
+            "query": {
+                "bool": {
+                    "filter": {
+                        "geo_distance": {
+                            "distance": "1km",
+                            "coord": { "lat": 5.0, "lon": 5.0015 }
+                        }
+                    }
+                }
+            },
+            "sort": [{ "_geo_distance": { "coord": { "lat": 5.0, "lon": 5.0015 }, "order": "asc" } }]
+        }),
+    );
+
+    let out_of_range = search(
+        "munin_addr",
+        json!({
+            "query": {
+                "geo_distance": {
+                    "distance": "10m",
+                    "coord": { "lat": 5.0, "lon": 5.0015 }
+                }
+            }
+        }),
+    );
+
+    let missing_index = search("munin_street", json!({ "query": { "match_all": {} } }));
+
+    let results = LazyEs::batch_make_progress_until_value(
+        &es,
+        vec![
+            by_id.join(reverse).map(|(x, y)| vec![x, y]),
+            LazyEs::join_all([out_of_range, missing_index]),
+        ],
+        10,
+        None,
+        None,
+    )
+    .await;
+
+    assert_eq!(
+        results,
+        [
+            vec![
+                vec!["2 rue du test".to_string()],
+                vec!["2 rue du test".to_string(), "1 rue du test".to_string()],
+            ],
+            vec![vec![], vec![]],
+        ]
+    );
+}
@@ -44,7 +44,8 @@ INSERT INTO osm_poi_point (
             "name_int" => "Spagnolo",
             "name:latin" => "Spagnolo",
             "addr:housenumber" => "12",
-            "addr:street" => "rue bob"
+            "addr:street" => "rue bob",
+            "opening_hours" => "Mo-Sa 10h-19h"
         '
     ),
     -- POI located at lon=3, lat=3
@@ -83,7 +84,7 @@ INSERT INTO osm_poi_point (
             "name:es" => "Le nomade",
             "name_int" =>"Le nomade",
             "name:latin" => "Le nomade",
-            "addr:housenumber" => "7",
+            "addr:housenumber" => "7-9",
             "addr:street" => "rue spontini",
             "addr:postcode" => "75016"
         '
@@ -126,6 +127,23 @@ INSERT INTO osm_poi_point (
         'amenity',
         ST_GeomFromText('POINT(5.902 5.902)', 4326),
         ''
+    ),
+    -- POI located inside of "Halles du parc", with a house number but no street
+    (
+        77002,
+        14,
+        TRUE,
+        0,
+        'sport',
+        'Boulangerie du parc',
+        'bakery',
+        'shop',
+        ST_GeomFromText('POINT(6.5 6.5)', 4326),
+        '
+            "name" => "Boulangerie du parc",
+            "shop" => "bakery",
+            "addr:housenumber" => "8bis"
+        '
     );
 
 --
@@ -283,7 +301,37 @@ INSERT INTO osm_poi_polygon (
         null,
         'christian',
         '0101000020E610000000000000000014400000000000001440',
-        ''
+        '
+            "addr:housenumber" => "3",
+            "addr:place" => "bob''s town"
+        '
+    ),
+    -- A building with an address, containing "Boulangerie du parc"
+    (
+        77001,
+        14,
+        TRUE,
+        0,
+        'sport',
+        -77001,
+        'Halles du parc',
+        null,
+        null,
+        'marketplace',
+        'amenity',
+        null,
+        null,
+        null,
+        null,
+        null,
+        ST_GeomFromText('POLYGON((6 6, 7 6, 7 7, 6 7, 6 6))', 4326),
+        '
+            "name" => "Halles du parc",
+            "amenity" => "marketplace",
+            "addr:housenumber" => "8",
+            "addr:street" => "rue du parc",
+            "addr:postcode" => "75016"
+        '
     );
 
 --
@@ -47,6 +47,10 @@ async fn load_es_data(es_wrapper: &mut ElasticSearchWrapper, country_code: &str)
     let addresses = std::iter::once(test_address);
     es_wrapper.index(DATASET, addresses).await;
 
+    let test_street = make_test_street(city.clone());
+    let streets = std::iter::once(test_street);
+    es_wrapper.index(DATASET, streets).await;
+
     let cities = std::iter::once(city);
     es_wrapper.index(DATASET, cities).await;
 }
@@ -96,6 +100,22 @@ fn unwrap_street(address: &Address) -> &Street {
     }
 }
 
+fn make_test_street(city: places::admin::Admin) -> places::street::Street {
+    let country_codes = places::admin::find_country_codes(std::iter::once(&city));
+
+    places::street::Street {
+        id: "street:5.001;5".to_string(),
+        name: "rue du test".to_string(),
+        label: "rue du test (bob's town)".to_string(),
+        administrative_regions: vec![Arc::new(city)],
+        weight: 50.0,
+        zip_codes: vec!["12345".to_string()],
+        coord: places::coord::Coord::new(5.001, 5.),
+        country_codes,
+        ..Default::default()
+    }
+}
+
 fn make_test_address(city: places::admin::Admin) -> places::addr::Addr {
     let country_codes = places::admin::find_country_codes(std::iter::once(&city));
 
@@ -143,21 +163,23 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
                 r#"postgres.url="postgres://test@{}/test""#,
                 &pg_wrapper.host()
             ),
+            "-s".to_string(),
+            r#"fafnir.inherit_address_from=["osm_poi_polygon"]"#.to_string(),
         ],
     )
     .await;
 
     let rows = &pg_wrapper.get_rows("osm_poi_point").await;
-    assert_eq!(rows.len(), 7);
+    assert_eq!(rows.len(), 8);
     let rows = &pg_wrapper.get_rows("osm_poi_polygon").await;
-    assert_eq!(rows.len(), 3);
+    assert_eq!(rows.len(), 4);
 
     assert_eq!(
         es_wrapper
             .search_and_filter("name:*", |p| p.is_poi())
             .await
             .count(),
-        9 // 5 valid points + 2 valid polygons + 1 airport + 1 hamlet
+        11 // 6 valid points + 3 valid polygons + 1 airport + 1 hamlet
     );
 
     // Test that the place "Ocean Studio" has been imported in the elastic wrapper
@@ -212,6 +234,17 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
     assert_relative_eq!(address_coord.lat(), 1., epsilon = f64::EPSILON);
     assert_relative_eq!(address_coord.lon(), 1., epsilon = f64::EPSILON);
 
+    // Provenance of the POI, its address was found by a reverse
+    let provenance = |key: &str| properties_ocean_poi.get(key).map(String::as_str);
+    assert_eq!(provenance("fafnir:source"), Some("osm"));
+    assert_eq!(provenance("fafnir:source_id"), Some("osm:node:5589618289"));
+    assert_eq!(provenance("fafnir:input"), Some("test"));
+    assert_eq!(provenance("fafnir:address_method"), Some("reverse"));
+    assert_eq!(
+        provenance("fafnir:version"),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+
     let le_nomade_query: Vec<places::Place> = es_wrapper
         .search_and_filter("name:Le nomade", |_| true)
         .await
@@ -225,6 +258,15 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
     assert_eq!(le_nomade_addr.name, "7 rue spontini");
     assert_eq!(le_nomade_addr.house_number, "7");
     assert_eq!(le_nomade_addr.zip_codes, ["75016".to_string()]);
+    assert_eq!(
+        le_nomade.properties.get("fafnir:address_method"),
+        Some(&"tag".to_string())
+    );
+
+    // The house number tag is a range, the other number is searchable
+    assert!(le_nomade
+        .full_label_extra
+        .contains(&"9 rue spontini".to_string()));
 
     // Test that the airport 'Isla Cristina Agricultural Airstrip' has been imported in the elastic wrapper
     let airport_cristina: Vec<places::Place> = es_wrapper
@@ -243,6 +285,10 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
     assert_relative_eq!(airport_coord.lat(), 5.0, epsilon = f64::EPSILON);
     assert_relative_eq!(airport_coord.lon(), 5.0, epsilon = f64::EPSILON);
 
+    // There is no address close to the airport, but there is a street
+    let airport_street = unwrap_street(airport.address.as_ref().unwrap());
+    assert_eq!(airport_street.name, "rue du test");
+
     // Test the airport poi_class and poi_subclass
     let properties_airport = &airport.properties;
     let poi_class = properties_airport
@@ -288,6 +334,9 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
     assert_eq!(spagnolo.zip_codes, ["421337".to_string()]);
     assert_eq!(spagnolo_addr.zip_codes, ["421337".to_string()]);
 
+    // Invalid opening hours are not indexed
+    assert_eq!(spagnolo.properties.get("opening_hours"), None);
+
     // Test that two "Tour Eiffel" POI should have been imported: the hotel + the monument
     let eiffels: Vec<places::Place> = es_wrapper
         .search_and_filter("name:(Tour Eiffel)", |_| true)
@@ -298,6 +347,12 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
     // Test they are both POI
     assert!(&eiffels.iter().all(|p| p.is_poi()));
 
+    // Test that opening hours are normalized
+    assert!(eiffels.iter().filter_map(|p| p.poi()).any(|p| {
+        p.properties.get("opening_hours").map(String::as_str)
+            == Some("09:30-23:45; Jun 21-Sep 02 09:00-00:45; Jul 14,Jul 15 off")
+    }));
+
     // Test their weight are not both equal to 0.0
     assert!(!&eiffels
         .iter()
@@ -344,6 +399,31 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
     assert_eq!(church_class.1, "place_of_worship");
     assert_eq!(church_subclass.1, "christian");
 
+    // The church address refers to a place instead of a street
+    let church_addr = unwrap_addr(church.address.as_ref().unwrap());
+    assert_eq!(church_addr.house_number, "3");
+    assert_eq!(church_addr.street.name, "bob's town");
+    assert_eq!(church_addr.name, "3 bob's town");
+    assert_eq!(church_addr.label, "3 bob's town");
+
+    // The bakery only has a house number, the street is inherited from the
+    // building it is located in
+    let bakery_query: Vec<places::Place> = es_wrapper
+        .search_and_filter("name:Boulangerie", |_| true)
+        .await
+        .collect();
+    assert_eq!(&bakery_query.len(), &1);
+    let bakery = &bakery_query[0].poi().unwrap();
+    let bakery_addr = unwrap_addr(bakery.address.as_ref().unwrap());
+    assert_eq!(bakery_addr.house_number, "8 bis");
+    assert_eq!(bakery_addr.street.name, "rue du parc");
+    assert_eq!(bakery_addr.label, "8 bis rue du parc (bob's town)");
+    assert_eq!(bakery_addr.zip_codes, ["75016".to_string()]);
+    assert_eq!(
+        bakery.properties.get("fafnir:address_method"),
+        Some(&"tag".to_string())
+    );
+
     // 2 pois in nosearch index
     assert_eq!(es_wrapper.get_all_nosearch_pois().await.count(), 2);
 
@@ -390,9 +470,9 @@ pub async fn bbox_test(mut es_wrapper: ElasticSearchWrapper, pg_wrapper: Postgre
     )
     .await;
 
-    // We filtered the import by a bounding box, we still have 6 rows in PG
+    // We filtered the import by a bounding box, we still have 8 rows in PG
     let rows = &pg_wrapper.get_rows("osm_poi_point").await;
-    assert_eq!(rows.len(), 7);
+    assert_eq!(rows.len(), 8);
     // but there is only 3 elements in the ES now, 'Le nomade' and 'Isla Cristina Agricultural Airstrip'
     // have been filtered
     assert_eq!(
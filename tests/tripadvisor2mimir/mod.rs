@@ -140,12 +140,27 @@ pub async fn main_test(mut es_wrapper: ElasticSearchWrapper) {
 
     assert_eq!(
         gasthof_au.properties.get("phone"),
+        Some(&"+4232321117".to_string())
+    );
+
+    assert_eq!(
+        gasthof_au.properties.get("raw:phone"),
         Some(&"+423 232 11 17".to_string())
     );
 
     assert_eq!(
         gasthof_au.properties.get("opening_hours"),
-        Some(&"Mo 11:00-00:00; Tu 11:00-00:00; We 11:00-00:00; Th 11:00-00:00; Fr 11:00-00:00; Sa 11:00-00:00".to_string())
+        Some(&"Mo-Sa 11:00-24:00".to_string())
+    );
+
+    assert_eq!(
+        gasthof_au.properties.get("fafnir:source"),
+        Some(&"tripadvisor".to_string())
+    );
+
+    assert_eq!(
+        gasthof_au.properties.get("fafnir:input"),
+        Some(&PROPERTY_LIST.to_string())
     );
 
     // Test that the place "Bergrestaurant Suecka" has been imported in the elastic wrapper